@@ -1,44 +1,12 @@
 (define else true)
 
-(defmacro (syntax-list . xs)
-  (define (fold-right op initial sequence)
-    (if
-      (nil? sequence)
-      initial
-      (op
-        (car sequence)
-        (fold-right op initial (cdr sequence)))))
-  (fold-right
-    (lambda (curr acc)
-      (cons 'cons (cons curr (cons acc '()))))
-    '()
-    xs))
-
-(defmacro (let definitions . body)
-  (define (fold-right op initial sequence)
-    (if
-      (nil? sequence)
-      initial
-      (op
-        (car sequence)
-        (fold-right op initial (cdr sequence)))))
-
-  (define deflist (fold-right
-                   (lambda (curr acc)
-                     (define definee (car curr))
-                     (define expr (car (cdr curr)))
-                     (cons
-                       (cons 'define (cons definee (cons expr '())))
-                       acc))
-                   body
-                   definitions))
-  (cons (cons 'lambda (cons '() deflist)) '()))
+(define-syntax syntax-list
+  (syntax-rules ()
+    ((_) '())
+    ((_ x rest ...) (cons x (syntax-list rest ...)))))
 
 (define (list . xs) xs)
 
-(define (progn . xs)
-  (cons 'lambda (cons '() xs)))
-
 (define (print . xs)
   (define (fold-right op initial sequence)
     (if
@@ -54,49 +22,34 @@
       ""
       xs)))
 
-(defmacro (cond . exprs)
-  (define (fold-right op initial sequence)
-    (if
-
-      (nil? sequence)
-      initial
-      (op
-        (car sequence)
-        (fold-right op initial (cdr sequence)))))
-  (fold-right (lambda (curr acc)
-               (define predicate (car curr))
-               (define consequent (car (cdr curr)))
-               (cons 'if (cons predicate (cons consequent (cons acc '())))))
-    '()
-    exprs))
-
 (define (list-ref list count)
   (cond
     ((nil? list) '())
     ((< 1 count) (car list))
     (true (list-ref (cdr list) (- count 1)))))
 
-(defmacro (dprint . exprs)
-  (define (fold-right op initial sequence)
-    (if
-
-      (nil? sequence)
-      initial
-      (op
-        (car sequence)
-        (fold-right op initial (cdr sequence)))))
-  (define separator "=========")
+;; dprint's body runs while expanding, where it needs its own fold-right
+(define-for-syntax (fold-right op initial sequence)
+  (if
+    (nil? sequence)
+    initial
+    (op
+      (car sequence)
+      (fold-right op initial (cdr sequence)))))
 
-  (cons 'progn
-    (fold-right
-      (lambda (curr acc)
-        (cons
-          (if (string? curr)
-            (syntax-list 'print curr)
-            (syntax-list 'print (to-string curr) " = " (syntax-list 'to-string curr)))
-          acc))
-      '((print "========="))
-      (cons "===dprint===" exprs))))
+(defmacro (dprint . exprs)
+  `(begin
+     (print "===dprint===")
+     ,@(fold-right
+         (lambda (curr acc)
+           (cons
+             (if (string? curr)
+               `(print ,curr)
+               `(print ,(to-string curr) " = " (to-string ,curr)))
+             acc))
+         '()
+         exprs)
+     (print "=========")))
 
 (define (null? x) (nil? x))
 
@@ -105,15 +58,20 @@
     (x false)
     (true true)))
 
-(define (append list1 list2)
-  (if
-    (null? list1)
-    list2
-    (cons (car list1) (append (cdr list1) list2))))
-
 (define nil '())
 (define (null? x) (nil? x))
 
+;; dynamic-wind, make-parameter and force are special forms when called
+;; directly, these make them values too
+(define (dynamic-wind before thunk after)
+  (dynamic-wind before thunk after))
+(define (force promise)
+  (force promise))
+(define (make-parameter value . converter)
+  (if (null? converter)
+    (make-parameter value)
+    (make-parameter value (car converter))))
+
 (define (map proc items)
   (cond
     ((null? items) nil)
@@ -150,16 +108,13 @@
     (true
       (filter predicate (cdr sequence)))))
 
-(defmacro (assert a b)
-  (syntax-list 'if (syntax-list '= a b) '()
-    (syntax-list 'print "assertion failed, found: "
-      (syntax-list 'to-string a)
-      " but expected: "
-      (syntax-list 'to-string b)
-      ". "
-      (to-string a)
-      " != "
-      (to-string b))))
+(define-syntax assert
+  (syntax-rules ()
+    ((_ a b)
+     (if (= a b) '()
+       (print "assertion failed, found: " (to-string a)
+         " but expected: " (to-string b)
+         ". " (to-string 'a) " != " (to-string 'b))))))
 
 (define (reverse x)
   (define reverse-iter
@@ -239,3 +194,43 @@
   '(apple pear))
 (define (cadr list) (car (cdr list)))
 (define (caddr list) (car (cdr (cdr list))))
+
+(define the-empty-stream '())
+(define (stream-null? s) (null? s))
+(define (stream-car s) (car s))
+(define (stream-cdr s) (force (cdr s)))
+(define (stream-ref s n)
+  (if (= n 0)
+    (stream-car s)
+    (stream-ref (stream-cdr s) (- n 1))))
+
+(define (vector-map proc vector)
+  (list->vector (map proc (vector->list vector))))
+(define (vector-for-each proc vector)
+  (define (iter index)
+    (if (< index (vector-length vector))
+      (begin
+        (proc (vector-ref vector index))
+        (iter (+ index 1)))
+      nil))
+  (iter 0))
+
+;; failure is a thunk called when key is missing, without it that is an error
+(define (hash-table-ref table key . failure)
+  (cond ((hash-table-contains? table key) (hash-table-ref/default table key nil))
+        ((null? failure)
+         (error (str-append "hash-table-ref: no value for key " (to-string key))))
+        (else ((car failure)))))
+(define (hash-table-update! table key proc . failure)
+  (hash-table-set! table key
+    (proc (if (null? failure)
+            (hash-table-ref table key)
+            (hash-table-ref table key (car failure))))))
+(define (hash-table-walk table proc)
+  (define (iter entries)
+    (if (null? entries)
+      nil
+      (begin
+        (proc (car (car entries)) (cdr (car entries)))
+        (iter (cdr entries)))))
+  (iter (hash-table->alist table)))
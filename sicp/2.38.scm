@@ -16,18 +16,17 @@
         (fold-right op initial (cdr sequence))))))
 
 (assert (fold-left + 0 (list 1 2 3)) 6)
-(assert (fold-left / 1 (list 1 2 3)) (/ 0.5 3))
+(assert (fold-left / 1 (list 1 2 3)) 1/6)
 ; (/ (/ (/ 1 1) 2) 3)
 ; (/ (/ 1 2) 3)
-; (/ 0.5 3)
-; 0.1666...
+; (/ 1/2 3)
+; 1/6
 
 (assert (accumulate / 1 (list 1 2 3)) 1.5)
 
 ; (/ 1 (/ 2 (/ 3 1)))
 ; (/ 1 (/ 2 3))
-; (/ 1 (/ 2 3))
-; (/ 1 0.666)
-; 1.5
+; (/ 1 2/3)
+; 3/2
 
 ; Answer: The operator should be commutative (x * y) = (y * x)
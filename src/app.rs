@@ -1,9 +1,12 @@
 use crate::expr::Expr;
+use crate::macro_expand::ExpansionStep;
 use crate::vm;
 use crate::vm::get_prelude;
 use crate::vm::prepare_vm;
 use crate::vm::run;
+use crate::vm::trace_expansion;
 use crate::vm::Callframe;
+use crate::vm::CompilerEnv;
 use crate::vm::VM;
 
 use wasm_bindgen::JsCast;
@@ -50,6 +53,29 @@ pub fn Callframes(props: &CallFramesProps) -> Html {
     }
 }
 
+#[derive(Properties, PartialEq)]
+pub struct ExpansionsProps {
+    steps: Result<Vec<ExpansionStep>, String>,
+}
+
+// How the macros in the source rewrote it, one macro call at a time.
+#[function_component]
+pub fn Expansions(props: &ExpansionsProps) -> Html {
+    html! {
+        <div class="expansions">
+            if let Ok(steps) = &props.steps {
+                { steps.iter().map(|step| {
+                    let srcloc = step.srcloc.as_ref().map(|srcloc| format!("{srcloc} ")).unwrap_or_default();
+                    html! {<div class="expansion">{format!("{srcloc}{}: {} => {}", step.macro_name, step.input, step.output)}</div>}
+                }).collect::<Html>() }
+            }
+            if let Err(error) = &props.steps {
+                <div class="error">{ error }</div>
+            }
+        </div>
+    }
+}
+
 #[function_component(App)]
 pub fn app() -> Html {
     let fib = [
@@ -83,28 +109,54 @@ pub fn app() -> Html {
     let source_handle = use_state(|| fib.last().cloned().unwrap_or("not found".to_string()));
 
     let source = (*source_handle).clone();
-    fn prepare_with_prelude(src: &str) -> Result<VM, String> {
-        match get_prelude() {
+    fn prepare_with_prelude(
+        prelude: &Result<CompilerEnv, String>,
+        src: &str,
+    ) -> Result<VM, String> {
+        match prelude {
             Ok(prelude) => prepare_vm(
                 &crate::parse::ParseInput {
                     source: src,
                     file_name: Some("app"),
                 },
-                Some(prelude),
+                Some(prelude.clone()),
             )
             .map(|x| x.0),
             Err(err) => Err(format!("Error when compiling prelude: {err}")),
         }
     }
 
-    let vm_handle = use_state(|| prepare_with_prelude(source.as_str()));
+    fn trace_with_prelude(
+        prelude: &Result<CompilerEnv, String>,
+        src: &str,
+    ) -> Result<Vec<ExpansionStep>, String> {
+        match prelude {
+            Ok(prelude) => trace_expansion(
+                &crate::parse::ParseInput {
+                    source: src,
+                    file_name: Some("app"),
+                },
+                Some(prelude.clone()),
+            ),
+            Err(err) => Err(format!("Error when compiling prelude: {err}")),
+        }
+    }
+
+    // The prelude only needs compiling once, every source is prepared on top of it.
+    let prelude = use_memo(|_| get_prelude(), ());
+
+    let vm_handle = use_state(|| prepare_with_prelude(&prelude, source.as_str()));
+    let expansions_handle = use_state(|| trace_with_prelude(&prelude, source.as_str()));
     let vm = (*vm_handle).clone();
 
     use_effect_with_deps(
         {
             let vm_handle = vm_handle.clone();
+            let expansions_handle = expansions_handle.clone();
+            let prelude = prelude.clone();
             move |arg: &String| {
-                let prepared_vm = prepare_with_prelude(arg.as_str());
+                let prepared_vm = prepare_with_prelude(&prelude, arg.as_str());
+                expansions_handle.set(trace_with_prelude(&prelude, arg.as_str()));
 
                 vm_handle.set(prepared_vm)
             }
@@ -139,8 +191,9 @@ pub fn app() -> Html {
     let run = Callback::from({
         let source = source.clone();
         let vm_handle = vm_handle.clone();
+        let prelude = prelude.clone();
         move |_stuff: MouseEvent| {
-            let prepared_vm = prepare_with_prelude(source.as_str());
+            let prepared_vm = prepare_with_prelude(&prelude, source.as_str());
 
             let res = prepared_vm.and_then(|mut vm| {
                 run(&mut vm)?;
@@ -164,6 +217,7 @@ pub fn app() -> Html {
                             { vm.log.into_iter().map(|log| html!{<div>{log}</div>}).collect::<Html>() }
                         }
                     </div>
+                    <Expansions steps={(*expansions_handle).clone()} />
                 </div>
                 <div style="display: flex; flex-direction: row; gap: 8px;">
                     if let Ok(vm) = vm.clone() {
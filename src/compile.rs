@@ -1,10 +1,22 @@
-use std::{borrow::Borrow, collections::HashMap, fmt::Display, sync::Arc};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use im_rc::{OrdMap, OrdSet};
 use once_cell::sync::Lazy;
 
 use crate::{
-    expr::{Bool, Expr, Num},
-    parse::SrcLoc,
+    expr::{address, Bool, Expr, Num, Promise, Record},
+    hash_table::{Equivalence, HashTable},
+    macro_expand::SyntaxEnv,
+    number::Number,
+    parse::{make_pair_from_vec, SrcLoc},
+    persistent::MapKey,
     vm::{Chunk, VMInstruction},
 };
 
@@ -24,13 +36,14 @@ impl Display for CompileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let message = self.message.clone();
         if let Some(srcloc) = self.srcloc.clone() {
-            write!(
-                f,
-                "{}:{}:{}: {message}",
-                srcloc.file_name.unwrap_or("unknown".to_string()),
-                srcloc.line,
-                srcloc.column
-            )
+            write!(f, "{srcloc}: {message}")?;
+            let mut expanded_from = srcloc.expanded_from;
+            while let Some(expansion) = expanded_from {
+                let (name, site) = expansion.as_ref();
+                write!(f, "\n  in expansion of macro {name} at {site}")?;
+                expanded_from = site.expanded_from.clone();
+            }
+            Ok(())
         } else {
             write!(f, "unknown: {message}")
         }
@@ -66,29 +79,641 @@ pub fn extract_srcloc(expr: &Expr) -> Option<SrcLoc> {
         Expr::Keyword(_, s) => s,
         Expr::Pair(_, _, s) => s,
         Expr::String(_, s) => s,
-        Expr::Quote(_, s) => s,
+        Expr::Char(_, s) => s,
         Expr::Num(Num { srcloc: s, .. }) => s,
         Expr::Boolean(Bool { srcloc: s, .. }) => s,
-        Expr::Lambda(..) => todo!("Not implemented src_loc for this lambda."),
-        Expr::Nil => &Some(SrcLoc {
-            line: 13391339,
-            column: 0,
-            file_name: None,
-        }),
+        Expr::Lambda(..)
+        | Expr::Parameter(..)
+        | Expr::Values(..)
+        | Expr::Promise(..)
+        | Expr::RecordType(..)
+        | Expr::Record(..)
+        | Expr::Vector(..)
+        | Expr::HashTable(..)
+        | Expr::Map(..)
+        | Expr::Set(..)
+        | Expr::Nil => &None,
     }
     .clone()
 }
 
 type CompileResult = Result<(), CompileError>;
 
+// eqv? compares atoms by value and everything else by identity. Procedures
+// have no identity of their own, they are the same if they share their code
+// and closed over variables.
+pub fn is_eqv(l: &Expr, r: &Expr) -> bool {
+    match (l, r) {
+        (Expr::Lambda(l_chunk, .., l_env), Expr::Lambda(r_chunk, .., r_env)) => {
+            l_chunk == r_chunk && l_env == r_env
+        }
+        (Expr::Record(l), Expr::Record(r)) => Rc::ptr_eq(l, r),
+        (Expr::Vector(l), Expr::Vector(r)) => Rc::ptr_eq(l, r),
+        (Expr::HashTable(l), Expr::HashTable(r)) => Rc::ptr_eq(l, r),
+        // = compares 1 and 1.0 by value, eqv? also by exactness
+        (Expr::Num(l), Expr::Num(r)) => {
+            l.value.is_exact() == r.value.is_exact() && l.value == r.value
+        }
+        (Expr::Pair(..), _) => false,
+        _ => l == r,
+    }
+}
+
+// Pairs are copied rather than shared, so they have no identity for eqv? to
+// compare. Everything that compares with eqv? refuses them instead of
+// answering false for the same pair.
+pub fn eqv_arg(name: &str, expr: &Expr) -> Result<(), String> {
+    match expr {
+        Expr::Pair(..) => Err(format!(
+            "{name}: pairs have no identity to compare, use equal? instead: {expr}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+// equal? compares pairs and vectors by their contents and everything else
+// like eqv?.
+pub fn is_equal(l: &Expr, r: &Expr) -> bool {
+    equal_visiting(l, r, &mut vec![])
+}
+
+// Vectors can contain themselves, the ones being compared are kept in
+// visiting and meeting the same two again compares them the same.
+fn equal_visiting(l: &Expr, r: &Expr, visiting: &mut Vec<(*const (), *const ())>) -> bool {
+    match (l, r) {
+        (Expr::Pair(l_car, l_cdr, ..), Expr::Pair(r_car, r_cdr, ..)) => {
+            equal_visiting(l_car, r_car, visiting) && equal_visiting(l_cdr, r_cdr, visiting)
+        }
+        (Expr::Vector(l), Expr::Vector(r)) if Rc::ptr_eq(l, r) => true,
+        (Expr::Vector(l), Expr::Vector(r)) if visiting.contains(&(address(l), address(r))) => true,
+        (Expr::Vector(l), Expr::Vector(r)) => {
+            visiting.push((address(l), address(r)));
+            let equal = {
+                let (l, r) = (RefCell::borrow(l), RefCell::borrow(r));
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|(l, r)| equal_visiting(l, r, visiting))
+            };
+            visiting.pop();
+            equal
+        }
+        _ => is_eqv(l, r),
+    }
+}
+
+// Forms only the expander and the compiler produce. The reader ends a name at
+// a space, so programs can neither write nor shadow these.
+pub const GLOBAL_REF: &str = "#<global ref>";
+const NAMED_LET_RECUR: &str = "#<named-let recur>";
+
+static FRESH_NAMES: AtomicUsize = AtomicUsize::new(0);
+
+// Names made here contain a # so they can only clash with a user's name if
+// the user writes names like that.
+pub fn fresh_name(prefix: &str) -> String {
+    format!("{prefix}#{}", FRESH_NAMES.fetch_add(1, Ordering::Relaxed))
+}
+
+fn numeric(
+    name: &str,
+    l: &Expr,
+    r: &Expr,
+    op: impl Fn(&Number, &Number) -> Result<Expr, String>,
+) -> Result<Expr, String> {
+    match (l, r) {
+        (Expr::Num(l), Expr::Num(r)) => op(&l.value, &r.value),
+        _ => Err(format!("{name}: expected numbers, found: {l} and {r}")),
+    }
+}
+
+fn num_arg<'a>(name: &str, expr: &'a Expr) -> Result<&'a Number, String> {
+    match expr {
+        Expr::Num(nr) => Ok(&nr.value),
+        other => Err(format!("{name}: expected num but found: {other}")),
+    }
+}
+
+fn float_fn(name: &str, expr: &Expr, f: fn(f64) -> f64) -> Result<Expr, String> {
+    Ok(Expr::number(Number::Real(f(num_arg(name, expr)?.to_f64()))))
+}
+
+// min and max are inexact if any argument is.
+fn extremum(name: &str, args: &[Expr], pick: std::cmp::Ordering) -> Result<Expr, String> {
+    let numbers = args
+        .iter()
+        .map(|arg| num_arg(name, arg))
+        .collect::<Result<Vec<&Number>, String>>()?;
+    let (first, rest) = numbers
+        .split_first()
+        .ok_or(format!("{name}: expected at least one number"))?;
+    let extreme = rest.iter().fold(*first, |extreme, number| {
+        match number.partial_cmp(&extreme) == Some(pick) {
+            true => number,
+            false => extreme,
+        }
+    });
+    match numbers.iter().all(|number| number.is_exact()) {
+        true => Ok(Expr::number(extreme.clone())),
+        false => Ok(Expr::number(extreme.to_inexact())),
+    }
+}
+
+// gcd and lcm of any number of integers, (gcd) is 0 and (lcm) is 1.
+fn integer_fold(
+    name: &str,
+    args: &[Expr],
+    initial: i64,
+    op: fn(&Number, &Number) -> Result<Number, String>,
+) -> Result<Expr, String> {
+    args.iter()
+        .try_fold(Number::Fixnum(initial), |acc, arg| {
+            op(&acc, num_arg(name, arg)?)
+        })
+        .map(Expr::number)
+}
+
+fn integer_predicate(name: &str, expr: &Expr, even: bool) -> Result<Expr, String> {
+    match num_arg(name, expr)?.is_even() {
+        Some(is_even) => Ok(Expr::bool(is_even == even)),
+        None => Err(format!("{name}: expected integer but found: {expr}")),
+    }
+}
+
+fn log(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [z] => float_fn("log", z, f64::ln),
+        [z, base] => {
+            let (z, base) = (num_arg("log", z)?, num_arg("log", base)?);
+            Ok(Expr::number(Number::Real(
+                z.to_f64().ln() / base.to_f64().ln(),
+            )))
+        }
+        _ => Err(format!(
+            "log: expected a number and an optional base, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn atan(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [z] => float_fn("atan", z, f64::atan),
+        [y, x] => {
+            let (y, x) = (num_arg("atan", y)?, num_arg("atan", x)?);
+            Ok(Expr::number(Number::Real(y.to_f64().atan2(x.to_f64()))))
+        }
+        _ => Err(format!(
+            "atan: expected one or two numbers, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn exact(expr: &Expr) -> Result<Expr, String> {
+    match expr {
+        Expr::Num(nr) => nr.value.to_exact().map(Expr::number),
+        other => Err(format!("exact: expected num but found: {other}")),
+    }
+}
+
+fn inexact(expr: &Expr) -> Result<Expr, String> {
+    match expr {
+        Expr::Num(nr) => Ok(Expr::number(nr.value.to_inexact())),
+        other => Err(format!("inexact: expected num but found: {other}")),
+    }
+}
+
+fn char_predicate(name: &str, expr: &Expr, test: fn(&char) -> bool) -> Result<Expr, String> {
+    match expr {
+        Expr::Char(c, _) => Ok(Expr::bool(test(c))),
+        other => Err(format!("{name}: expected char but found: {other}")),
+    }
+}
+
+fn char_compare(
+    name: &str,
+    l: &Expr,
+    r: &Expr,
+    test: fn(&char, &char) -> bool,
+) -> Result<Expr, String> {
+    match (l, r) {
+        (Expr::Char(l, _), Expr::Char(r, _)) => Ok(Expr::bool(test(l, r))),
+        _ => Err(format!("{name}: expected chars, found: {l} and {r}")),
+    }
+}
+
+// Case conversion keeps the character if it doesn't map to a single one.
+fn char_case(name: &str, expr: &Expr, convert: fn(&char) -> Option<char>) -> Result<Expr, String> {
+    match expr {
+        Expr::Char(c, _) => Ok(Expr::Char(convert(c).unwrap_or(*c), None)),
+        other => Err(format!("{name}: expected char but found: {other}")),
+    }
+}
+
+// The procedures define-record-type defines call these, with the record type
+// as their first argument and the name of the procedure for errors.
+fn make_record(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [Expr::RecordType(record_type), fields @ ..]
+            if fields.len() == record_type.fields.len() =>
+        {
+            Ok(Expr::Record(Rc::new(Record {
+                record_type: record_type.clone(),
+                fields: RefCell::new(fields.to_vec()),
+            })))
+        }
+        _ => Err(format!(
+            "make-record: expected a record type and its fields, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn record_field(args: &[Expr]) -> Result<(&Record, usize, &[Expr]), String> {
+    match args {
+        [Expr::RecordType(record_type), Expr::String(name, _), record, Expr::Num(index), rest @ ..] => {
+            match record {
+                Expr::Record(record) if Rc::ptr_eq(&record.record_type, record_type) => match index
+                    .value
+                    .to_usize()
+                {
+                    Some(index) if index < record_type.fields.len() => Ok((record, index, rest)),
+                    _ => Err(format!(
+                        "{name}: a {} has no field {}",
+                        record_type.name, index.value
+                    )),
+                },
+                _ => Err(format!(
+                    "{name}: expected a {}, found: {record}",
+                    record_type.name
+                )),
+            }
+        }
+        _ => Err(format!(
+            "expected a record type, a name, a record and a field index, found: {}",
+            make_pair_from_vec(args.to_vec())
+        )),
+    }
+}
+
+fn record_ref(args: &[Expr]) -> Result<Expr, String> {
+    let (record, index, _) = record_field(args)?;
+    Ok(record.fields.borrow()[index].clone())
+}
+
+fn record_set(args: &[Expr]) -> Result<Expr, String> {
+    match record_field(args)? {
+        (record, index, [value]) => {
+            record.fields.borrow_mut()[index] = value.clone();
+            Ok(Expr::Nil)
+        }
+        _ => Err(format!(
+            "record-set!: expected a value to set, found: {}",
+            make_pair_from_vec(args.to_vec())
+        )),
+    }
+}
+
+fn string_arg<'a>(name: &str, expr: &'a Expr) -> Result<&'a str, String> {
+    match expr {
+        Expr::String(s, _) => Ok(s),
+        other => Err(format!("{name}: expected string but found: {other}")),
+    }
+}
+
+fn string_compare(
+    name: &str,
+    l: &Expr,
+    r: &Expr,
+    test: fn(&str, &str) -> bool,
+) -> Result<Expr, String> {
+    Ok(Expr::bool(test(string_arg(name, l)?, string_arg(name, r)?)))
+}
+
+// Indexes count characters, the end is optional.
+fn substring(args: &Vec<Expr>) -> Result<Expr, String> {
+    let (s, start, end) = match args.as_slice() {
+        [s, Expr::Num(start), end @ ..] if end.len() <= 1 => {
+            (string_arg("substring", s)?, start, end)
+        }
+        _ => {
+            return Err(format!(
+                "substring: expected a string, a start and an optional end, found: {}",
+                make_pair_from_vec(args.clone())
+            ))
+        }
+    };
+    let length = s.chars().count();
+    let end = match end {
+        [Expr::Num(end)] => end.value.to_usize(),
+        _ => Some(length),
+    };
+    match (start.value.to_usize(), end) {
+        (Some(start), Some(end)) if start <= end && end <= length => Ok(Expr::String(
+            s.chars().skip(start).take(end - start).collect(),
+            None,
+        )),
+        _ => Err(format!(
+            "substring: range out of bounds for {s:?}: {}",
+            make_pair_from_vec(args[1..].to_vec())
+        )),
+    }
+}
+
+// Without a separator the string is split on whitespace.
+fn string_split(args: &Vec<Expr>) -> Result<Expr, String> {
+    let parts: Vec<&str> = match args.as_slice() {
+        [s] => string_arg("string-split", s)?.split_whitespace().collect(),
+        [s, Expr::Char(separator, _)] => string_arg("string-split", s)?.split(*separator).collect(),
+        [s, Expr::String(separator, _)] if !separator.is_empty() => string_arg("string-split", s)?
+            .split(separator.as_str())
+            .collect(),
+        _ => {
+            return Err(format!(
+                "string-split: expected a string and an optional separator, found: {}",
+                make_pair_from_vec(args.clone())
+            ))
+        }
+    };
+    Ok(make_pair_from_vec(
+        parts
+            .into_iter()
+            .map(|part| Expr::String(part.to_string(), None))
+            .collect(),
+    ))
+}
+
+// The separator defaults to a space.
+fn string_join(args: &Vec<Expr>) -> Result<Expr, String> {
+    let (list, separator) = match args.as_slice() {
+        [list] => (list, " "),
+        [list, separator] => (list, string_arg("string-join", separator)?),
+        _ => {
+            return Err(format!(
+                "string-join: expected a list and an optional separator, found: {}",
+                make_pair_from_vec(args.clone())
+            ))
+        }
+    };
+    let items = collect_exprs_from_body(list)
+        .map_err(|_| format!("string-join: expected list but found: {list}"))?;
+    let strings = items
+        .iter()
+        .map(|item| string_arg("string-join", item))
+        .collect::<Result<Vec<&str>, String>>()?;
+    Ok(Expr::String(strings.join(separator), None))
+}
+
+// The index of a byte offset in characters.
+fn char_index(s: &str, offset: Option<usize>) -> Expr {
+    match offset {
+        Some(offset) => Expr::int(s[..offset].chars().count() as i64),
+        None => Expr::bool(false),
+    }
+}
+
+fn radix_arg(name: &str, radix: &[Expr]) -> Result<u32, String> {
+    match radix {
+        [] => Ok(10),
+        [Expr::Num(nr)] if let Some(radix @ 2..=36) = nr.value.to_usize() => Ok(radix as u32),
+        [other, ..] => Err(format!(
+            "{name}: expected a radix from 2 to 36 but found: {other}"
+        )),
+    }
+}
+
+fn number_to_string(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [Expr::Num(nr), radix @ ..] if radix.len() <= 1 => {
+            let radix = radix_arg("number->string", radix)?;
+            nr.value
+                .to_string_radix(radix)
+                .map(|s| Expr::String(s, None))
+                .ok_or(format!(
+                    "number->string: {} can only be written in radix 10",
+                    nr.value
+                ))
+        }
+        _ => Err(format!(
+            "number->string: expected a number and an optional radix, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+// false if the string isn't a number
+fn string_to_number(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [s, radix @ ..] if radix.len() <= 1 => {
+            let s = string_arg("string->number", s)?;
+            let radix = radix_arg("string->number", radix)?;
+            Ok(Number::parse(s, radix).map_or(Expr::bool(false), Expr::number))
+        }
+        _ => Err(format!(
+            "string->number: expected a string and an optional radix, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn vector_slot<'a>(
+    name: &str,
+    vector: &'a Expr,
+    index: &Expr,
+) -> Result<(&'a RefCell<Vec<Expr>>, usize), String> {
+    match (vector, index) {
+        (Expr::Vector(items), Expr::Num(nr)) => match nr.value.to_usize() {
+            Some(slot) if slot < RefCell::borrow(items).len() => Ok((items, slot)),
+            _ if nr.value.is_integer() && nr.value.is_exact() => {
+                Err(format!("{name}: index {index} out of range for {vector}"))
+            }
+            _ => Err(format!(
+                "{name}: expected an exact integer index, found: {index}"
+            )),
+        },
+        _ => Err(format!(
+            "{name}: expected a vector and an index, found: {vector} and {index}"
+        )),
+    }
+}
+
+fn make_vector(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [Expr::Num(length), fill @ ..]
+            if let Some(length) = length.value.to_usize()
+                && fill.len() <= 1 =>
+        {
+            let fill = fill.first().cloned().unwrap_or(Expr::bool(false));
+            Ok(Expr::Vector(Rc::new(RefCell::new(vec![fill; length]))))
+        }
+        _ => Err(format!(
+            "make-vector: expected a length and an optional fill, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn vector_set(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [vector, index, value] => {
+            let (items, slot) = vector_slot("vector-set!", vector, index)?;
+            items.borrow_mut()[slot] = value.clone();
+            Ok(Expr::Nil)
+        }
+        _ => Err(format!(
+            "vector-set!: expected a vector, an index and a value, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn make_hash_table(args: &Vec<Expr>) -> Result<Expr, String> {
+    let equivalence = match args.as_slice() {
+        [] => Equivalence::Equal,
+        [Expr::Keyword(name, ..)] if name == "equal?" => Equivalence::Equal,
+        [Expr::Keyword(name, ..)] if name == "eqv?" => Equivalence::Eqv,
+        _ => {
+            return Err(format!(
+                "make-hash-table: expected equal? or eqv?, found: {}",
+                make_pair_from_vec(args.clone())
+            ))
+        }
+    };
+    Ok(Expr::HashTable(Rc::new(HashTable::new(equivalence))))
+}
+
+fn hash_table_arg<'a>(name: &str, expr: &'a Expr) -> Result<&'a HashTable, String> {
+    match expr {
+        Expr::HashTable(table) => Ok(table),
+        other => Err(format!("{name}: expected hash table but found: {other}")),
+    }
+}
+
+// hash-table-ref is in the prelude, it calls its failure thunk itself.
+fn hash_table_ref_default(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [table, key, default] => Ok(hash_table_arg("hash-table-ref/default", table)?
+            .get(key)
+            .unwrap_or_else(|| default.clone())),
+        _ => Err(format!(
+            "hash-table-ref/default: expected a hash table, a key and a default, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn hash_table_set(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [table, key, value] => {
+            let table = hash_table_arg("hash-table-set!", table)?;
+            if table.equivalence == Equivalence::Eqv {
+                eqv_arg("hash-table-set!", key)?;
+            }
+            table.insert(key.clone(), value.clone());
+            Ok(Expr::Nil)
+        }
+        _ => Err(format!(
+            "hash-table-set!: expected a hash table, a key and a value, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn persistent_map(args: &[Expr]) -> Result<Expr, String> {
+    match args.len() % 2 {
+        0 => args
+            .chunks(2)
+            .map(|entry| Ok((MapKey::new("persistent-map", &entry[0])?, entry[1].clone())))
+            .collect::<Result<OrdMap<MapKey, Expr>, String>>()
+            .map(Expr::Map),
+        _ => Err(format!(
+            "persistent-map: expected keys and values, found: {}",
+            make_pair_from_vec(args.to_vec())
+        )),
+    }
+}
+
+fn persistent_set(name: &str, items: &[Expr]) -> Result<Expr, String> {
+    items
+        .iter()
+        .map(|item| MapKey::new(name, item))
+        .collect::<Result<OrdSet<MapKey>, String>>()
+        .map(Expr::Set)
+}
+
+fn map_arg<'a>(name: &str, expr: &'a Expr) -> Result<&'a OrdMap<MapKey, Expr>, String> {
+    match expr {
+        Expr::Map(map) => Ok(map),
+        other => Err(format!("{name}: expected map but found: {other}")),
+    }
+}
+
+fn set_arg<'a>(name: &str, expr: &'a Expr) -> Result<&'a OrdSet<MapKey>, String> {
+    match expr {
+        Expr::Set(set) => Ok(set),
+        other => Err(format!("{name}: expected set but found: {other}")),
+    }
+}
+
+fn map_assoc(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [map, key, value] => Ok(Expr::Map(
+            map_arg("map-assoc", map)?.update(MapKey::new("map-assoc", key)?, value.clone()),
+        )),
+        _ => Err(format!(
+            "map-assoc: expected a map, a key and a value, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+// A missing key gives the default, or false without one.
+fn map_get(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [map, key, default @ ..] if default.len() <= 1 => Ok(map_arg("map-get", map)?
+            .get(&MapKey::new("map-get", key)?)
+            .or(default.first())
+            .cloned()
+            .unwrap_or(Expr::bool(false))),
+        _ => Err(format!(
+            "map-get: expected a map, a key and an optional default, found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
+fn gensym(args: &Vec<Expr>) -> Result<Expr, String> {
+    match args.as_slice() {
+        [] => Ok(Expr::Keyword(fresh_name("g"), None)),
+        [Expr::String(prefix, ..) | Expr::Keyword(prefix, ..)] => {
+            Ok(Expr::Keyword(fresh_name(prefix), None))
+        }
+        _ => Err(format!(
+            "gensym, expected an optional string or symbol prefix but found: {}",
+            make_pair_from_vec(args.clone())
+        )),
+    }
+}
+
 pub static BUILTIN_FNS: Lazy<HashMap<String, BuiltIn>> = Lazy::new(|| {
     HashMap::from([
         (
             "error".to_string(),
-            BuiltIn::OneArg(|expr| {
-                comp_err!(expr, "{expr}")
-                    .map(|_: Expr| expr.clone())
-                    .map_err(|err| format!("{err}"))
+            // (error message irritant...), located where the message is
+            BuiltIn::Variadic(|args| match args.as_slice() {
+                [message, irritants @ ..] => {
+                    let message = [message]
+                        .into_iter()
+                        .chain(irritants)
+                        .map(|expr| format!("{expr}"))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    comp_err!(&args[0], "{message}")
+                        .map(|_: Expr| Expr::Nil)
+                        .map_err(|err| format!("{err}"))
+                }
+                [] => Err("error, expected a message".to_string()),
             }),
         ),
         (
@@ -105,6 +730,16 @@ pub static BUILTIN_FNS: Lazy<HashMap<String, BuiltIn>> = Lazy::new(|| {
                 _ => Ok(Expr::bool(false)),
             }),
         ),
+        (
+            "list?".to_string(),
+            BuiltIn::OneArg(|expr| {
+                let mut rest = expr;
+                while let Expr::Pair(_, box next, ..) = rest {
+                    rest = next;
+                }
+                Ok(Expr::bool(matches!(rest, Expr::Nil)))
+            }),
+        ),
         (
             "number?".to_string(),
             BuiltIn::OneArg(|expr| match expr {
@@ -127,456 +762,2681 @@ pub static BUILTIN_FNS: Lazy<HashMap<String, BuiltIn>> = Lazy::new(|| {
             }),
         ),
         (
-            "abs".to_string(),
+            "char?".to_string(),
             BuiltIn::OneArg(|expr| match expr {
-                Expr::Num(nr) => Ok(Expr::num(nr.value.abs())),
-                other => Err(format!("abs: expected num but found: {other}")),
+                Expr::Char(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
             }),
         ),
         (
-            "function?".to_string(),
+            "char->integer".to_string(),
             BuiltIn::OneArg(|expr| match expr {
-                Expr::Lambda(..) => Ok(Expr::bool(true)),
-                _ => Ok(Expr::bool(false)),
+                Expr::Char(c, _) => Ok(Expr::int(*c as i64)),
+                other => Err(format!("char->integer: expected char but found: {other}")),
             }),
         ),
         (
-            "symbol?".to_string(),
+            "integer->char".to_string(),
             BuiltIn::OneArg(|expr| match expr {
-                Expr::Keyword(..) => Ok(Expr::bool(true)),
-                _ => Ok(Expr::bool(false)),
+                Expr::Num(nr)
+                    if let Some(c) = nr
+                        .value
+                        .to_usize()
+                        .and_then(|n| char::from_u32(n.try_into().ok()?)) =>
+                {
+                    Ok(Expr::Char(c, None))
+                }
+                other => Err(format!(
+                    "integer->char: expected a character code but found: {other}"
+                )),
             }),
         ),
         (
-            "+".to_string(),
+            "char-upcase".to_string(),
+            BuiltIn::OneArg(|expr| char_case("char-upcase", expr, |c| c.to_uppercase().next())),
+        ),
+        (
+            "char-downcase".to_string(),
+            BuiltIn::OneArg(|expr| char_case("char-downcase", expr, |c| c.to_lowercase().next())),
+        ),
+        (
+            "char-alphabetic?".to_string(),
+            BuiltIn::OneArg(|expr| char_predicate("char-alphabetic?", expr, |c| c.is_alphabetic())),
+        ),
+        (
+            "char-numeric?".to_string(),
+            BuiltIn::OneArg(|expr| char_predicate("char-numeric?", expr, |c| c.is_numeric())),
+        ),
+        (
+            "char-whitespace?".to_string(),
+            BuiltIn::OneArg(|expr| char_predicate("char-whitespace?", expr, |c| c.is_whitespace())),
+        ),
+        (
+            "char-upper-case?".to_string(),
+            BuiltIn::OneArg(|expr| char_predicate("char-upper-case?", expr, |c| c.is_uppercase())),
+        ),
+        (
+            "char-lower-case?".to_string(),
+            BuiltIn::OneArg(|expr| char_predicate("char-lower-case?", expr, |c| c.is_lowercase())),
+        ),
+        (
+            "char=?".to_string(),
+            BuiltIn::TwoArg(|l, r| char_compare("char=?", l, r, char::eq)),
+        ),
+        (
+            "char<?".to_string(),
+            BuiltIn::TwoArg(|l, r| char_compare("char<?", l, r, char::lt)),
+        ),
+        (
+            "char>?".to_string(),
+            BuiltIn::TwoArg(|l, r| char_compare("char>?", l, r, char::gt)),
+        ),
+        (
+            "string-length".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::int(
+                    string_arg("string-length", s)?.chars().count() as i64
+                ))
+            }),
+        ),
+        ("substring".to_string(), BuiltIn::Variadic(substring)),
+        (
+            "string-append".to_string(),
             BuiltIn::Variadic(|args| {
-                (args.clone())
-                    .into_iter()
-                    .try_reduce::<Result<Expr, String>>(|acc, curr| {
-                        match (acc.clone(), curr.clone()) {
-                            (Expr::Num(Num { value: l, .. }), Expr::Num(Num { value: r, .. })) => {
-                                Ok(Expr::num(l + r))
-                            }
-                            _ => Err(format!("Expected numbers, found: {} and {}", acc, curr)),
-                        }
-                    })
-                    .map(|x| x.unwrap_or_else(|| Expr::num(0.0)))
+                args.iter()
+                    .map(|s| string_arg("string-append", s))
+                    .collect::<Result<String, String>>()
+                    .map(|s| Expr::String(s, None))
             }),
         ),
+        ("string-split".to_string(), BuiltIn::Variadic(string_split)),
+        ("string-join".to_string(), BuiltIn::Variadic(string_join)),
         (
-            "-".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::num(l.value - r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-index".to_string(),
+            BuiltIn::TwoArg(|s, c| match c {
+                Expr::Char(c, _) => {
+                    let s = string_arg("string-index", s)?;
+                    Ok(char_index(s, s.find(*c)))
+                }
+                other => Err(format!("string-index: expected char but found: {other}")),
             }),
         ),
         (
-            "*".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::num(l.value * r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-contains".to_string(),
+            BuiltIn::TwoArg(|s, needle| {
+                let s = string_arg("string-contains", s)?;
+                Ok(char_index(
+                    s,
+                    s.find(string_arg("string-contains", needle)?),
+                ))
             }),
         ),
         (
-            ">".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::bool(l.value > r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-upcase".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::String(
+                    string_arg("string-upcase", s)?.to_uppercase(),
+                    None,
+                ))
             }),
         ),
         (
-            "<".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::bool(l.value < r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-downcase".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::String(
+                    string_arg("string-downcase", s)?.to_lowercase(),
+                    None,
+                ))
             }),
         ),
         (
-            "/".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::num(l.value / r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-trim".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::String(
+                    string_arg("string-trim", s)?.trim().to_string(),
+                    None,
+                ))
             }),
         ),
         (
-            "%".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::num(l.value % r.value)),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-trim-left".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::String(
+                    string_arg("string-trim-left", s)?.trim_start().to_string(),
+                    None,
+                ))
             }),
         ),
         (
-            "^".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::Num(l), Expr::Num(r)) => Ok(Expr::num(l.value.powf(r.value))),
-                _ => Err(format!("Expected numbers, found: {} and {}", l, r)),
+            "string-trim-right".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::String(
+                    string_arg("string-trim-right", s)?.trim_end().to_string(),
+                    None,
+                ))
             }),
         ),
         (
-            "=".to_string(),
-            BuiltIn::TwoArg(|l, r| Ok(Expr::bool(l == r))),
+            "string=?".to_string(),
+            BuiltIn::TwoArg(|l, r| string_compare("string=?", l, r, |l, r| l == r)),
         ),
         (
-            "not".to_string(),
-            BuiltIn::OneArg(|arg| match arg {
-                Expr::Boolean(arg) => Ok(Expr::bool(!arg.value)),
-                _ => Err(format!("Expected boolean, found: {}", arg)),
-            }),
+            "string<?".to_string(),
+            BuiltIn::TwoArg(|l, r| string_compare("string<?", l, r, |l, r| l < r)),
         ),
         (
-            "cons".to_string(),
-            BuiltIn::TwoArg(|l, r| Ok(Expr::Pair(Box::new(l.clone()), Box::new(r.clone()), None))),
+            "string>?".to_string(),
+            BuiltIn::TwoArg(|l, r| string_compare("string>?", l, r, |l, r| l > r)),
         ),
         (
-            "car".to_string(),
-            BuiltIn::OneArg(|pair| match pair {
-                Expr::Pair(box l, ..) => Ok(l.clone()),
-                _ => Err(format!("car expected pair, found: {}", pair)),
+            "string->list".to_string(),
+            BuiltIn::OneArg(|s| {
+                let chars = string_arg("string->list", s)?.chars();
+                Ok(make_pair_from_vec(
+                    chars.map(|c| Expr::Char(c, None)).collect(),
+                ))
             }),
         ),
         (
-            "cdr".to_string(),
-            BuiltIn::OneArg(|pair| match pair {
-                Expr::Pair(_, box r, ..) => Ok(r.clone()),
-                _ => Err(format!("cdr expected pair, found: {}", pair)),
+            "list->string".to_string(),
+            BuiltIn::OneArg(|list| {
+                collect_exprs_from_body(list)
+                    .map_err(|_| format!("list->string: expected list but found: {list}"))?
+                    .iter()
+                    .map(|c| match c {
+                        Expr::Char(c, _) => Ok(*c),
+                        other => Err(format!("list->string: expected char but found: {other}")),
+                    })
+                    .collect::<Result<String, String>>()
+                    .map(|s| Expr::String(s, None))
             }),
         ),
         (
-            "str-append".to_string(),
-            BuiltIn::TwoArg(|l, r| match (l, r) {
-                (Expr::String(l, _), Expr::String(r, _)) => Ok(Expr::String(l.clone() + r, None)),
-                _ => Err(format!("Expected strings, found: {} and {}", l, r)),
+            "symbol->string".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Keyword(name, _) => Ok(Expr::String(name.clone(), None)),
+                other => Err(format!(
+                    "symbol->string: expected symbol but found: {other}"
+                )),
+            }),
+        ),
+        (
+            "string->symbol".to_string(),
+            BuiltIn::OneArg(|s| {
+                Ok(Expr::Keyword(
+                    string_arg("string->symbol", s)?.to_string(),
+                    None,
+                ))
+            }),
+        ),
+        (
+            "number->string".to_string(),
+            BuiltIn::Variadic(number_to_string),
+        ),
+        (
+            "string->number".to_string(),
+            BuiltIn::Variadic(string_to_number),
+        ),
+        (
+            "string-ref".to_string(),
+            BuiltIn::TwoArg(|string, index| match (string, index) {
+                (Expr::String(s, _), Expr::Num(nr)) => nr
+                    .value
+                    .to_usize()
+                    .and_then(|index| s.chars().nth(index))
+                    .map(|c| Expr::Char(c, None))
+                    .ok_or(format!("string-ref: index {index} out of range for {s:?}")),
+                _ => Err(format!(
+                    "string-ref: expected a string and an index, found: {string} and {index}"
+                )),
+            }),
+        ),
+        (
+            "vector?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Vector(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        ("make-vector".to_string(), BuiltIn::Variadic(make_vector)),
+        (
+            "vector".to_string(),
+            BuiltIn::Variadic(|args| Ok(Expr::Vector(Rc::new(RefCell::new(args.clone()))))),
+        ),
+        (
+            "vector-ref".to_string(),
+            BuiltIn::TwoArg(|vector, index| {
+                let (items, slot) = vector_slot("vector-ref", vector, index)?;
+                Ok(items.borrow()[slot].clone())
+            }),
+        ),
+        ("vector-set!".to_string(), BuiltIn::Variadic(vector_set)),
+        (
+            "vector-length".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Vector(items) => Ok(Expr::int(RefCell::borrow(items).len() as i64)),
+                other => Err(format!("vector-length: expected vector but found: {other}")),
+            }),
+        ),
+        (
+            "vector->list".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Vector(items) => Ok(make_pair_from_vec(RefCell::borrow(items).clone())),
+                other => Err(format!("vector->list: expected vector but found: {other}")),
+            }),
+        ),
+        (
+            "list->vector".to_string(),
+            BuiltIn::OneArg(|expr| {
+                collect_exprs_from_body(expr)
+                    .map(|items| Expr::Vector(Rc::new(RefCell::new(items))))
+                    .map_err(|_| format!("list->vector: expected list but found: {expr}"))
+            }),
+        ),
+        (
+            "vector-fill!".to_string(),
+            BuiltIn::TwoArg(|vector, fill| match vector {
+                Expr::Vector(items) => {
+                    items.borrow_mut().fill(fill.clone());
+                    Ok(Expr::Nil)
+                }
+                other => Err(format!("vector-fill!: expected vector but found: {other}")),
+            }),
+        ),
+        (
+            "hash-table?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::HashTable(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "make-hash-table".to_string(),
+            BuiltIn::Variadic(make_hash_table),
+        ),
+        (
+            "hash-table-ref/default".to_string(),
+            BuiltIn::Variadic(hash_table_ref_default),
+        ),
+        (
+            "hash-table-set!".to_string(),
+            BuiltIn::Variadic(hash_table_set),
+        ),
+        (
+            "hash-table-delete!".to_string(),
+            BuiltIn::TwoArg(|table, key| {
+                hash_table_arg("hash-table-delete!", table)?.remove(key);
+                Ok(Expr::Nil)
+            }),
+        ),
+        (
+            "hash-table-contains?".to_string(),
+            BuiltIn::TwoArg(|table, key| {
+                let table = hash_table_arg("hash-table-contains?", table)?;
+                Ok(Expr::bool(table.get(key).is_some()))
+            }),
+        ),
+        (
+            "hash-table-count".to_string(),
+            BuiltIn::OneArg(|table| {
+                let table = hash_table_arg("hash-table-count", table)?;
+                Ok(Expr::int(table.entries().len() as i64))
+            }),
+        ),
+        (
+            "hash-table-keys".to_string(),
+            BuiltIn::OneArg(|table| {
+                let entries = hash_table_arg("hash-table-keys", table)?.entries();
+                Ok(make_pair_from_vec(
+                    entries.into_iter().map(|(key, _)| key).collect(),
+                ))
+            }),
+        ),
+        (
+            "hash-table-values".to_string(),
+            BuiltIn::OneArg(|table| {
+                let entries = hash_table_arg("hash-table-values", table)?.entries();
+                Ok(make_pair_from_vec(
+                    entries.into_iter().map(|(_, value)| value).collect(),
+                ))
+            }),
+        ),
+        (
+            "hash-table->alist".to_string(),
+            BuiltIn::OneArg(|table| {
+                let entries = hash_table_arg("hash-table->alist", table)?.entries();
+                Ok(make_pair_from_vec(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| Expr::Pair(Box::new(key), Box::new(value), None))
+                        .collect(),
+                ))
+            }),
+        ),
+        (
+            "persistent-map".to_string(),
+            BuiltIn::Variadic(|args| persistent_map(args)),
+        ),
+        (
+            "persistent-set".to_string(),
+            BuiltIn::Variadic(|items| persistent_set("persistent-set", items)),
+        ),
+        (
+            "persistent-map?".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::bool(matches!(expr, Expr::Map(..))))),
+        ),
+        (
+            "persistent-set?".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::bool(matches!(expr, Expr::Set(..))))),
+        ),
+        ("map-assoc".to_string(), BuiltIn::Variadic(map_assoc)),
+        (
+            "map-dissoc".to_string(),
+            BuiltIn::TwoArg(|map, key| {
+                let key = MapKey::new("map-dissoc", key)?;
+                Ok(Expr::Map(map_arg("map-dissoc", map)?.without(&key)))
+            }),
+        ),
+        ("map-get".to_string(), BuiltIn::Variadic(map_get)),
+        (
+            "map-contains?".to_string(),
+            BuiltIn::TwoArg(|map, key| {
+                let key = MapKey::new("map-contains?", key)?;
+                Ok(Expr::bool(
+                    map_arg("map-contains?", map)?.contains_key(&key),
+                ))
+            }),
+        ),
+        (
+            "set-contains?".to_string(),
+            BuiltIn::TwoArg(|set, item| {
+                let item = MapKey::new("set-contains?", item)?;
+                Ok(Expr::bool(set_arg("set-contains?", set)?.contains(&item)))
+            }),
+        ),
+        (
+            "map-count".to_string(),
+            BuiltIn::OneArg(|map| Ok(Expr::int(map_arg("map-count", map)?.len() as i64))),
+        ),
+        (
+            "map-keys".to_string(),
+            BuiltIn::OneArg(|map| {
+                let keys = map_arg("map-keys", map)?.keys();
+                Ok(make_pair_from_vec(
+                    keys.map(|MapKey(key)| key.clone()).collect(),
+                ))
+            }),
+        ),
+        (
+            "map-values".to_string(),
+            BuiltIn::OneArg(|map| {
+                let values = map_arg("map-values", map)?.values();
+                Ok(make_pair_from_vec(values.cloned().collect()))
+            }),
+        ),
+        (
+            "map->alist".to_string(),
+            BuiltIn::OneArg(|map| {
+                let entries = map_arg("map->alist", map)?.iter();
+                Ok(make_pair_from_vec(
+                    entries
+                        .map(|(MapKey(key), value)| {
+                            Expr::Pair(Box::new(key.clone()), Box::new(value.clone()), None)
+                        })
+                        .collect(),
+                ))
+            }),
+        ),
+        (
+            "alist->map".to_string(),
+            BuiltIn::OneArg(|alist| {
+                let entries = collect_exprs_from_body(alist)
+                    .map_err(|_| format!("alist->map: expected list but found: {alist}"))?;
+                entries
+                    .iter()
+                    .map(|entry| match entry {
+                        Expr::Pair(key, value, ..) => {
+                            Ok((MapKey::new("alist->map", key)?, (**value).clone()))
+                        }
+                        other => Err(format!("alist->map: expected pair but found: {other}")),
+                    })
+                    .collect::<Result<OrdMap<MapKey, Expr>, String>>()
+                    .map(Expr::Map)
+            }),
+        ),
+        (
+            "set-add".to_string(),
+            BuiltIn::TwoArg(|set, item| {
+                let item = MapKey::new("set-add", item)?;
+                Ok(Expr::Set(set_arg("set-add", set)?.update(item)))
+            }),
+        ),
+        (
+            "set-remove".to_string(),
+            BuiltIn::TwoArg(|set, item| {
+                let item = MapKey::new("set-remove", item)?;
+                Ok(Expr::Set(set_arg("set-remove", set)?.without(&item)))
+            }),
+        ),
+        (
+            "set-union".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                let (l, r) = (set_arg("set-union", l)?, set_arg("set-union", r)?);
+                Ok(Expr::Set(l.clone().union(r.clone())))
+            }),
+        ),
+        (
+            "set-intersection".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                let (l, r) = (
+                    set_arg("set-intersection", l)?,
+                    set_arg("set-intersection", r)?,
+                );
+                Ok(Expr::Set(l.clone().intersection(r.clone())))
+            }),
+        ),
+        (
+            "set-difference".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                let (l, r) = (set_arg("set-difference", l)?, set_arg("set-difference", r)?);
+                Ok(Expr::Set(l.clone().relative_complement(r.clone())))
+            }),
+        ),
+        (
+            "set-count".to_string(),
+            BuiltIn::OneArg(|set| Ok(Expr::int(set_arg("set-count", set)?.len() as i64))),
+        ),
+        (
+            "set->list".to_string(),
+            BuiltIn::OneArg(|set| {
+                let items = set_arg("set->list", set)?.iter();
+                Ok(make_pair_from_vec(
+                    items.map(|MapKey(item)| item.clone()).collect(),
+                ))
+            }),
+        ),
+        (
+            "list->set".to_string(),
+            BuiltIn::OneArg(|list| {
+                collect_exprs_from_body(list)
+                    .map_err(|_| format!("list->set: expected list but found: {list}"))
+                    .and_then(|items| persistent_set("list->set", &items))
+            }),
+        ),
+        (
+            "abs".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => Ok(Expr::Num(Num {
+                    value: nr.value.abs(),
+                    srcloc: None,
+                })),
+                other => Err(format!("abs: expected num but found: {other}")),
+            }),
+        ),
+        (
+            "function?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Lambda(..) | Expr::Parameter(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "symbol?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Keyword(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "+".to_string(),
+            BuiltIn::Variadic(|args| {
+                args.iter().try_fold(Expr::int(0), |acc, curr| {
+                    numeric("+", &acc, curr, |l, r| Ok(Expr::number(l.add(r))))
+                })
+            }),
+        ),
+        (
+            "-".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("-", l, r, |l, r| Ok(Expr::number(l.sub(r))))),
+        ),
+        (
+            "*".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("*", l, r, |l, r| Ok(Expr::number(l.mul(r))))),
+        ),
+        (
+            ">".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric(">", l, r, |l, r| Ok(Expr::bool(l > r)))),
+        ),
+        (
+            "<".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("<", l, r, |l, r| Ok(Expr::bool(l < r)))),
+        ),
+        (
+            "/".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("/", l, r, |l, r| l.div(r).map(Expr::number))),
+        ),
+        (
+            "%".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("%", l, r, |l, r| l.rem(r).map(Expr::number))),
+        ),
+        (
+            "^".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("^", l, r, |l, r| l.pow(r, "^").map(Expr::number))),
+        ),
+        (
+            ">=".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric(">=", l, r, |l, r| Ok(Expr::bool(l >= r)))),
+        ),
+        (
+            "<=".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("<=", l, r, |l, r| Ok(Expr::bool(l <= r)))),
+        ),
+        (
+            "expt".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                numeric("expt", l, r, |l, r| l.pow(r, "expt").map(Expr::number))
+            }),
+        ),
+        (
+            "quotient".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                numeric("quotient", l, r, |l, r| l.quotient(r).map(Expr::number))
+            }),
+        ),
+        (
+            "remainder".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                numeric("remainder", l, r, |l, r| l.remainder(r).map(Expr::number))
+            }),
+        ),
+        (
+            "modulo".to_string(),
+            BuiltIn::TwoArg(|l, r| numeric("modulo", l, r, |l, r| l.modulo(r).map(Expr::number))),
+        ),
+        (
+            "gcd".to_string(),
+            BuiltIn::Variadic(|args| integer_fold("gcd", args, 0, Number::gcd)),
+        ),
+        (
+            "lcm".to_string(),
+            BuiltIn::Variadic(|args| integer_fold("lcm", args, 1, Number::lcm)),
+        ),
+        (
+            "min".to_string(),
+            BuiltIn::Variadic(|args| extremum("min", args, std::cmp::Ordering::Less)),
+        ),
+        (
+            "max".to_string(),
+            BuiltIn::Variadic(|args| extremum("max", args, std::cmp::Ordering::Greater)),
+        ),
+        (
+            "sqrt".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::number(num_arg("sqrt", expr)?.sqrt()))),
+        ),
+        (
+            "exact-integer-sqrt".to_string(),
+            BuiltIn::OneArg(|expr| {
+                match num_arg("exact-integer-sqrt", expr)?.exact_integer_sqrt() {
+                    Some((root, rest)) => {
+                        Ok(Expr::Values(vec![Expr::number(root), Expr::number(rest)]))
+                    }
+                    None => Err(format!(
+                    "exact-integer-sqrt: expected a non-negative exact integer but found: {expr}"
+                )),
+                }
+            }),
+        ),
+        (
+            "exp".to_string(),
+            BuiltIn::OneArg(|expr| float_fn("exp", expr, f64::exp)),
+        ),
+        ("log".to_string(), BuiltIn::Variadic(log)),
+        (
+            "sin".to_string(),
+            BuiltIn::OneArg(|expr| float_fn("sin", expr, f64::sin)),
+        ),
+        (
+            "cos".to_string(),
+            BuiltIn::OneArg(|expr| float_fn("cos", expr, f64::cos)),
+        ),
+        (
+            "tan".to_string(),
+            BuiltIn::OneArg(|expr| float_fn("tan", expr, f64::tan)),
+        ),
+        ("atan".to_string(), BuiltIn::Variadic(atan)),
+        (
+            "floor".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::number(num_arg("floor", expr)?.floor()))),
+        ),
+        (
+            "ceiling".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::number(num_arg("ceiling", expr)?.ceiling()))),
+        ),
+        (
+            "round".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::number(num_arg("round", expr)?.round()))),
+        ),
+        (
+            "truncate".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::number(num_arg("truncate", expr)?.truncate()))),
+        ),
+        (
+            "even?".to_string(),
+            BuiltIn::OneArg(|expr| integer_predicate("even?", expr, true)),
+        ),
+        (
+            "odd?".to_string(),
+            BuiltIn::OneArg(|expr| integer_predicate("odd?", expr, false)),
+        ),
+        (
+            "zero?".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::bool(num_arg("zero?", expr)?.is_zero()))),
+        ),
+        (
+            "positive?".to_string(),
+            BuiltIn::OneArg(|expr| {
+                Ok(Expr::bool(*num_arg("positive?", expr)? > Number::Fixnum(0)))
+            }),
+        ),
+        (
+            "negative?".to_string(),
+            BuiltIn::OneArg(|expr| {
+                Ok(Expr::bool(*num_arg("negative?", expr)? < Number::Fixnum(0)))
+            }),
+        ),
+        (
+            "exact?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => Ok(Expr::bool(nr.value.is_exact())),
+                other => Err(format!("exact?: expected num but found: {other}")),
+            }),
+        ),
+        (
+            "inexact?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => Ok(Expr::bool(!nr.value.is_exact())),
+                other => Err(format!("inexact?: expected num but found: {other}")),
+            }),
+        ),
+        (
+            "integer?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => Ok(Expr::bool(nr.value.is_integer())),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "rational?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => Ok(Expr::bool(nr.value.to_f64().is_finite())),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "numerator".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => nr.value.numerator().map(Expr::number),
+                other => Err(format!("numerator: expected num but found: {other}")),
+            }),
+        ),
+        (
+            "denominator".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Num(nr) => nr.value.denominator().map(Expr::number),
+                other => Err(format!("denominator: expected num but found: {other}")),
             }),
         ),
-        (
-            "to-string".to_string(),
-            BuiltIn::OneArg(|expr| Ok(Expr::String(format!("{expr}"), None))),
+        ("exact".to_string(), BuiltIn::OneArg(exact)),
+        ("inexact->exact".to_string(), BuiltIn::OneArg(exact)),
+        ("inexact".to_string(), BuiltIn::OneArg(inexact)),
+        ("exact->inexact".to_string(), BuiltIn::OneArg(inexact)),
+        (
+            "=".to_string(),
+            BuiltIn::TwoArg(|l, r| Ok(Expr::bool(l == r))),
+        ),
+        ("gensym".to_string(), BuiltIn::Variadic(gensym)),
+        ("make-record".to_string(), BuiltIn::Variadic(make_record)),
+        (
+            "record-of-type?".to_string(),
+            BuiltIn::TwoArg(|record_type, expr| match (record_type, expr) {
+                (Expr::RecordType(record_type), Expr::Record(record)) => {
+                    Ok(Expr::bool(Rc::ptr_eq(record_type, &record.record_type)))
+                }
+                (Expr::RecordType(..), _) => Ok(Expr::bool(false)),
+                _ => Err(format!(
+                    "record-of-type?: expected a record type, found: {record_type}"
+                )),
+            }),
+        ),
+        (
+            "record-ref".to_string(),
+            BuiltIn::Variadic(|args| record_ref(args)),
+        ),
+        (
+            "record-set!".to_string(),
+            BuiltIn::Variadic(|args| record_set(args)),
+        ),
+        ("generate-temporary".to_string(), BuiltIn::Variadic(gensym)),
+        (
+            "eqv?".to_string(),
+            BuiltIn::TwoArg(|l, r| {
+                eqv_arg("eqv?", l)?;
+                eqv_arg("eqv?", r)?;
+                Ok(Expr::bool(is_eqv(l, r)))
+            }),
+        ),
+        (
+            "equal?".to_string(),
+            BuiltIn::TwoArg(|l, r| Ok(Expr::bool(is_equal(l, r)))),
+        ),
+        (
+            "not".to_string(),
+            BuiltIn::OneArg(|arg| match arg {
+                Expr::Boolean(arg) => Ok(Expr::bool(!arg.value)),
+                _ => Err(format!("Expected boolean, found: {}", arg)),
+            }),
+        ),
+        (
+            "cons".to_string(),
+            BuiltIn::TwoArg(|l, r| Ok(Expr::Pair(Box::new(l.clone()), Box::new(r.clone()), None))),
+        ),
+        (
+            "car".to_string(),
+            BuiltIn::OneArg(|pair| match pair {
+                Expr::Pair(box l, ..) => Ok(l.clone()),
+                _ => Err(format!("car expected pair, found: {}", pair)),
+            }),
+        ),
+        (
+            "cdr".to_string(),
+            BuiltIn::OneArg(|pair| match pair {
+                Expr::Pair(_, box r, ..) => Ok(r.clone()),
+                _ => Err(format!("cdr expected pair, found: {}", pair)),
+            }),
+        ),
+        (
+            "append".to_string(),
+            BuiltIn::Variadic(|args| match args.split_last() {
+                Some((last, lists)) => lists.iter().rev().try_fold(last.clone(), |acc, list| {
+                    collect_exprs_from_body(list)
+                        .map_err(|_| format!("append expected list, found: {list}"))
+                        .map(|items| {
+                            items.into_iter().rev().fold(acc, |acc, item| {
+                                Expr::Pair(Box::new(item), Box::new(acc), None)
+                            })
+                        })
+                }),
+                None => Ok(Expr::Nil),
+            }),
+        ),
+        (
+            "str-append".to_string(),
+            BuiltIn::TwoArg(|l, r| match (l, r) {
+                (Expr::String(l, _), Expr::String(r, _)) => Ok(Expr::String(l.clone() + r, None)),
+                _ => Err(format!("Expected strings, found: {} and {}", l, r)),
+            }),
+        ),
+        (
+            "values".to_string(),
+            BuiltIn::Variadic(|args| match args.as_slice() {
+                [value] => Ok(value.clone()),
+                values => Ok(Expr::Values(values.to_vec())),
+            }),
+        ),
+        (
+            "promise?".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                Expr::Promise(..) => Ok(Expr::bool(true)),
+                _ => Ok(Expr::bool(false)),
+            }),
+        ),
+        (
+            "make-promise".to_string(),
+            BuiltIn::OneArg(|expr| match expr {
+                promise @ Expr::Promise(..) => Ok(promise.clone()),
+                value => Ok(Expr::Promise(Rc::new(RefCell::new(Promise::Forced(
+                    value.clone(),
+                ))))),
+            }),
+        ),
+        (
+            "to-string".to_string(),
+            BuiltIn::OneArg(|expr| Ok(Expr::String(format!("{expr}"), None))),
+        ),
+    ])
+});
+
+pub fn collect_kws_from_expr(expr: &Expr) -> Result<Vec<String>, CompileError> {
+    match expr {
+        Expr::Pair(box Expr::Keyword(kw, ..), box rest, ..) => {
+            collect_kws_from_expr(rest).map(|mut x| {
+                x.insert(0, kw.clone());
+                x
+            })
+        }
+        Expr::Nil => Ok(vec![]),
+        _ => Err(CompileError {
+            message: format!("Invalid keyword list: {}", expr),
+            srcloc: extract_srcloc(expr),
+        }),
+    }
+}
+
+pub fn collect_exprs_from_body(expr: &Expr) -> Result<Vec<Expr>, CompileError> {
+    match expr {
+        Expr::Nil => Ok(vec![]),
+        Expr::Pair(box expr, box Expr::Nil, ..) => Ok(vec![expr.to_owned()]),
+        Expr::Pair(box expr, next @ box Expr::Pair(..), ..) => {
+            collect_exprs_from_body(next).map(|mut x| {
+                x.insert(0, expr.to_owned());
+                x
+            })
+        }
+        otherwise => Err(CompileError {
+            srcloc: extract_srcloc(expr),
+            message: format!("tried to collect exprs from body on: {}", otherwise),
+        }),
+    }
+}
+
+// internal fn that finds closed variables.
+pub fn find_closed_variables(
+    exprs: &Vec<Expr>,
+    original_parent_scope: &Vec<String>, // variables already defined before
+    // defined since before
+    new_definitions: &[String], // variables being defined since start of recursion
+) -> Result<Vec<String>, CompileError> {
+    let local_scope = [new_definitions.to_vec(), get_all_defines(exprs)].concat();
+    let mut closed = vec![];
+    for expr in exprs {
+        match expr {
+            Expr::Pair(
+                box Expr::Keyword(lambda_kw, ..),
+                box Expr::Pair(kw_pairs, box lambda_body, ..),
+                ..,
+            ) if lambda_kw == "lambda" => {
+                let new_locals = [collect_kws_from_expr(kw_pairs)?, local_scope.clone()].concat();
+                let mut closed_in_lambda = find_closed_variables(
+                    &collect_exprs_from_body(lambda_body)?,
+                    original_parent_scope,
+                    &new_locals,
+                )?;
+                closed.append(&mut closed_in_lambda);
+            }
+            Expr::Pair(
+                box Expr::Keyword(define_kw, ..),
+                box Expr::Pair(
+                    box Expr::Pair(box Expr::Keyword(lambda_name, ..), kw_pairs, ..),
+                    box lambda_body,
+                    ..,
+                ),
+                ..,
+            ) if define_kw == "define" => {
+                let new_locals = {
+                    let mut new_locals =
+                        [collect_kws_from_expr(kw_pairs)?, local_scope.clone()].concat();
+                    new_locals.push(lambda_name.clone());
+                    new_locals
+                };
+                let mut closed_in_lambda = find_closed_variables(
+                    &collect_exprs_from_body(lambda_body)?,
+                    original_parent_scope,
+                    &new_locals,
+                )?;
+                closed.append(&mut closed_in_lambda);
+            }
+            Expr::Pair(box Expr::Keyword(quote_kw, ..), box _, ..)
+                if quote_kw == "quote" || quote_kw == GLOBAL_REF =>
+            {
+                // noop
+            }
+            Expr::Pair(
+                box Expr::Keyword(quasiquote_kw, ..),
+                box Expr::Pair(box template, ..),
+                ..,
+            ) if quasiquote_kw == "quasiquote" => {
+                let mut unquoted = vec![];
+                collect_unquoted(template, 1, &mut unquoted);
+                let mut closed_in_unquoted =
+                    find_closed_variables(&unquoted, original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_unquoted);
+            }
+            Expr::Pair(box Expr::Keyword(receive_kw, ..), box rest, srcloc)
+                if receive_kw == "receive" =>
+            {
+                // same scoping as ((lambda formals body...) producer)
+                let (lambda, producer) = destructure_receive(rest)?;
+                let application = make_pair_from_vec(vec![
+                    Expr::Pair(
+                        Box::new(Expr::Keyword("lambda".to_string(), srcloc.clone())),
+                        Box::new(lambda),
+                        srcloc.clone(),
+                    ),
+                    producer.clone(),
+                ]);
+                let mut closed_in_receive =
+                    find_closed_variables(&vec![application], original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_receive);
+            }
+            Expr::Pair(
+                box Expr::Keyword(define_values_kw, ..),
+                box Expr::Pair(_, box rest, ..),
+                ..,
+            ) if define_values_kw == "define-values" => {
+                // the formals are already part of the local scope
+                let mut closed_in_producer = find_closed_variables(
+                    &collect_exprs_from_body(rest)?,
+                    original_parent_scope,
+                    &local_scope,
+                )?;
+                closed.append(&mut closed_in_producer);
+            }
+            Expr::Pair(box Expr::Keyword(let_values_kw, ..), box rest, ..)
+                if let_values_kw == "let-values" || let_values_kw == "let*-values" =>
+            {
+                let mut closed_in_let_values = find_closed_variables(
+                    &vec![expand_let_values(rest, let_values_kw == "let*-values")?],
+                    original_parent_scope,
+                    &local_scope,
+                )?;
+                closed.append(&mut closed_in_let_values);
+            }
+            Expr::Pair(
+                box Expr::Keyword(case_kw, ..),
+                box Expr::Pair(box key, box clauses, ..),
+                ..,
+            ) if case_kw == "case" => {
+                // the datums are not evaluated
+                let exprs = collect_case_clauses(clauses)?.into_iter().fold(
+                    vec![key.clone()],
+                    |mut exprs, clause| {
+                        exprs.extend(clause.body);
+                        exprs
+                    },
+                );
+                let mut closed_in_case =
+                    find_closed_variables(&exprs, original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_case);
+            }
+            Expr::Pair(box Expr::Keyword(cond_kw, ..), box clauses, ..) if cond_kw == "cond" => {
+                let exprs = collect_cond_clauses(clauses)?
+                    .into_iter()
+                    .flat_map(|clause| clause.test.into_iter().chain(clause.body))
+                    .collect::<Vec<Expr>>();
+                let mut closed_in_cond =
+                    find_closed_variables(&exprs, original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_cond);
+            }
+            Expr::Pair(box Expr::Keyword(do_kw, ..), box rest, srcloc) if do_kw == "do" => {
+                // same scoping as ((lambda (var...) test expr... command... step...) init...)
+                let DoLoop {
+                    vars,
+                    test,
+                    results,
+                    commands,
+                } = destructure_do(rest)?;
+                let keyword = |kw: &str| Expr::Keyword(kw.to_string(), srcloc.clone());
+                let body = [
+                    vec![test],
+                    results,
+                    commands,
+                    vars.iter()
+                        .filter_map(|(_, _, step)| step.clone())
+                        .collect(),
+                ]
+                .concat();
+                let lambda = make_pair_from_vec(
+                    [
+                        vec![
+                            keyword("lambda"),
+                            make_pair_from_vec(vars.iter().map(|(var, ..)| keyword(var)).collect()),
+                        ],
+                        body,
+                    ]
+                    .concat(),
+                );
+                let application = make_pair_from_vec(
+                    [
+                        vec![lambda],
+                        vars.into_iter().map(|(_, init, _)| init).collect(),
+                    ]
+                    .concat(),
+                );
+                let mut closed_in_do =
+                    find_closed_variables(&vec![application], original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_do);
+            }
+            Expr::Pair(box Expr::Keyword(let_kw, ..), box rest, ..)
+                if ["let", "let*", "letrec", "letrec*"].contains(&let_kw.as_str()) =>
+            {
+                let mut closed_in_let = find_closed_variables(
+                    &vec![let_as_lambdas(let_kw, rest)?],
+                    original_parent_scope,
+                    &local_scope,
+                )?;
+                closed.append(&mut closed_in_let);
+            }
+            Expr::Pair(box l, box r, ..) => {
+                let mut closed_in_l =
+                    find_closed_variables(&vec![l.clone()], original_parent_scope, &local_scope)?;
+                let mut closed_in_r =
+                    find_closed_variables(&vec![r.clone()], original_parent_scope, &local_scope)?;
+                closed.append(&mut closed_in_l);
+                closed.append(&mut closed_in_r);
+            }
+            Expr::Keyword(kw, ..) => {
+                if original_parent_scope.contains(kw) {
+                    closed.push(kw.clone())
+                } else if local_scope.contains(kw)
+                    || BUILTIN_FNS.contains_key(kw)
+                    || SPECIAL_FORMS.contains_key(kw)
+                {
+                    // ok
+                } else {
+                    return comp_err!(expr, "{kw} is not defined");
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(closed)
+}
+
+fn make_lambda(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (pairs, unextracted_body) = match expr {
+        Expr::Pair(pairs @ box Expr::Nil, body @ box Expr::Pair(..), ..) => (pairs, body),
+        Expr::Pair(pairs @ box Expr::Pair(..), body @ box Expr::Pair(..), ..) => (pairs, body),
+        otherwise => return comp_err!(expr, "Invalid lambda expression: {}", otherwise),
+    };
+
+    let body = collect_exprs_from_body(unextracted_body)?;
+
+    // all kws (including rest)
+
+    let all_kws = collect_kws_from_expr(pairs.borrow())?;
+
+    let dot_kw = all_kws
+        .iter()
+        .enumerate()
+        .find(|(_, kw)| *kw == ".")
+        .map(|(index, _)| index);
+
+    if let Some(dot_index) = dot_kw {
+        // only valid if it's the second to last argument
+        if dot_index + 2 != all_kws.len() {
+            return comp_err!(
+                expr,
+                "rest-dot can only occur as second-to-last argument, but found: ({})",
+                all_kws.join(" ")
+            );
+        }
+    };
+
+    let rest_arg = dot_kw.and_then(|index| all_kws.get(index + 1));
+    let (kws, _) = all_kws.split_at(dot_kw.unwrap_or(all_kws.len()));
+
+    let mut new_body_chunk = Chunk { code: vec![] };
+    let locals = get_all_defines(&body);
+
+    // find closing over variables
+    let mut lambda_env = {
+        let mut lambda_env = env.clone();
+        lambda_env.append(&mut kws.to_vec());
+        if let Some(rest_arg) = rest_arg {
+            lambda_env.push(rest_arg.clone());
+        }
+        lambda_env.append(&mut locals.clone());
+        lambda_env
+    };
+    let closed_variables = find_closed_variables(&body.clone(), env, &lambda_env)?;
+    compile_many_exprs(body.clone(), &mut new_body_chunk, &mut lambda_env)?;
+
+    chunk.code.push(VMInstruction::MakeLambda(
+        new_body_chunk,
+        rest_arg.cloned(),
+        kws.to_vec(),
+        locals,
+        closed_variables,
+    ));
+    Ok(())
+}
+
+fn make_define(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let kw = match expr {
+        Expr::Pair(
+            box Expr::Pair(box Expr::Keyword(fn_name, ..), fn_args, ..),
+            body @ box Expr::Pair(..),
+            src_loc,
+        ) => {
+            // this is a lambda definition
+            // kws should contain a fn name and then its args
+            make_lambda(
+                &Expr::Pair(fn_args.clone(), body.clone(), src_loc.clone()),
+                chunk,
+                env,
+            )?;
+            Ok(fn_name.clone())
+        }
+        Expr::Pair(
+            box Expr::Keyword(kw, ..),
+            box Expr::Pair(box definee, box Expr::Nil, ..),
+            ..,
+        ) => {
+            compile_internal(definee, chunk, env)?;
+            Ok(kw.clone())
+        }
+        otherwise => {
+            return comp_err!(
+                expr,
+                "definition, expected kw and expr but found: {}",
+                otherwise
+            )
+        }
+    }?;
+
+    chunk.code.push(VMInstruction::Define(kw.clone()));
+    chunk.code.push(VMInstruction::Constant(Expr::Nil));
+    Ok(())
+}
+
+fn make_if(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (pred, consequent, alternate) = match expr {
+        Expr::Pair(
+            box pred,
+            box Expr::Pair(box consequent, box Expr::Pair(box alternate, box Expr::Nil, ..), ..),
+            ..,
+        ) => (pred, consequent, alternate),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "if, expected pred, cons, alt but found: {}",
+                otherwise
+            )
+        }
+    };
+
+    let mut pred_chunk = Chunk { code: vec![] };
+    compile_internal(pred, &mut pred_chunk, env)?;
+
+    let mut alt_chunk = Chunk { code: vec![] };
+    compile_internal(alternate, &mut alt_chunk, env)?;
+
+    let mut cons_chunk = Chunk { code: vec![] };
+    compile_internal(consequent, &mut cons_chunk, env)?;
+
+    chunk.code.extend_from_slice(&pred_chunk.code);
+    push_branches(chunk, cons_chunk, alt_chunk);
+    Ok(())
+}
+
+// Jumps to the consequent if the value on top of the stack is truthy and to
+// the alternate otherwise, popping the value.
+fn push_branches(chunk: &mut Chunk, consequent: Chunk, alternate: Chunk) {
+    let end_ip = consequent.code.len();
+
+    let cons_ip = 1 + 1 + alternate.code.len();
+
+    chunk.code.push(VMInstruction::CondJumpPop(cons_ip));
+
+    chunk.code.extend(alternate.code);
+
+    chunk.code.push(VMInstruction::Constant(Expr::bool(true)));
+    chunk.code.push(VMInstruction::CondJumpPop(end_ip));
+    chunk.code.extend(consequent.code);
+}
+
+fn make_and(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let and_chunk = compile_and(&collect_exprs_from_body(expr)?, env)?;
+    chunk.code.extend(and_chunk.code);
+    Ok(())
+}
+
+// (and) is true, otherwise the value of the first falsy operand or of the last
+fn compile_and(exprs: &[Expr], env: &mut Vec<String>) -> Result<Chunk, CompileError> {
+    let mut chunk = Chunk { code: vec![] };
+    match exprs {
+        [] => chunk.code.push(VMInstruction::Constant(Expr::bool(true))),
+        [last] => compile_internal(last, &mut chunk, env)?,
+        [l, rest @ ..] => {
+            // l + popjmp(r) + jmp(return) + r + return
+            compile_internal(l, &mut chunk, env)?;
+            let r_chunk = compile_and(rest, env)?;
+            chunk.code.push(VMInstruction::CondJump(2));
+            chunk.code.push(VMInstruction::Constant(Expr::bool(true)));
+            chunk
+                .code
+                .push(VMInstruction::CondJumpPop(1 + r_chunk.code.len()));
+            chunk.code.push(VMInstruction::PopStack);
+            chunk.code.extend(r_chunk.code);
+        }
+    }
+    Ok(chunk)
+}
+
+fn make_or(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let or_chunk = compile_or(&collect_exprs_from_body(expr)?, env)?;
+    chunk.code.extend(or_chunk.code);
+    Ok(())
+}
+
+// (or) is false, otherwise the value of the first truthy operand or of the last
+fn compile_or(exprs: &[Expr], env: &mut Vec<String>) -> Result<Chunk, CompileError> {
+    let mut chunk = Chunk { code: vec![] };
+    match exprs {
+        [] => chunk.code.push(VMInstruction::Constant(Expr::bool(false))),
+        [last] => compile_internal(last, &mut chunk, env)?,
+        [l, rest @ ..] => {
+            compile_internal(l, &mut chunk, env)?;
+            let r_chunk = compile_or(rest, env)?;
+            chunk
+                .code
+                .push(VMInstruction::CondJump(1 + r_chunk.code.len()));
+            chunk.code.push(VMInstruction::PopStack);
+            chunk.code.extend(r_chunk.code);
+        }
+    }
+    Ok(chunk)
+}
+
+fn has_vector(datum: &Expr) -> bool {
+    match datum {
+        Expr::Vector(..) => true,
+        Expr::Pair(car, cdr, ..) => has_vector(car) || has_vector(cdr),
+        _ => false,
+    }
+}
+
+// Vectors can be changed, so a datum with vectors is copied each time it is
+// evaluated instead of being shared by every evaluation.
+fn literal(datum: &Expr) -> VMInstruction {
+    match has_vector(datum) {
+        true => VMInstruction::Literal(datum.clone()),
+        false => VMInstruction::Constant(datum.clone()),
+    }
+}
+
+fn make_quote(expr: &Expr, chunk: &mut Chunk, _env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    if let (Some(arg), 1) = (exprs.first(), exprs.len()) {
+        chunk.code.push(literal(arg))
+    } else {
+        return comp_err!(expr, "quote expects 1 arg, but found: {:#?}", exprs);
+    }
+    Ok(())
+}
+// (#<global ref> name) is what a hygienic macro expands a free identifier to
+// when the use site shadows it, it skips the local bindings of name.
+fn make_global_ref(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    match expr {
+        Expr::Pair(box name @ Expr::Keyword(kw, ..), box Expr::Nil, ..) => {
+            // reports names that aren't defined anywhere
+            compile_internal(name, &mut Chunk { code: vec![] }, env)?;
+            chunk.code.push(VMInstruction::LookupGlobal(kw.clone()));
+            Ok(())
+        }
+        otherwise => comp_err!(
+            expr,
+            "{GLOBAL_REF}, expected a name but found: {}",
+            otherwise
+        ),
+    }
+}
+
+fn make_display(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    match expr {
+        Expr::Pair(box displayee, box Expr::Nil, ..) => {
+            compile_internal(displayee, chunk, env)?;
+            chunk.code.push(VMInstruction::Display);
+            Ok(())
+        }
+        otherwise => {
+            comp_err!(
+                expr,
+                "Expected one argument for display, but found {}",
+                otherwise
+            )
+        }
+    }
+}
+fn make_apply(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    if let (Some(function), Some(args), 2) = (exprs.first(), exprs.get(1), exprs.len()) {
+        compile_internal(function, chunk, env)?;
+        compile_internal(args, chunk, env)?;
+        chunk.code.push(VMInstruction::Apply);
+        chunk.code.push(VMInstruction::Call(0));
+    } else {
+        return comp_err!(expr, "apply expects 2 args, but found: {}", exprs.len());
+    }
+    Ok(())
+}
+
+fn make_dynamic_wind(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    let (before, thunk, after) = match exprs.as_slice() {
+        [before, thunk, after] => (before, thunk, after),
+        _ => {
+            return comp_err!(
+                expr,
+                "dynamic-wind expects 3 args, but found: {}",
+                exprs.len()
+            )
+        }
+    };
+    compile_internal(before, chunk, env)?;
+    chunk.code.push(VMInstruction::Call(0));
+    chunk.code.push(VMInstruction::PopStack);
+    compile_internal(after, chunk, env)?;
+    chunk.code.push(VMInstruction::PushWind);
+    compile_internal(thunk, chunk, env)?;
+    chunk.code.push(VMInstruction::Call(0));
+    // leaves the after-thunk on top of the thunk's return value
+    chunk.code.push(VMInstruction::PopWind);
+    chunk.code.push(VMInstruction::Call(0));
+    chunk.code.push(VMInstruction::PopStack);
+    Ok(())
+}
+
+fn make_make_parameter(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    match exprs.as_slice() {
+        [value] => {
+            compile_internal(value, chunk, env)?;
+            chunk.code.push(VMInstruction::MakeParameter(false));
+        }
+        [value, converter] => {
+            compile_internal(value, chunk, env)?;
+            compile_internal(converter, chunk, env)?;
+            chunk.code.push(VMInstruction::MakeParameter(true));
+        }
+        _ => {
+            return comp_err!(
+                expr,
+                "make-parameter expects 1 or 2 args, but found: {}",
+                exprs.len()
+            )
+        }
+    }
+    Ok(())
+}
+
+fn make_parameterize(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (bindings, body, srcloc) = match expr {
+        Expr::Pair(box bindings, body @ box Expr::Pair(..), srcloc) => (bindings, body, srcloc),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "parameterize, expected bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_exprs_from_body(bindings)?;
+    for binding in bindings.iter() {
+        match collect_exprs_from_body(binding)?.as_slice() {
+            [parameter, value] => {
+                compile_internal(parameter, chunk, env)?;
+                compile_internal(value, chunk, env)?;
+            }
+            _ => {
+                return comp_err!(
+                    binding,
+                    "parameterize, expected (parameter value) but found: {}",
+                    binding
+                )
+            }
+        }
+    }
+    chunk.code.push(VMInstruction::Parameterize(bindings.len()));
+    make_lambda(
+        &Expr::Pair(Box::new(Expr::Nil), body.clone(), srcloc.clone()),
+        chunk,
+        env,
+    )?;
+    chunk.code.push(VMInstruction::Call(0));
+    chunk.code.push(VMInstruction::PopParameterize);
+    Ok(())
+}
+
+// Splits formals into the required parameters and the rest parameter,
+// a lone keyword collects all values into the rest parameter.
+fn collect_formals(formals: &Expr) -> Result<(Vec<String>, Option<String>), CompileError> {
+    if let Expr::Keyword(rest, ..) = formals {
+        return Ok((vec![], Some(rest.clone())));
+    }
+    let all_kws = collect_kws_from_expr(formals)?;
+    match all_kws.iter().position(|kw| kw == ".") {
+        Some(dot_index) if dot_index + 2 == all_kws.len() => Ok((
+            all_kws[..dot_index].to_vec(),
+            all_kws.get(dot_index + 1).cloned(),
+        )),
+        Some(_) => comp_err!(
+            formals,
+            "rest-dot can only occur as second-to-last argument, but found: ({})",
+            all_kws.join(" ")
+        ),
+        None => Ok((all_kws, None)),
+    }
+}
+
+fn make_call_with_values(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    let (producer, consumer) = match exprs.as_slice() {
+        [producer, consumer] => (producer, consumer),
+        _ => {
+            return comp_err!(
+                expr,
+                "call-with-values expects 2 args, but found: {}",
+                exprs.len()
+            )
+        }
+    };
+    compile_internal(consumer, chunk, env)?;
+    compile_internal(producer, chunk, env)?;
+    chunk.code.push(VMInstruction::Call(0));
+    chunk.code.push(VMInstruction::CallWithValues);
+    Ok(())
+}
+
+// (receive formals producer body...) => ((formals . body), producer)
+fn destructure_receive(expr: &Expr) -> Result<(Expr, &Expr), CompileError> {
+    match expr {
+        Expr::Pair(
+            box formals,
+            box Expr::Pair(box producer, body @ box Expr::Pair(..), ..),
+            srcloc,
+        ) => {
+            let formals = match formals {
+                Expr::Keyword(_, kw_srcloc) => make_pair_from_vec(vec![
+                    Expr::Keyword(".".to_string(), kw_srcloc.clone()),
+                    formals.clone(),
+                ]),
+                _ => formals.clone(),
+            };
+            Ok((
+                Expr::Pair(Box::new(formals), body.clone(), srcloc.clone()),
+                producer,
+            ))
+        }
+        otherwise => comp_err!(
+            expr,
+            "receive, expected formals, expr and body but found: {}",
+            otherwise
+        ),
+    }
+}
+
+fn make_receive(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (lambda, producer) = destructure_receive(expr)?;
+    make_lambda(&lambda, chunk, env)?;
+    compile_internal(producer, chunk, env)?;
+    chunk.code.push(VMInstruction::CallWithValues);
+    Ok(())
+}
+
+// (let*-values ((formals1 e1) (formals2 e2)) body...)
+// => (receive formals1 e1 (receive formals2 e2 body...))
+// let-values receives into fresh names instead, so every producer is evaluated
+// before any of the formals are bound:
+// => (receive t1 e1 (receive t2 e2 (let ((formal1 t1) ...) body...)))
+fn expand_let_values(expr: &Expr, sequential: bool) -> Result<Expr, CompileError> {
+    let (bindings, body, srcloc) = match expr {
+        Expr::Pair(box bindings, body @ box Expr::Pair(..), srcloc) => (bindings, body, srcloc),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "let-values, expected bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_exprs_from_body(bindings)?
+        .iter()
+        .map(
+            |binding| match collect_exprs_from_body(binding)?.as_slice() {
+                [formals, producer] => Ok((formals.clone(), producer.clone())),
+                _ => comp_err!(
+                    binding,
+                    "let-values, expected (formals expr) but found: {}",
+                    binding
+                ),
+            },
+        )
+        .collect::<Result<Vec<(Expr, Expr)>, CompileError>>()?;
+    let mut renames = vec![];
+    let bindings = match sequential {
+        true => bindings,
+        false => bindings
+            .into_iter()
+            .map(|(formals, producer)| (rename_formals(&formals, &mut renames), producer))
+            .collect(),
+    };
+    let body = match renames.is_empty() {
+        true => *body.clone(),
+        false => make_pair_from_vec(vec![Expr::Pair(
+            Box::new(Expr::Keyword("let".to_string(), srcloc.clone())),
+            Box::new(Expr::Pair(
+                Box::new(make_pair_from_vec(renames)),
+                body.clone(),
+                srcloc.clone(),
+            )),
+            srcloc.clone(),
+        )]),
+    };
+    if bindings.is_empty() {
+        return Ok(make_pair_from_vec(vec![Expr::Pair(
+            Box::new(Expr::Keyword("lambda".to_string(), srcloc.clone())),
+            Box::new(Expr::Pair(
+                Box::new(Expr::Nil),
+                Box::new(body),
+                srcloc.clone(),
+            )),
+            srcloc.clone(),
+        )]));
+    }
+    let receives = bindings
+        .into_iter()
+        .rev()
+        .fold(body, |body, (formals, producer)| {
+            make_pair_from_vec(vec![Expr::Pair(
+                Box::new(Expr::Keyword("receive".to_string(), srcloc.clone())),
+                Box::new(Expr::Pair(
+                    Box::new(formals),
+                    Box::new(Expr::Pair(
+                        Box::new(producer),
+                        Box::new(body),
+                        srcloc.clone(),
+                    )),
+                    srcloc.clone(),
+                )),
+                srcloc.clone(),
+            )])
+        });
+    match receives {
+        Expr::Pair(box receive, ..) => Ok(receive),
+        otherwise => comp_err!(&otherwise, "let-values, could not expand: {}", otherwise),
+    }
+}
+
+// Replaces every name in formals with a fresh one, and adds a (name fresh)
+// binding for each to renames.
+fn rename_formals(formals: &Expr, renames: &mut Vec<Expr>) -> Expr {
+    match formals {
+        Expr::Keyword(dot, ..) if dot == "." => formals.clone(),
+        Expr::Keyword(name, srcloc) => {
+            let fresh = Expr::Keyword(fresh_name(name), srcloc.clone());
+            renames.push(make_pair_from_vec(vec![formals.clone(), fresh.clone()]));
+            fresh
+        }
+        Expr::Pair(box car, box cdr, srcloc) => Expr::Pair(
+            Box::new(rename_formals(car, renames)),
+            Box::new(rename_formals(cdr, renames)),
+            srcloc.clone(),
+        ),
+        _ => formals.clone(),
+    }
+}
+
+fn make_let_values(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    compile_internal(&expand_let_values(expr, false)?, chunk, env)
+}
+
+fn make_let_star_values(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    compile_internal(&expand_let_values(expr, true)?, chunk, env)
+}
+
+fn make_define_values(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (formals, producer) = match expr {
+        Expr::Pair(box formals, box Expr::Pair(box producer, box Expr::Nil, ..), ..) => {
+            (formals, producer)
+        }
+        otherwise => {
+            return comp_err!(
+                expr,
+                "define-values, expected formals and expr but found: {}",
+                otherwise
+            )
+        }
+    };
+    let (required, rest) = collect_formals(formals)?;
+    compile_internal(producer, chunk, env)?;
+    chunk
+        .code
+        .push(VMInstruction::SpreadValues(required.len(), rest.is_some()));
+    // the rest list ends up on top of the stack, after the required values
+    for name in rest.iter().chain(required.iter().rev()) {
+        chunk.code.push(VMInstruction::Define(name.clone()));
+    }
+    chunk.code.push(VMInstruction::Constant(Expr::Nil));
+    Ok(())
+}
+
+fn make_promise(
+    expr: &Expr,
+    chunk: &mut Chunk,
+    env: &mut Vec<String>,
+    form: &str,
+    instruction: VMInstruction,
+) -> CompileResult {
+    match expr {
+        Expr::Pair(_, box Expr::Nil, srcloc) => {
+            make_lambda(
+                &Expr::Pair(Box::new(Expr::Nil), Box::new(expr.clone()), srcloc.clone()),
+                chunk,
+                env,
+            )?;
+            chunk.code.push(instruction);
+            Ok(())
+        }
+        otherwise => comp_err!(
+            expr,
+            "Expected one argument for {}, but found {}",
+            form,
+            otherwise
         ),
-    ])
-});
+    }
+}
 
-pub fn collect_kws_from_expr(expr: &Expr) -> Result<Vec<String>, CompileError> {
+fn make_delay(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    make_promise(expr, chunk, env, "delay", VMInstruction::MakePromise(false))
+}
+
+fn make_delay_force(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    make_promise(
+        expr,
+        chunk,
+        env,
+        "delay-force",
+        VMInstruction::MakePromise(true),
+    )
+}
+
+fn make_force(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
     match expr {
-        Expr::Pair(box Expr::Keyword(kw, ..), box rest, ..) => {
-            collect_kws_from_expr(rest).map(|mut x| {
-                x.insert(0, kw.clone());
-                x
-            })
+        Expr::Pair(box promise, box Expr::Nil, ..) => {
+            compile_internal(promise, chunk, env)?;
+            chunk.code.push(VMInstruction::Force);
+            Ok(())
         }
-        Expr::Nil => Ok(vec![]),
-        _ => Err(CompileError {
-            message: format!("Invalid keyword list: {}", expr),
-            srcloc: extract_srcloc(expr),
-        }),
+        otherwise => comp_err!(
+            expr,
+            "Expected one argument for force, but found {}",
+            otherwise
+        ),
     }
 }
 
-pub fn collect_exprs_from_body(expr: &Expr) -> Result<Vec<Expr>, CompileError> {
+fn make_cons_stream(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
     match expr {
-        Expr::Nil => Ok(vec![]),
-        Expr::Pair(box expr, box Expr::Nil, ..) => Ok(vec![expr.to_owned()]),
-        Expr::Pair(box expr, next @ box Expr::Pair(..), ..) => {
-            collect_exprs_from_body(next).map(|mut x| {
-                x.insert(0, expr.to_owned());
-                x
-            })
+        Expr::Pair(box head, tail @ box Expr::Pair(_, box Expr::Nil, ..), ..) => {
+            chunk
+                .code
+                .push(VMInstruction::LookupGlobal("cons".to_string()));
+            compile_internal(head, chunk, env)?;
+            make_delay(tail, chunk, env)?;
+            chunk.code.push(VMInstruction::Call(2));
+            Ok(())
         }
-        otherwise => Err(CompileError {
-            srcloc: extract_srcloc(expr),
-            message: format!("tried to collect exprs from body on: {}", otherwise),
-        }),
+        otherwise => comp_err!(
+            expr,
+            "cons-stream, expected head and tail but found: {}",
+            otherwise
+        ),
     }
 }
 
-// internal fn that finds closed variables.
-pub fn find_closed_variables(
-    exprs: &Vec<Expr>,
-    original_parent_scope: &Vec<String>, // variables already defined before
-    // defined since before
-    new_definitions: &Vec<String>, // variables being defined since start of recursion
-) -> Result<Vec<String>, CompileError> {
-    let local_scope = [new_definitions.clone(), get_all_defines(exprs)].concat();
-    let mut closed = vec![];
-    for expr in exprs {
-        match expr {
-            Expr::Pair(
-                box Expr::Keyword(lambda_kw, ..),
-                box Expr::Pair(kw_pairs, box lambda_body, ..),
-                ..,
-            ) if *lambda_kw == "lambda".to_string() => {
-                let new_locals =
-                    [collect_kws_from_expr(kw_pairs)?, new_definitions.clone()].concat();
-                let mut closed_in_lambda = find_closed_variables(
-                    &collect_exprs_from_body(lambda_body)?,
-                    original_parent_scope,
-                    &new_locals,
-                )?;
-                closed.append(&mut closed_in_lambda);
-            }
-            Expr::Pair(
-                box Expr::Keyword(define_kw, ..),
-                box Expr::Pair(
-                    box Expr::Pair(box Expr::Keyword(lambda_name, ..), kw_pairs, ..),
-                    box lambda_body,
-                    ..,
+// (let ((name init)...) body...), all inits are evaluated before any of the
+// names are bound. The bindings live in a scope of the current callframe.
+fn make_let(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (bindings, body) = match expr {
+        Expr::Pair(box Expr::Keyword(name, ..), box rest, ..) => {
+            return make_named_let(name, rest, chunk, env)
+        }
+        Expr::Pair(box bindings, box body @ Expr::Pair(..), ..) => (bindings, body),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "let, expected bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, "let")?;
+    for (_, init) in bindings.iter() {
+        compile_internal(init, chunk, env)?;
+    }
+    chunk.code.push(VMInstruction::EnterScope(None));
+    // the names are only in scope until the end of the body
+    let outer_env_len = env.len();
+    for (name, _) in bindings.iter().rev() {
+        chunk.code.push(VMInstruction::Bind(name.clone()));
+        env.push(name.clone());
+    }
+    compile_scope_body(&collect_exprs_from_body(body)?, chunk, env)?;
+    chunk.code.push(VMInstruction::ExitScope);
+    env.truncate(outer_env_len);
+    Ok(())
+}
+
+// (let name ((var init)...) body...) loops by jumping back to the start of the
+// body when name is only ever called in tail position, otherwise name is bound
+// to a procedure like (letrec ((name (lambda (var...) body...))) name).
+fn make_named_let(
+    name: &str,
+    expr: &Expr,
+    chunk: &mut Chunk,
+    env: &mut Vec<String>,
+) -> CompileResult {
+    let (bindings, body) = match expr {
+        Expr::Pair(box bindings, box body @ Expr::Pair(..), ..) => (bindings, body),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "named let, expected name, bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, "let")?;
+    let body = collect_exprs_from_body(body)?;
+    let loop_body = match rewrite_loop_body(&body, name, bindings.len()) {
+        Some(loop_body) => loop_body,
+        None => return compile_internal(&expand_named_let(name, expr)?, chunk, env),
+    };
+    for (_, init) in bindings.iter() {
+        compile_internal(init, chunk, env)?;
+    }
+    // recurring jumps back here with the new values on the stack
+    chunk
+        .code
+        .push(VMInstruction::EnterScope(Some(name.to_string())));
+    let outer_env_len = env.len();
+    for (var, _) in bindings.iter().rev() {
+        chunk.code.push(VMInstruction::Bind(var.clone()));
+        env.push(var.clone());
+    }
+    compile_scope_body(&loop_body, chunk, env)?;
+    chunk.code.push(VMInstruction::ExitScope);
+    env.truncate(outer_env_len);
+    Ok(())
+}
+
+// (let* ((name init)...) body...), every init sees the names bound before it.
+fn make_let_star(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (bindings, body) = match expr {
+        Expr::Pair(box bindings, box body @ Expr::Pair(..), ..) => (bindings, body),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "let*, expected bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, "let*")?;
+    chunk.code.push(VMInstruction::EnterScope(None));
+    let outer_env_len = env.len();
+    for (name, init) in bindings.iter() {
+        compile_internal(init, chunk, env)?;
+        chunk.code.push(VMInstruction::Bind(name.clone()));
+        env.push(name.clone());
+    }
+    compile_scope_body(&collect_exprs_from_body(body)?, chunk, env)?;
+    chunk.code.push(VMInstruction::ExitScope);
+    env.truncate(outer_env_len);
+    Ok(())
+}
+
+// (letrec ((name init)...) body...), all names are bound (to nil) before the
+// inits are evaluated, so the inits can refer to each other.
+fn make_letrec(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (bindings, body) = match expr {
+        Expr::Pair(box bindings, box body @ Expr::Pair(..), ..) => (bindings, body),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "letrec, expected bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, "letrec")?;
+    chunk.code.push(VMInstruction::EnterScope(None));
+    let outer_env_len = env.len();
+    for (name, _) in bindings.iter() {
+        chunk.code.push(VMInstruction::Constant(Expr::Nil));
+        chunk.code.push(VMInstruction::Bind(name.clone()));
+        env.push(name.clone());
+    }
+    for (name, init) in bindings.iter() {
+        compile_internal(init, chunk, env)?;
+        chunk.code.push(VMInstruction::Define(name.clone()));
+    }
+    compile_scope_body(&collect_exprs_from_body(body)?, chunk, env)?;
+    chunk.code.push(VMInstruction::ExitScope);
+    env.truncate(outer_env_len);
+    Ok(())
+}
+
+fn collect_let_bindings(bindings: &Expr, form: &str) -> Result<Vec<(String, Expr)>, CompileError> {
+    collect_exprs_from_body(bindings)?
+        .iter()
+        .map(
+            |binding| match collect_exprs_from_body(binding)?.as_slice() {
+                [Expr::Keyword(name, ..), init] => Ok((name.clone(), init.clone())),
+                _ => comp_err!(
+                    binding,
+                    "{}, expected (name expr) but found: {}",
+                    form,
+                    binding
                 ),
-                ..,
-            ) if *define_kw == "define".to_string() => {
-                let new_locals = {
-                    let mut new_locals =
-                        [collect_kws_from_expr(kw_pairs)?, new_definitions.clone()].concat();
-                    new_locals.push(lambda_name.clone());
-                    new_locals
-                };
-                let mut closed_in_lambda = find_closed_variables(
-                    &collect_exprs_from_body(lambda_body)?,
-                    original_parent_scope,
-                    &new_locals,
-                )?;
-                closed.append(&mut closed_in_lambda);
-            }
-            Expr::Pair(box Expr::Keyword(quote_kw, ..), box _, ..) if quote_kw == &"quote" => {
-                // noop
+            },
+        )
+        .collect()
+}
+
+// Compiles the body of a let-like form into the current callframe, leaving
+// the value of the last expression on the stack. Internal defines get fresh
+// bindings in the scope of the form.
+fn compile_scope_body(body: &[Expr], chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    for name in get_all_defines(body) {
+        chunk.code.push(VMInstruction::Constant(Expr::Nil));
+        chunk.code.push(VMInstruction::Bind(name.clone()));
+        env.push(name);
+    }
+    compile_sequence(body, chunk, env)
+}
+
+// Compiles the exprs one after the other, only keeping the value of the last.
+fn compile_sequence(exprs: &[Expr], chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            chunk.code.push(VMInstruction::PopStack);
+        }
+        compile_internal(expr, chunk, env)?;
+    }
+    Ok(())
+}
+
+// (let name ((var init)...) body...)
+// => ((letrec ((name (lambda (var...) body...))) name) init...)
+fn expand_named_let(name: &str, expr: &Expr) -> Result<Expr, CompileError> {
+    let (bindings, body, srcloc) = match expr {
+        Expr::Pair(box bindings, body @ box Expr::Pair(..), srcloc) => (bindings, body, srcloc),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "named let, expected name, bindings and body but found: {}",
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, "let")?;
+    let keyword = |kw: &str| Expr::Keyword(kw.to_string(), srcloc.clone());
+    let lambda = Expr::Pair(
+        Box::new(keyword("lambda")),
+        Box::new(Expr::Pair(
+            Box::new(make_pair_from_vec(
+                bindings.iter().map(|(var, _)| keyword(var)).collect(),
+            )),
+            body.clone(),
+            srcloc.clone(),
+        )),
+        srcloc.clone(),
+    );
+    let letrec = make_pair_from_vec(vec![
+        keyword("letrec"),
+        make_pair_from_vec(vec![make_pair_from_vec(vec![keyword(name), lambda])]),
+        keyword(name),
+    ]);
+    Ok(make_pair_from_vec(
+        [
+            vec![letrec],
+            bindings.into_iter().map(|(_, init)| init).collect(),
+        ]
+        .concat(),
+    ))
+}
+
+// Rewrites the calls to a named let's own name in tail position of its body
+// into jumps back to the start of the loop. Returns None if the name is used
+// in any other way, then it has to be bound to a procedure.
+fn rewrite_loop_body(body: &[Expr], name: &str, arity: usize) -> Option<Vec<Expr>> {
+    let (last, init) = body.split_last()?;
+    if init.iter().any(|expr| mentions(expr, name)) {
+        return None;
+    }
+    let mut body = init.to_vec();
+    body.push(rewrite_loop_tail(last, name, arity)?);
+    Some(body)
+}
+
+fn rewrite_loop_tail(expr: &Expr, name: &str, arity: usize) -> Option<Expr> {
+    match expr {
+        Expr::Pair(box Expr::Keyword(kw, kw_srcloc), box args, srcloc) if kw == name => {
+            let args = collect_exprs_from_body(args).ok()?;
+            if args.len() != arity || args.iter().any(|arg| mentions(arg, name)) {
+                return None;
             }
-            Expr::Pair(box l, box r, ..) => {
-                let mut closed_in_l =
-                    find_closed_variables(&vec![l.clone()], original_parent_scope, &local_scope)?;
-                let mut closed_in_r =
-                    find_closed_variables(&vec![r.clone()], original_parent_scope, &local_scope)?;
-                closed.append(&mut closed_in_l);
-                closed.append(&mut closed_in_r);
+            Some(Expr::Pair(
+                Box::new(Expr::Keyword(
+                    NAMED_LET_RECUR.to_string(),
+                    kw_srcloc.clone(),
+                )),
+                Box::new(expr.clone()),
+                srcloc.clone(),
+            ))
+        }
+        Expr::Pair(box if_kw @ Expr::Keyword(kw, ..), box args, srcloc) if kw == "if" => {
+            match collect_exprs_from_body(args).ok()?.as_slice() {
+                [pred, consequent, alternate] if !mentions(pred, name) => Some(Expr::Pair(
+                    Box::new(if_kw.clone()),
+                    Box::new(make_pair_from_vec(vec![
+                        pred.clone(),
+                        rewrite_loop_tail(consequent, name, arity)?,
+                        rewrite_loop_tail(alternate, name, arity)?,
+                    ])),
+                    srcloc.clone(),
+                )),
+                _ => None,
             }
-            Expr::Keyword(kw, ..) => {
-                if original_parent_scope.contains(kw) {
-                    closed.push(kw.clone())
-                } else if local_scope.contains(kw)
-                    || BUILTIN_FNS.contains_key(kw)
-                    || SPECIAL_FORMS.contains_key(kw)
-                {
-                    // ok
-                } else {
-                    return comp_err!(expr, "{kw} is not defined");
-                }
+        }
+        Expr::Pair(box form_kw @ Expr::Keyword(kw, ..), box args, srcloc)
+            if ["and", "or", "begin", "progn"].contains(&kw.as_str()) =>
+        {
+            let args = collect_exprs_from_body(args).ok()?;
+            Some(Expr::Pair(
+                Box::new(form_kw.clone()),
+                Box::new(make_pair_from_vec(rewrite_loop_body(&args, name, arity)?)),
+                srcloc.clone(),
+            ))
+        }
+        Expr::Pair(
+            box form_kw @ Expr::Keyword(kw, ..),
+            box Expr::Pair(box test, box body, ..),
+            srcloc,
+        ) if (kw == "when" || kw == "unless") && !mentions(test, name) => {
+            let body = collect_exprs_from_body(body).ok()?;
+            Some(Expr::Pair(
+                Box::new(form_kw.clone()),
+                Box::new(make_pair_from_vec(
+                    [vec![test.clone()], rewrite_loop_body(&body, name, arity)?].concat(),
+                )),
+                srcloc.clone(),
+            ))
+        }
+        Expr::Pair(
+            box case_kw @ Expr::Keyword(kw, ..),
+            box Expr::Pair(box key, box clauses, ..),
+            srcloc,
+        ) if kw == "case" && !mentions(key, name) => {
+            let clauses = collect_exprs_from_body(clauses)
+                .ok()?
+                .iter()
+                .map(|clause| match clause {
+                    Expr::Pair(_, box Expr::Pair(box Expr::Keyword(arrow, ..), ..), ..)
+                        if arrow == "=>" =>
+                    {
+                        (!mentions(clause, name)).then(|| clause.clone())
+                    }
+                    Expr::Pair(datums, box body, clause_srcloc) => {
+                        let body = collect_exprs_from_body(body).ok()?;
+                        Some(Expr::Pair(
+                            datums.clone(),
+                            Box::new(make_pair_from_vec(rewrite_loop_body(&body, name, arity)?)),
+                            clause_srcloc.clone(),
+                        ))
+                    }
+                    _ => None,
+                })
+                .collect::<Option<Vec<Expr>>>()?;
+            Some(Expr::Pair(
+                Box::new(case_kw.clone()),
+                Box::new(make_pair_from_vec([vec![key.clone()], clauses].concat())),
+                srcloc.clone(),
+            ))
+        }
+        Expr::Pair(box cond_kw @ Expr::Keyword(kw, ..), box clauses, srcloc) if kw == "cond" => {
+            let clauses = collect_exprs_from_body(clauses)
+                .ok()?
+                .iter()
+                .map(
+                    |clause| match collect_exprs_from_body(clause).ok()?.as_slice() {
+                        [test, Expr::Keyword(arrow, ..), ..] if arrow == "=>" => {
+                            (!mentions(clause, name) && !mentions(test, name))
+                                .then(|| clause.clone())
+                        }
+                        [test] => (!mentions(test, name)).then(|| clause.clone()),
+                        [test, body @ ..] if !mentions(test, name) => Some(make_pair_from_vec(
+                            [vec![test.clone()], rewrite_loop_body(body, name, arity)?].concat(),
+                        )),
+                        _ => None,
+                    },
+                )
+                .collect::<Option<Vec<Expr>>>()?;
+            Some(Expr::Pair(
+                Box::new(cond_kw.clone()),
+                Box::new(make_pair_from_vec(clauses)),
+                srcloc.clone(),
+            ))
+        }
+        expr if mentions(expr, name) => None,
+        expr => Some(expr.clone()),
+    }
+}
+
+fn mentions(expr: &Expr, name: &str) -> bool {
+    match expr {
+        Expr::Keyword(kw, ..) => kw == name,
+        Expr::Pair(box Expr::Keyword(quote_kw, ..), ..)
+            if quote_kw == "quote" || quote_kw == GLOBAL_REF =>
+        {
+            false
+        }
+        Expr::Pair(box l, box r, ..) => mentions(l, name) || mentions(r, name),
+        _ => false,
+    }
+}
+
+// (#<named-let recur> name arg...) is only produced by rewrite_loop_tail
+fn make_named_let_recur(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    match expr {
+        Expr::Pair(box Expr::Keyword(name, ..), box args, ..) => {
+            for arg in collect_exprs_from_body(args)? {
+                compile_internal(&arg, chunk, env)?;
             }
-            _ => {}
+            chunk.code.push(VMInstruction::Recur(name.clone()));
+            Ok(())
+        }
+        otherwise => comp_err!(
+            expr,
+            "{NAMED_LET_RECUR}, expected name and args but found: {}",
+            otherwise
+        ),
+    }
+}
+
+// The scoping of the let forms expressed with lambdas, used when looking for
+// closed over variables.
+fn let_as_lambdas(form: &str, expr: &Expr) -> Result<Expr, CompileError> {
+    let (bindings, body, srcloc) = match expr {
+        Expr::Pair(box Expr::Keyword(name, ..), box rest, ..) if form == "let" => {
+            return expand_named_let(name, rest)
+        }
+        Expr::Pair(box bindings, body @ box Expr::Pair(..), srcloc) => (bindings, body, srcloc),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "{}, expected bindings and body but found: {}",
+                form,
+                otherwise
+            )
+        }
+    };
+    let bindings = collect_let_bindings(bindings, form)?;
+    let keyword = |kw: &str| Expr::Keyword(kw.to_string(), srcloc.clone());
+    let lambda = |formals: Expr, body: Box<Expr>| {
+        Expr::Pair(
+            Box::new(keyword("lambda")),
+            Box::new(Expr::Pair(Box::new(formals), body, srcloc.clone())),
+            srcloc.clone(),
+        )
+    };
+    match (form, bindings.split_first()) {
+        ("let", _) | ("let*", None) => Ok(make_pair_from_vec(
+            [
+                vec![lambda(
+                    make_pair_from_vec(bindings.iter().map(|(name, _)| keyword(name)).collect()),
+                    body.clone(),
+                )],
+                bindings.into_iter().map(|(_, init)| init).collect(),
+            ]
+            .concat(),
+        )),
+        ("let*", Some(((name, init), _))) => {
+            let rest = Expr::Pair(
+                Box::new(keyword("let*")),
+                Box::new(Expr::Pair(
+                    Box::new(make_pair_from_vec(
+                        bindings[1..]
+                            .iter()
+                            .map(|(name, init)| {
+                                make_pair_from_vec(vec![keyword(name), init.clone()])
+                            })
+                            .collect(),
+                    )),
+                    body.clone(),
+                    srcloc.clone(),
+                )),
+                srcloc.clone(),
+            );
+            Ok(make_pair_from_vec(vec![
+                lambda(
+                    make_pair_from_vec(vec![keyword(name)]),
+                    Box::new(make_pair_from_vec(vec![rest])),
+                ),
+                init.clone(),
+            ]))
+        }
+        _ => {
+            let defines = bindings
+                .into_iter()
+                .map(|(name, init)| {
+                    make_pair_from_vec(vec![keyword("define"), keyword(&name), init])
+                })
+                .collect::<Vec<_>>();
+            Ok(make_pair_from_vec(vec![lambda(
+                Expr::Nil,
+                Box::new(make_pair_from_vec(
+                    [defines, collect_exprs_from_body(body)?].concat(),
+                )),
+            )]))
         }
     }
-    Ok(closed)
 }
 
-fn make_lambda(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let (pairs, unextracted_body) = match expr {
-        Expr::Pair(pairs @ box Expr::Nil, body @ box Expr::Pair(..), ..) => (pairs, body),
-        Expr::Pair(pairs @ box Expr::Pair(..), body @ box Expr::Pair(..), ..) => (pairs, body),
-        otherwise => return comp_err!(expr, "Invalid lambda expression: {}", otherwise),
+fn make_begin(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let exprs = collect_exprs_from_body(expr)?;
+    if exprs.is_empty() {
+        return comp_err!(expr, "begin expects at least one expression");
+    }
+    compile_sequence(&exprs, chunk, env)
+}
+
+fn make_when_unless(
+    expr: &Expr,
+    chunk: &mut Chunk,
+    env: &mut Vec<String>,
+    form: &str,
+) -> CompileResult {
+    let (test, body) = match expr {
+        Expr::Pair(box test, box body @ Expr::Pair(..), ..) => (test, body),
+        otherwise => {
+            return comp_err!(
+                expr,
+                "{}, expected test and body but found: {}",
+                form,
+                otherwise
+            )
+        }
+    };
+    compile_internal(test, chunk, env)?;
+    let mut body_chunk = Chunk { code: vec![] };
+    compile_sequence(&collect_exprs_from_body(body)?, &mut body_chunk, env)?;
+    let nil_chunk = Chunk {
+        code: vec![VMInstruction::Constant(Expr::Nil)],
     };
+    if form == "when" {
+        push_branches(chunk, body_chunk, nil_chunk);
+    } else {
+        push_branches(chunk, nil_chunk, body_chunk);
+    }
+    Ok(())
+}
 
-    let body = collect_exprs_from_body(unextracted_body)?;
+fn make_when(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    make_when_unless(expr, chunk, env, "when")
+}
 
-    // all kws (including rest)
+fn make_unless(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    make_when_unless(expr, chunk, env, "unless")
+}
 
-    let all_kws = collect_kws_from_expr(pairs.borrow())?;
+// A clause of a case, the datums are None for the else clause. With an arrow
+// the body is the procedure to call with the key.
+struct CaseClause {
+    datums: Option<Vec<Expr>>,
+    body: Vec<Expr>,
+    arrow: bool,
+}
 
-    let dot_kw = all_kws
+fn collect_case_clauses(clauses: &Expr) -> Result<Vec<CaseClause>, CompileError> {
+    let clauses = collect_exprs_from_body(clauses)?;
+    clauses
         .iter()
         .enumerate()
-        .find(|(_, kw)| *kw == ".")
-        .map(|(index, _)| index);
+        .map(|(i, clause)| {
+            let (datums, body) = match clause {
+                Expr::Pair(box datums, box body @ Expr::Pair(..), ..) => (datums, body),
+                otherwise => {
+                    return comp_err!(
+                        clause,
+                        "case, expected (datums expr...) but found: {}",
+                        otherwise
+                    )
+                }
+            };
+            let datums = match datums {
+                Expr::Keyword(else_kw, ..) if else_kw == "else" => {
+                    if i + 1 != clauses.len() {
+                        return comp_err!(clause, "case, else has to be the last clause");
+                    }
+                    None
+                }
+                datums => Some(collect_exprs_from_body(datums)?),
+            };
+            let body = collect_exprs_from_body(body)?;
+            match body.as_slice() {
+                [Expr::Keyword(arrow, ..), proc] if arrow == "=>" => Ok(CaseClause {
+                    datums,
+                    body: vec![proc.clone()],
+                    arrow: true,
+                }),
+                [Expr::Keyword(arrow, ..), ..] if arrow == "=>" => comp_err!(
+                    clause,
+                    "case, expected one expression after => but found: {}",
+                    clause
+                ),
+                _ => Ok(CaseClause {
+                    datums,
+                    body,
+                    arrow: false,
+                }),
+            }
+        })
+        .collect()
+}
 
-    if let Some(dot_index) = dot_kw {
-        // only valid if it's the second to last argument
-        if dot_index + 2 != all_kws.len() {
+// (case key ((datum...) expr...)... (else expr...)), the key stays on the
+// stack while it is compared with the datums of each clause.
+fn make_case(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let (key, clauses) = match expr {
+        Expr::Pair(box key, box clauses @ Expr::Pair(..), ..) => (key, clauses),
+        otherwise => {
             return comp_err!(
                 expr,
-                "rest-dot can only occur as second-to-last argument, but found: ({})",
-                all_kws.join(" ")
-            );
+                "case, expected key and clauses but found: {}",
+                otherwise
+            )
         }
     };
+    let clauses = collect_case_clauses(clauses)?;
+    compile_internal(key, chunk, env)?;
+    let clauses_chunk = compile_case_clauses(&clauses, env)?;
+    chunk.code.extend(clauses_chunk.code);
+    Ok(())
+}
 
-    let rest_arg = dot_kw.and_then(|index| all_kws.get(index + 1));
-    let (kws, _) = all_kws.split_at(dot_kw.unwrap_or(all_kws.len()));
-
-    let mut new_body_chunk = Chunk { code: vec![] };
-    let locals = get_all_defines(&body);
-
-    // find closing over variables
-    let mut lambda_env = {
-        let mut lambda_env = env.clone();
-        lambda_env.append(&mut kws.to_vec());
-        if let Some(rest_arg) = rest_arg {
-            lambda_env.push(rest_arg.clone());
+fn compile_case_clauses(
+    clauses: &[CaseClause],
+    env: &mut Vec<String>,
+) -> Result<Chunk, CompileError> {
+    let (clause, rest) = match clauses.split_first() {
+        Some(split) => split,
+        // no clause matched the key
+        None => {
+            return Ok(Chunk {
+                code: vec![VMInstruction::PopStack, VMInstruction::Constant(Expr::Nil)],
+            })
         }
-        lambda_env.append(&mut locals.clone());
-        lambda_env
     };
-    let closed_variables = find_closed_variables(&body.clone(), env, &lambda_env)?;
-    compile_many_exprs(body.clone(), &mut new_body_chunk, &mut lambda_env)?;
+    let mut consequent = Chunk { code: vec![] };
+    if clause.arrow {
+        compile_sequence(&clause.body, &mut consequent, env)?;
+        consequent.code.push(VMInstruction::Swap);
+        consequent.code.push(VMInstruction::Call(1));
+    } else {
+        consequent.code.push(VMInstruction::PopStack);
+        compile_sequence(&clause.body, &mut consequent, env)?;
+    }
+    match &clause.datums {
+        None => Ok(consequent),
+        Some(datums) => {
+            let alternate = compile_case_clauses(rest, env)?;
+            let mut chunk = Chunk {
+                code: vec![VMInstruction::Eqv(datums.clone())],
+            };
+            push_branches(&mut chunk, consequent, alternate);
+            Ok(chunk)
+        }
+    }
+}
 
-    chunk.code.push(VMInstruction::MakeLambda(
-        new_body_chunk,
-        rest_arg.cloned(),
-        kws.to_vec(),
-        locals,
-        closed_variables,
-    ));
-    Ok(())
+// The parts of (do ((var init step)...) (test expr...) command...)
+struct DoLoop {
+    vars: Vec<(String, Expr, Option<Expr>)>,
+    test: Expr,
+    results: Vec<Expr>,
+    commands: Vec<Expr>,
 }
 
-fn make_define(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let kw = match expr {
-        Expr::Pair(
-            box Expr::Pair(box Expr::Keyword(fn_name, ..), fn_args, ..),
-            body @ box Expr::Pair(..),
-            src_loc,
-        ) => {
-            // this is a lambda definition
-            // kws should contain a fn name and then its args
-            make_lambda(
-                &Expr::Pair(fn_args.clone(), body.clone(), src_loc.clone()),
-                chunk,
-                env,
-            )?;
-            Ok(fn_name.clone())
-        }
-        Expr::Pair(
-            box Expr::Keyword(kw, ..),
-            box Expr::Pair(box definee, box Expr::Nil, ..),
-            ..,
-        ) => {
-            compile_internal(definee, chunk, env)?;
-            Ok(kw.clone())
+fn destructure_do(expr: &Expr) -> Result<DoLoop, CompileError> {
+    let (specs, test_clause, commands) = match expr {
+        Expr::Pair(box specs, box Expr::Pair(box test_clause, box commands, ..), ..) => {
+            (specs, test_clause, commands)
         }
         otherwise => {
             return comp_err!(
                 expr,
-                "definition, expected kw and expr but found: {}",
+                "do, expected variables, test and body but found: {}",
                 otherwise
             )
         }
-    }?;
-
-    chunk.code.push(VMInstruction::Define(kw.clone()));
-    chunk.code.push(VMInstruction::Constant(Expr::Nil));
-    Ok(())
-}
-
-fn make_if(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let (pred, consequent, alternate) = match expr {
-        Expr::Pair(
-            box pred,
-            box Expr::Pair(box consequent, box Expr::Pair(box alternate, box Expr::Nil, ..), ..),
-            ..,
-        ) => (pred, consequent, alternate),
-        otherwise => {
+    };
+    let vars = collect_exprs_from_body(specs)?
+        .iter()
+        .map(|spec| match collect_exprs_from_body(spec)?.as_slice() {
+            [Expr::Keyword(var, ..), init] => Ok((var.clone(), init.clone(), None)),
+            [Expr::Keyword(var, ..), init, step] => {
+                Ok((var.clone(), init.clone(), Some(step.clone())))
+            }
+            _ => comp_err!(spec, "do, expected (var init step) but found: {}", spec),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (test, results) = match collect_exprs_from_body(test_clause)?.split_first() {
+        Some((test, results)) => (test.clone(), results.to_vec()),
+        None => {
             return comp_err!(
-                expr,
-                "if, expected pred, cons, alt but found: {}",
-                otherwise
+                test_clause,
+                "do, expected (test expr...) but found: {}",
+                test_clause
             )
         }
     };
+    Ok(DoLoop {
+        vars,
+        test,
+        results,
+        commands: collect_exprs_from_body(commands)?,
+    })
+}
 
-    let mut pred_chunk = Chunk { code: vec![] };
-    compile_internal(pred, &mut pred_chunk, env)?;
-
-    let mut alt_chunk = Chunk { code: vec![] };
-    compile_internal(alternate, &mut alt_chunk, env)?;
-
-    let mut cons_chunk = Chunk { code: vec![] };
-    compile_internal(consequent, &mut cons_chunk, env)?;
-
-    let end_ip = cons_chunk.code.len();
-
-    let cons_ip = 1 + 1 + alt_chunk.code.len();
-
-    chunk.code.extend_from_slice(&pred_chunk.code);
+// Loops like a named let, the variables get fresh bindings every iteration.
+fn make_do(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let DoLoop {
+        vars,
+        test,
+        results,
+        commands,
+    } = destructure_do(expr)?;
+    for (_, init, _) in vars.iter() {
+        compile_internal(init, chunk, env)?;
+    }
+    chunk
+        .code
+        .push(VMInstruction::EnterScope(Some("do".to_string())));
+    let outer_env_len = env.len();
+    for (var, _, _) in vars.iter().rev() {
+        chunk.code.push(VMInstruction::Bind(var.clone()));
+        env.push(var.clone());
+    }
+    compile_internal(&test, chunk, env)?;
 
-    chunk.code.push(VMInstruction::CondJumpPop(cons_ip));
+    let mut results_chunk = Chunk { code: vec![] };
+    if results.is_empty() {
+        results_chunk.code.push(VMInstruction::Constant(Expr::Nil));
+    } else {
+        compile_sequence(&results, &mut results_chunk, env)?;
+    }
 
-    chunk.code.extend_from_slice(&alt_chunk.code);
+    let mut loop_chunk = Chunk { code: vec![] };
+    for command in commands.iter() {
+        compile_internal(command, &mut loop_chunk, env)?;
+        loop_chunk.code.push(VMInstruction::PopStack);
+    }
+    for (var, _, step) in vars.iter() {
+        match step {
+            Some(step) => compile_internal(step, &mut loop_chunk, env)?,
+            None => loop_chunk.code.push(VMInstruction::Lookup(var.clone())),
+        }
+    }
+    loop_chunk.code.push(VMInstruction::Recur("do".to_string()));
 
-    chunk.code.push(VMInstruction::Constant(Expr::bool(true)));
-    chunk.code.push(VMInstruction::CondJumpPop(end_ip));
-    chunk.code.extend_from_slice(&cons_chunk.code);
+    push_branches(chunk, results_chunk, loop_chunk);
+    chunk.code.push(VMInstruction::ExitScope);
+    env.truncate(outer_env_len);
     Ok(())
 }
 
-fn make_and(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let (l, r) = match expr {
-        Expr::Pair(box l, box Expr::Pair(box r, box Expr::Nil, ..), ..) => (l, r),
-        otherwise => return comp_err!(expr, "and, expected two args but found: {}", otherwise),
-    };
-    // l + popjmp(r) + jmp(return) + r + return
-    let mut r_chunk = Chunk { code: vec![] };
-    compile_internal(r, &mut r_chunk, env)?;
-    compile_internal(l, chunk, env)?;
-    chunk.code.push(VMInstruction::CondJump(2));
-    chunk.code.push(VMInstruction::Constant(Expr::bool(true)));
-    chunk
-        .code
-        .push(VMInstruction::CondJumpPop(1 + r_chunk.code.len()));
-    chunk.code.push(VMInstruction::PopStack);
-    chunk.code.extend_from_slice(&r_chunk.code);
-    Ok(())
+// A clause of a cond, the test is None for the else clause. With an arrow
+// the body is the procedure to call with the value of the test.
+struct CondClause {
+    test: Option<Expr>,
+    body: Vec<Expr>,
+    arrow: bool,
 }
 
-fn make_or(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let (l, r) = match expr {
-        Expr::Pair(box l, box Expr::Pair(box r, box Expr::Nil, ..), ..) => (l, r),
-        otherwise => return comp_err!(expr, "or, expected two args but found: {}", otherwise),
-    };
-    let mut r_chunk = Chunk { code: vec![] };
-    compile_internal(r, &mut r_chunk, env)?;
-    compile_internal(l, chunk, env)?;
-    chunk
-        .code
-        .push(VMInstruction::CondJump(1 + r_chunk.code.len()));
-    chunk.code.push(VMInstruction::PopStack);
-    chunk.code.extend_from_slice(&r_chunk.code);
+fn collect_cond_clauses(clauses: &Expr) -> Result<Vec<CondClause>, CompileError> {
+    let clauses = collect_exprs_from_body(clauses)?;
+    clauses
+        .iter()
+        .enumerate()
+        .map(|(i, clause)| {
+            let (test, body) = match collect_exprs_from_body(clause)?.split_first() {
+                Some((test, body)) => (test.clone(), body.to_vec()),
+                None => return comp_err!(clause, "cond, expected (test expr...) but found: ()"),
+            };
+            let test = match test {
+                Expr::Keyword(else_kw, ..) if else_kw == "else" => {
+                    if i + 1 != clauses.len() {
+                        return comp_err!(clause, "cond, else has to be the last clause");
+                    }
+                    if body.is_empty() {
+                        return comp_err!(clause, "cond, expected expressions after else");
+                    }
+                    None
+                }
+                test => Some(test),
+            };
+            match body.as_slice() {
+                [Expr::Keyword(arrow, ..), proc] if arrow == "=>" => Ok(CondClause {
+                    test,
+                    body: vec![proc.clone()],
+                    arrow: true,
+                }),
+                [Expr::Keyword(arrow, ..), ..] if arrow == "=>" => comp_err!(
+                    clause,
+                    "cond, expected one expression after => but found: {}",
+                    clause
+                ),
+                _ => Ok(CondClause {
+                    test,
+                    body,
+                    arrow: false,
+                }),
+            }
+        })
+        .collect()
+}
+
+fn make_cond(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    let cond_chunk = compile_cond_clauses(&collect_cond_clauses(expr)?, env)?;
+    chunk.code.extend(cond_chunk.code);
     Ok(())
 }
 
-fn make_quote(expr: &Expr, chunk: &mut Chunk, _env: &mut Vec<String>) -> CompileResult {
-    let exprs = collect_exprs_from_body(expr)?;
-    if let (Some(arg), 1) = (exprs.first(), exprs.len()) {
-        chunk.code.push(VMInstruction::Constant(arg.clone()))
+fn compile_cond_clauses(
+    clauses: &[CondClause],
+    env: &mut Vec<String>,
+) -> Result<Chunk, CompileError> {
+    let mut chunk = Chunk { code: vec![] };
+    let (clause, rest) = match clauses.split_first() {
+        Some(split) => split,
+        // no test was truthy
+        None => {
+            chunk.code.push(VMInstruction::Constant(Expr::Nil));
+            return Ok(chunk);
+        }
+    };
+    let test = match &clause.test {
+        Some(test) => test,
+        None => {
+            compile_sequence(&clause.body, &mut chunk, env)?;
+            return Ok(chunk);
+        }
+    };
+    compile_internal(test, &mut chunk, env)?;
+    let alternate = compile_cond_clauses(rest, env)?;
+    if clause.body.is_empty() {
+        // the value of the test is the value of the cond
+        chunk
+            .code
+            .push(VMInstruction::CondJump(1 + alternate.code.len()));
+        chunk.code.push(VMInstruction::PopStack);
+        chunk.code.extend(alternate.code);
+    } else if clause.arrow {
+        let mut consequent = Chunk { code: vec![] };
+        compile_sequence(&clause.body, &mut consequent, env)?;
+        consequent.code.push(VMInstruction::Swap);
+        consequent.code.push(VMInstruction::Call(1));
+        // keeps the value of the test to call the procedure with
+        chunk
+            .code
+            .push(VMInstruction::CondJump(1 + alternate.code.len() + 2));
+        chunk.code.push(VMInstruction::PopStack);
+        chunk.code.extend(alternate.code);
+        chunk.code.push(VMInstruction::Constant(Expr::bool(true)));
+        chunk
+            .code
+            .push(VMInstruction::CondJumpPop(consequent.code.len()));
+        chunk.code.extend(consequent.code);
     } else {
-        return comp_err!(expr, "quote expects 1 arg, but found: {:#?}", exprs);
+        let mut consequent = Chunk { code: vec![] };
+        compile_sequence(&clause.body, &mut consequent, env)?;
+        push_branches(&mut chunk, consequent, alternate);
     }
-    Ok(())
+    Ok(chunk)
 }
-fn make_display(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+
+// Returns the expr inside (form expr), used for quasiquote and the unquotes.
+pub fn unwrap_quote_like<'a>(expr: &'a Expr, form: &str) -> Option<&'a Expr> {
     match expr {
-        Expr::Pair(box displayee, box Expr::Nil, ..) => {
-            compile_internal(displayee, chunk, env)?;
-            chunk.code.push(VMInstruction::Display);
-            Ok(())
+        Expr::Pair(box Expr::Keyword(kw, ..), box Expr::Pair(box inner, box Expr::Nil, ..), ..)
+            if kw == form =>
+        {
+            Some(inner)
         }
-        otherwise => {
-            comp_err!(
-                expr,
-                "Expected one argument for display, but found {}",
-                otherwise
-            )
+        _ => None,
+    }
+}
+
+// Collects the exprs unquoted at the given depth of a quasiquote template,
+// these are the only parts of it that get evaluated.
+pub fn collect_unquoted(template: &Expr, depth: usize, unquoted: &mut Vec<Expr>) {
+    if let Some(inner) = unwrap_quote_like(template, "quasiquote") {
+        return collect_unquoted(inner, depth + 1, unquoted);
+    }
+    for form in ["unquote", "unquote-splicing"] {
+        if let Some(inner) = unwrap_quote_like(template, form) {
+            if depth == 1 {
+                unquoted.push(inner.clone());
+            } else {
+                collect_unquoted(inner, depth - 1, unquoted);
+            }
+            return;
+        }
+    }
+    match template {
+        Expr::Pair(box car, box cdr, ..) => {
+            collect_unquoted(car, depth, unquoted);
+            collect_unquoted(cdr, depth, unquoted);
         }
+        Expr::Vector(items) => RefCell::borrow(items)
+            .iter()
+            .for_each(|item| collect_unquoted(item, depth, unquoted)),
+        _ => {}
     }
 }
-fn make_apply(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
-    let exprs = collect_exprs_from_body(expr)?;
-    if let (Some(function), Some(args), 2) = (exprs.first(), exprs.get(1), exprs.len()) {
-        compile_internal(function, chunk, env)?;
-        compile_internal(args, chunk, env)?;
-        chunk.code.push(VMInstruction::Apply);
-        chunk.code.push(VMInstruction::Call(0));
-    } else {
-        return comp_err!(expr, "apply expects 2 args, but found: {}", exprs.len());
+
+fn make_quasiquote(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    match expr {
+        Expr::Pair(box template, box Expr::Nil, ..) => compile_quasiquote(template, 1, chunk, env),
+        otherwise => comp_err!(expr, "quasiquote expects 1 arg, but found: {}", otherwise),
+    }
+}
+
+// Builds the template with calls to the cons and append builtins, the parts
+// without unquotes are constants.
+fn compile_quasiquote(
+    template: &Expr,
+    depth: usize,
+    chunk: &mut Chunk,
+    env: &mut Vec<String>,
+) -> CompileResult {
+    let mut unquoted = vec![];
+    collect_unquoted(template, depth, &mut unquoted);
+    if unquoted.is_empty() {
+        chunk.code.push(literal(template));
+        return Ok(());
+    }
+    let builtin = |name: &str| VMInstruction::Constant(Expr::Keyword(name.to_string(), None));
+    // nested quasiquotes and unquotes stay in the result as lists
+    let nested = [
+        ("quasiquote", depth + 1),
+        ("unquote", depth - 1),
+        ("unquote-splicing", depth - 1),
+    ];
+    for (form, inner_depth) in nested {
+        if let Some(inner) = unwrap_quote_like(template, form) {
+            match form {
+                "unquote" if depth == 1 => return compile_internal(inner, chunk, env),
+                "unquote-splicing" if depth == 1 => {
+                    return comp_err!(
+                        template,
+                        "unquote-splicing has to be inside a list, but found: {}",
+                        template
+                    )
+                }
+                _ => {}
+            }
+            chunk.code.push(builtin("cons"));
+            chunk.code.push(VMInstruction::Constant(Expr::Keyword(
+                form.to_string(),
+                None,
+            )));
+            chunk.code.push(builtin("cons"));
+            compile_quasiquote(inner, inner_depth, chunk, env)?;
+            chunk.code.push(VMInstruction::Constant(Expr::Nil));
+            chunk.code.push(VMInstruction::Call(2));
+            chunk.code.push(VMInstruction::Call(2));
+            return Ok(());
+        }
+    }
+    match template {
+        // (a . ,b)
+        Expr::Pair(box Expr::Keyword(dot, ..), box Expr::Pair(box tail, box Expr::Nil, ..), ..)
+            if dot == "." =>
+        {
+            compile_quasiquote(tail, depth, chunk, env)
+        }
+        Expr::Pair(box car, box cdr, ..) => {
+            match unwrap_quote_like(car, "unquote-splicing") {
+                Some(spliced) if depth == 1 => {
+                    chunk.code.push(builtin("append"));
+                    compile_internal(spliced, chunk, env)?;
+                }
+                _ => {
+                    chunk.code.push(builtin("cons"));
+                    compile_quasiquote(car, depth, chunk, env)?;
+                }
+            }
+            compile_quasiquote(cdr, depth, chunk, env)?;
+            chunk.code.push(VMInstruction::Call(2));
+            Ok(())
+        }
+        // #(a ,b) is built as a list first
+        Expr::Vector(items) => {
+            chunk.code.push(builtin("list->vector"));
+            compile_quasiquote(
+                &make_pair_from_vec(RefCell::borrow(items).clone()),
+                depth,
+                chunk,
+                env,
+            )?;
+            chunk.code.push(VMInstruction::Call(1));
+            Ok(())
+        }
+        otherwise => comp_err!(
+            otherwise,
+            "quasiquote, expected a template but found: {}",
+            otherwise
+        ),
     }
-    Ok(())
 }
 
 pub type CompileFn = fn(&Expr, &mut Chunk, env: &mut Vec<String>) -> CompileResult;
-pub type MacroFn = Arc<dyn Fn(Option<SrcLoc>, &Vec<Expr>) -> Result<Expr, CompileError>>;
+pub type MacroFn =
+    Rc<dyn Fn(Option<SrcLoc>, &Vec<Expr>, &mut SyntaxEnv) -> Result<Expr, CompileError>>;
 
 pub static SPECIAL_FORMS: Lazy<HashMap<String, CompileFn>> = Lazy::new(|| {
     let mut hm = HashMap::<String, CompileFn>::new();
@@ -586,12 +3446,50 @@ pub static SPECIAL_FORMS: Lazy<HashMap<String, CompileFn>> = Lazy::new(|| {
     hm.insert("and".to_string(), make_and);
     hm.insert("or".to_string(), make_or);
     hm.insert("quote".to_string(), make_quote);
+    hm.insert(GLOBAL_REF.to_string(), make_global_ref);
+    hm.insert("quasiquote".to_string(), make_quasiquote);
     hm.insert("apply".to_string(), make_apply);
     hm.insert("display".to_string(), make_display);
+    hm.insert("dynamic-wind".to_string(), make_dynamic_wind);
+    hm.insert("make-parameter".to_string(), make_make_parameter);
+    hm.insert("parameterize".to_string(), make_parameterize);
+    hm.insert("call-with-values".to_string(), make_call_with_values);
+    hm.insert("receive".to_string(), make_receive);
+    hm.insert("let-values".to_string(), make_let_values);
+    hm.insert("let*-values".to_string(), make_let_star_values);
+    hm.insert("define-values".to_string(), make_define_values);
+    hm.insert("delay".to_string(), make_delay);
+    hm.insert("delay-force".to_string(), make_delay_force);
+    hm.insert("force".to_string(), make_force);
+    hm.insert("cons-stream".to_string(), make_cons_stream);
+    hm.insert("let".to_string(), make_let);
+    hm.insert("let*".to_string(), make_let_star);
+    hm.insert("letrec".to_string(), make_letrec);
+    hm.insert("letrec*".to_string(), make_letrec);
+    hm.insert(NAMED_LET_RECUR.to_string(), make_named_let_recur);
+    hm.insert("begin".to_string(), make_begin);
+    hm.insert("progn".to_string(), make_begin);
+    hm.insert("when".to_string(), make_when);
+    hm.insert("unless".to_string(), make_unless);
+    hm.insert("case".to_string(), make_case);
+    hm.insert("cond".to_string(), make_cond);
+    hm.insert("do".to_string(), make_do);
     hm
 });
 
 pub fn compile_internal(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
+    // an error about something without a location of its own, like (), is
+    // reported at the closest form around it that has one
+    compile_expr(expr, chunk, env).map_err(|err| match err.srcloc {
+        None => CompileError {
+            srcloc: extract_srcloc(expr),
+            ..err
+        },
+        Some(_) => err,
+    })
+}
+
+fn compile_expr(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -> CompileResult {
     match &expr {
         expr @ Expr::Lambda(..) => {
             panic!("Cannot compile a {}", expr)
@@ -636,33 +3534,53 @@ pub fn compile_internal(expr: &Expr, chunk: &mut Chunk, env: &mut Vec<String>) -
             }
         }
         expr @ (Expr::String(..)
+        | Expr::Char(..)
+        | Expr::Parameter(..)
+        | Expr::Values(..)
+        | Expr::Promise(..)
+        | Expr::RecordType(..)
+        | Expr::Record(..)
+        | Expr::Vector(..)
+        | Expr::HashTable(..)
+        | Expr::Map(..)
+        | Expr::Set(..)
         | Expr::Num(..)
         | Expr::Boolean(..)
-        | Expr::Quote(..)
         | Expr::Nil) => {
-            chunk.code.push(VMInstruction::Constant((*expr).clone()));
+            chunk.code.push(literal(expr));
         }
     };
     Ok(())
 }
 
-fn get_kw_from_define(expr: &Expr) -> Option<String> {
+fn get_kws_from_define(expr: &Expr) -> Vec<String> {
     match expr {
         Expr::Pair(
             box Expr::Keyword(define_kw, ..),
             box Expr::Pair(box Expr::Pair(box Expr::Keyword(kw, ..), ..), ..),
             ..,
-        ) if define_kw == "define" => Some(kw.clone()),
+        ) if define_kw == "define" => vec![kw.clone()],
         Expr::Pair(
             box Expr::Keyword(define_kw, ..),
             box Expr::Pair(box Expr::Keyword(kw, ..), ..),
             ..,
-        ) if define_kw == "define" => Some(kw.clone()),
-        _ => None,
+        ) if define_kw == "define" => vec![kw.clone()],
+        Expr::Pair(box Expr::Keyword(define_kw, ..), box Expr::Pair(box formals, ..), ..)
+            if define_kw == "define-values" =>
+        {
+            collect_formals(formals)
+                .map(|(required, rest)| required.into_iter().chain(rest).collect())
+                .unwrap_or_default()
+        }
+        // definitions in a begin belong to the surrounding body
+        Expr::Pair(box Expr::Keyword(begin_kw, ..), box exprs, ..) if begin_kw == "begin" => {
+            get_all_defines(&collect_exprs_from_body(exprs).unwrap_or_default())
+        }
+        _ => vec![],
     }
 }
-pub fn get_all_defines(exprs: &Vec<Expr>) -> Vec<String> {
-    exprs.iter().filter_map(get_kw_from_define).collect()
+pub fn get_all_defines(exprs: &[Expr]) -> Vec<String> {
+    exprs.iter().flat_map(get_kws_from_define).collect()
 }
 
 pub fn compile_many_exprs(
@@ -673,7 +3591,7 @@ pub fn compile_many_exprs(
     let mut defines = get_all_defines(&exprs);
     env.append(&mut defines);
 
-    return exprs.iter().enumerate().try_fold((), |_, (i, expr)| {
+    exprs.iter().enumerate().try_fold((), |_, (i, expr)| {
         match compile_internal(expr, chunk, env) {
             Ok(_) => {}
             Err(e) => return Err(e),
@@ -685,5 +3603,5 @@ pub fn compile_many_exprs(
             chunk.code.push(VMInstruction::PopStack);
             Ok(())
         }
-    });
+    })
 }
@@ -1,13 +1,19 @@
+use crate::hash_table::HashTable;
+use crate::number::Number;
 use crate::parse::SrcLoc;
+use crate::persistent::MapKey;
 use crate::vm::Chunk;
 use crate::vm::HeapAddr;
 use core::fmt::Debug;
 use core::fmt::Display;
+use im_rc::{OrdMap, OrdSet};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Clone, Debug)]
 pub struct Num {
-    pub value: f64,
+    pub value: Number,
     pub srcloc: Option<SrcLoc>,
 }
 
@@ -17,6 +23,28 @@ pub struct Bool {
     pub srcloc: Option<SrcLoc>,
 }
 
+#[derive(Clone, Debug)]
+pub enum Promise {
+    Delayed(Expr /* thunk */),
+    DelayForce(Expr /* thunk returning a promise */),
+    Forced(Expr),
+    // a forced delay-force is the promise its thunk returned
+    Shared(Rc<RefCell<Promise>>),
+}
+
+// Made by define-record-type, each one is a distinct type.
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub fields: RefCell<Vec<Expr>>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Expr {
     Pair(Box<Expr>, Box<Expr>, Option<SrcLoc>),
@@ -24,7 +52,7 @@ pub enum Expr {
     Keyword(String, Option<SrcLoc>),
     Boolean(Bool),
     String(String, Option<SrcLoc>),
-    Quote(Box<Expr>, Option<SrcLoc>),
+    Char(char, Option<SrcLoc>),
     Lambda(
         Chunk,
         Vec<String>,
@@ -32,16 +60,38 @@ pub enum Expr {
         Option<String>,            /* variadic */
         HashMap<String, HeapAddr>, /* closed variables */
     ),
+    Parameter(HeapAddr, Option<Box<Expr>> /* converter */),
+    Values(Vec<Expr>),
+    Promise(Rc<RefCell<Promise>>),
+    RecordType(Rc<RecordType>),
+    Record(Rc<Record>),
+    Vector(Rc<RefCell<Vec<Expr>>>),
+    HashTable(Rc<HashTable>),
+    Map(OrdMap<MapKey, Expr>),
+    Set(OrdSet<MapKey>),
     Nil,
 }
 
 impl Expr {
+    #[allow(dead_code)]
     pub fn num(value: f64) -> Self {
+        Self::Num(Num {
+            value: Number::Real(value),
+            srcloc: None,
+        })
+    }
+    pub fn number(value: Number) -> Self {
         Self::Num(Num {
             value,
             srcloc: None,
         })
     }
+    pub fn int(value: i64) -> Self {
+        Self::Num(Num {
+            value: Number::Fixnum(value),
+            srcloc: None,
+        })
+    }
     pub fn bool(value: bool) -> Self {
         Self::Boolean(Bool {
             value,
@@ -55,44 +105,119 @@ impl Display for Expr {
         &self,
         formatter: &mut std::fmt::Formatter<'_>,
     ) -> std::result::Result<(), std::fmt::Error> {
-        match self {
-            Expr::Nil => write!(formatter, "'()"),
-            Expr::Pair(x, box Expr::Nil, ..) => write!(formatter, "({x})"),
-            Expr::Pair(x, r @ box Expr::Pair(..), ..) => {
-                let mut r_string = format!("{r}");
-                // remove parens for niceness
-                r_string.pop();
-                r_string.remove(0);
-                write!(formatter, "({x} {r_string})")
-            }
-            Expr::Pair(x, y, ..) => write!(formatter, "({x} . {y})"),
-            Expr::Num(Num { value: x, .. }) => {
-                let mut string_value = format!("{}", x);
-                if string_value.ends_with(".0") {
-                    string_value.pop();
-                    string_value.pop();
-                    write!(formatter, "{}", string_value)
-                } else {
-                    write!(formatter, "{}", x)
+        write_expr(self, formatter, &mut vec![])
+    }
+}
+
+// Where a shared value lives, to recognise it when walking into it again.
+pub fn address<T>(rc: &Rc<T>) -> *const () {
+    Rc::as_ptr(rc) as *const ()
+}
+
+// Vectors and records can contain themselves through vector-set! and record
+// mutators. The ones being printed are kept in visiting, and print as
+// #<cycle> when they come up again inside themselves.
+fn write_expr(
+    expr: &Expr,
+    formatter: &mut std::fmt::Formatter<'_>,
+    visiting: &mut Vec<*const ()>,
+) -> std::result::Result<(), std::fmt::Error> {
+    match expr {
+        Expr::Nil => write!(formatter, "'()"),
+        Expr::Pair(..) => {
+            write!(formatter, "(")?;
+            let mut rest = expr;
+            while let Expr::Pair(x, y, ..) = rest {
+                if !std::ptr::eq(rest, expr) {
+                    write!(formatter, " ")?;
                 }
+                write_expr(x, formatter, visiting)?;
+                rest = y;
             }
-            Expr::Keyword(x, ..) => write!(formatter, "{x}"),
-            Expr::Boolean(Bool { value: x, .. }) => write!(formatter, "{x}"),
-            Expr::Quote(xs, _) => write!(formatter, "'{xs:?}"),
-            Expr::Lambda(_, args, locals, _, env) => {
-                write!(
-                    formatter,
-                    "Lambda(args: {args:?}, {locals:?}, env: {:?})",
-                    env
-                )
+            if !matches!(rest, Expr::Nil) {
+                write!(formatter, " . ")?;
+                write_expr(rest, formatter, visiting)?;
+            }
+            write!(formatter, ")")
+        }
+        Expr::Num(Num { value, .. }) => write!(formatter, "{value}"),
+        Expr::Keyword(x, ..) => write!(formatter, "{x}"),
+        Expr::Boolean(Bool { value: x, .. }) => write!(formatter, "{x}"),
+        Expr::Lambda(_, args, locals, _, env) => {
+            write!(
+                formatter,
+                "Lambda(args: {args:?}, {locals:?}, env: {:?})",
+                env
+            )
+        }
+        Expr::String(s, _) => {
+            write!(formatter, "{s}")
+        }
+        Expr::Char(c, _) => write!(formatter, "{c}"),
+        Expr::Parameter(..) => write!(formatter, "#<parameter>"),
+        Expr::Promise(..) => write!(formatter, "#<promise>"),
+        Expr::HashTable(..) => write!(formatter, "#<hash-table>"),
+        Expr::RecordType(record_type) => {
+            write!(formatter, "#<record-type {}>", record_type.name)
+        }
+        Expr::Record(record) if visiting.contains(&address(record)) => {
+            write!(formatter, "#<cycle>")
+        }
+        Expr::Record(record) => {
+            visiting.push(address(record));
+            write!(formatter, "#<{}", record.record_type.name)?;
+            let fields = record.fields.borrow();
+            for (name, value) in record.record_type.fields.iter().zip(fields.iter()) {
+                write!(formatter, " {name}: ")?;
+                write_expr(value, formatter, visiting)?;
             }
-            Expr::String(s, _) => {
-                write!(formatter, "{s}")
+            visiting.pop();
+            write!(formatter, ">")
+        }
+        Expr::Vector(items) if visiting.contains(&address(items)) => {
+            write!(formatter, "#<cycle>")
+        }
+        Expr::Vector(items) => {
+            visiting.push(address(items));
+            write!(formatter, "#(")?;
+            write_separated(items.borrow().iter(), formatter, visiting)?;
+            visiting.pop();
+            write!(formatter, ")")
+        }
+        Expr::Map(map) => {
+            write!(formatter, "{{")?;
+            for (index, (MapKey(key), value)) in map.iter().enumerate() {
+                if index > 0 {
+                    write!(formatter, " ")?;
+                }
+                write!(formatter, "{key} ")?;
+                write_expr(value, formatter, visiting)?;
             }
+            write!(formatter, "}}")
+        }
+        Expr::Set(set) => {
+            write!(formatter, "#{{")?;
+            write_separated(set.iter().map(|MapKey(item)| item), formatter, visiting)?;
+            write!(formatter, "}}")
         }
+        Expr::Values(values) => write_separated(values.iter(), formatter, visiting),
     }
 }
 
+fn write_separated<'a>(
+    exprs: impl Iterator<Item = &'a Expr>,
+    formatter: &mut std::fmt::Formatter<'_>,
+    visiting: &mut Vec<*const ()>,
+) -> std::result::Result<(), std::fmt::Error> {
+    for (index, expr) in exprs.enumerate() {
+        if index > 0 {
+            write!(formatter, " ")?;
+        }
+        write_expr(expr, formatter, visiting)?;
+    }
+    Ok(())
+}
+
 #[test]
 fn test_display() {
     let bool_expr = Expr::bool(true);
@@ -106,7 +231,7 @@ fn test_display() {
 
     let list_with_values = crate::parse::make_pair_from_vec(vec![
         Expr::bool(false),
-        Expr::num(5.0),
+        Expr::int(5),
         crate::parse::make_pair_from_vec(vec![Expr::Keyword("hello".to_string(), None)]),
     ]);
     assert_eq!(format!("{list_with_values}"), "(false 5 (hello))");
@@ -114,22 +239,63 @@ fn test_display() {
 
 impl PartialEq for Expr {
     fn eq(&self, rhs: &Expr) -> bool {
-        match (self, rhs) {
-            (Expr::Pair(ax, ay, ..), Expr::Pair(bx, by, ..)) => ax == bx && ay == by,
-            (Expr::Num(Num { value: l, .. }), Expr::Num(Num { value: r, .. })) if l == r => true,
-            (Expr::String(l, _), Expr::String(r, _)) if l == r => true,
-            (Expr::Keyword(l, ..), Expr::Keyword(r, ..)) if l == r => true,
-            (Expr::Boolean(Bool { value: l, .. }), Expr::Boolean(Bool { value: r, .. }))
-                if l == r =>
-            {
-                true
-            }
-            (Expr::Nil, Expr::Nil) => true,
-            (
-                Expr::Lambda(c1, s1, locals1, variadic1, d1),
-                Expr::Lambda(c2, s2, locals2, variadic2, d2),
-            ) => c1 == c2 && s1 == s2 && d1 == d2 && variadic1 == variadic2 && locals1 == locals2,
-            _ => false,
+        eq_expr(self, rhs, &mut vec![])
+    }
+}
+
+// The vectors and records being compared are kept in visiting, meeting the
+// same two again means they contain themselves the same way.
+fn eq_expr(l: &Expr, r: &Expr, visiting: &mut Vec<(*const (), *const ())>) -> bool {
+    match (l, r) {
+        (Expr::Pair(ax, ay, ..), Expr::Pair(bx, by, ..)) => {
+            eq_expr(ax, bx, visiting) && eq_expr(ay, by, visiting)
+        }
+        (Expr::Num(Num { value: l, .. }), Expr::Num(Num { value: r, .. })) if l == r => true,
+        (Expr::String(l, _), Expr::String(r, _)) if l == r => true,
+        (Expr::Char(l, _), Expr::Char(r, _)) if l == r => true,
+        (Expr::Keyword(l, ..), Expr::Keyword(r, ..)) if l == r => true,
+        (Expr::Boolean(Bool { value: l, .. }), Expr::Boolean(Bool { value: r, .. })) if l == r => {
+            true
         }
+        (Expr::Nil, Expr::Nil) => true,
+        (
+            Expr::Lambda(c1, s1, locals1, variadic1, d1),
+            Expr::Lambda(c2, s2, locals2, variadic2, d2),
+        ) => c1 == c2 && s1 == s2 && d1 == d2 && variadic1 == variadic2 && locals1 == locals2,
+        (Expr::Parameter(l, ..), Expr::Parameter(r, ..)) => l == r,
+        (Expr::Values(l), Expr::Values(r)) => eq_exprs(l, r, visiting),
+        (Expr::Promise(l), Expr::Promise(r)) => Rc::ptr_eq(l, r),
+        (Expr::RecordType(l), Expr::RecordType(r)) => Rc::ptr_eq(l, r),
+        (Expr::Record(l), Expr::Record(r)) if Rc::ptr_eq(l, r) => true,
+        (Expr::Record(l), Expr::Record(r)) if visiting.contains(&(address(l), address(r))) => true,
+        // records of the same type are equal if their fields are
+        (Expr::Record(l), Expr::Record(r)) => {
+            visiting.push((address(l), address(r)));
+            let equal = Rc::ptr_eq(&l.record_type, &r.record_type)
+                && eq_exprs(&l.fields.borrow(), &r.fields.borrow(), visiting);
+            visiting.pop();
+            equal
+        }
+        (Expr::Vector(l), Expr::Vector(r)) if Rc::ptr_eq(l, r) => true,
+        (Expr::Vector(l), Expr::Vector(r)) if visiting.contains(&(address(l), address(r))) => true,
+        (Expr::Vector(l), Expr::Vector(r)) => {
+            visiting.push((address(l), address(r)));
+            let equal = eq_exprs(&l.borrow(), &r.borrow(), visiting);
+            visiting.pop();
+            equal
+        }
+        (Expr::HashTable(l), Expr::HashTable(r)) => Rc::ptr_eq(l, r),
+        (Expr::Map(l), Expr::Map(r)) => {
+            l.len() == r.len()
+                && l.iter()
+                    .zip(r.iter())
+                    .all(|((l_key, l), (r_key, r))| l_key == r_key && eq_expr(l, r, visiting))
+        }
+        (Expr::Set(l), Expr::Set(r)) => l == r,
+        _ => false,
     }
 }
+
+fn eq_exprs(l: &[Expr], r: &[Expr], visiting: &mut Vec<(*const (), *const ())>) -> bool {
+    l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| eq_expr(l, r, visiting))
+}
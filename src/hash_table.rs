@@ -0,0 +1,126 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    compile::{is_equal, is_eqv},
+    expr::{address, Bool, Expr, Num},
+};
+
+// Which procedure decides if two keys are the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Equivalence {
+    Eqv,
+    Equal,
+}
+
+// Keys are kept in buckets by their hash, so that keys the equivalence finds
+// the same hash the same. The buckets are ordered by hash, which keeps the
+// order of the keys the same from run to run.
+#[derive(Debug)]
+pub struct HashTable {
+    pub equivalence: Equivalence,
+    buckets: RefCell<BTreeMap<u64, Vec<(Expr, Expr)>>>,
+}
+
+impl HashTable {
+    pub fn new(equivalence: Equivalence) -> Self {
+        HashTable {
+            equivalence,
+            buckets: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn same(&self, l: &Expr, r: &Expr) -> bool {
+        match self.equivalence {
+            Equivalence::Eqv => is_eqv(l, r),
+            Equivalence::Equal => is_equal(l, r),
+        }
+    }
+
+    fn hash(&self, key: &Expr) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_expr(key, self.equivalence, &mut hasher, &mut vec![]);
+        hasher.finish()
+    }
+
+    pub fn get(&self, key: &Expr) -> Option<Expr> {
+        self.buckets
+            .borrow()
+            .get(&self.hash(key))?
+            .iter()
+            .find(|(k, _)| self.same(k, key))
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn insert(&self, key: Expr, value: Expr) {
+        let hash = self.hash(&key);
+        let mut buckets = self.buckets.borrow_mut();
+        let bucket = buckets.entry(hash).or_default();
+        match bucket.iter_mut().find(|(k, _)| self.same(k, &key)) {
+            Some(entry) => entry.1 = value,
+            None => bucket.push((key, value)),
+        }
+    }
+
+    pub fn remove(&self, key: &Expr) {
+        let hash = self.hash(key);
+        let mut buckets = self.buckets.borrow_mut();
+        if let Some(bucket) = buckets.get_mut(&hash) {
+            bucket.retain(|(k, _)| !self.same(k, key));
+            if bucket.is_empty() {
+                buckets.remove(&hash);
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(Expr, Expr)> {
+        self.buckets.borrow().values().flatten().cloned().collect()
+    }
+}
+
+// Procedures all hash the same and are told apart by the equivalence. eqv?
+// tables refuse pair keys, as pairs have no identity. Vectors can contain
+// themselves, the ones being hashed are kept in visiting and hash as a
+// marker when they come up again.
+fn hash_expr(
+    expr: &Expr,
+    equivalence: Equivalence,
+    state: &mut impl Hasher,
+    visiting: &mut Vec<*const ()>,
+) {
+    std::mem::discriminant(expr).hash(state);
+    match expr {
+        // numbers print the same if they are the same, except 0.0 and -0.0
+        Expr::Num(Num { value, .. }) if value.is_zero() => value.is_exact().hash(state),
+        Expr::Num(Num { value, .. }) => (value.is_exact(), value.to_string()).hash(state),
+        Expr::Keyword(name, ..) | Expr::String(name, ..) => name.hash(state),
+        Expr::Char(c, ..) => c.hash(state),
+        Expr::Boolean(Bool { value, .. }) => value.hash(state),
+        Expr::Record(record) => Rc::as_ptr(record).hash(state),
+        Expr::HashTable(table) => Rc::as_ptr(table).hash(state),
+        Expr::Promise(promise) => Rc::as_ptr(promise).hash(state),
+        Expr::Pair(car, cdr, ..) if equivalence == Equivalence::Equal => {
+            hash_expr(car, equivalence, state, visiting);
+            hash_expr(cdr, equivalence, state, visiting);
+        }
+        Expr::Vector(items) if equivalence == Equivalence::Equal => {
+            match visiting.contains(&address(items)) {
+                true => "#<cycle>".hash(state),
+                false => {
+                    visiting.push(address(items));
+                    items
+                        .borrow()
+                        .iter()
+                        .for_each(|item| hash_expr(item, equivalence, state, visiting));
+                    visiting.pop();
+                }
+            }
+        }
+        Expr::Vector(items) => Rc::as_ptr(items).hash(state),
+        _ => {}
+    }
+}
@@ -1,158 +1,328 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::comp_err;
 use crate::compile::{
-    collect_exprs_from_body, collect_kws_from_expr, compile_many_exprs, extract_srcloc,
-    get_all_defines, CompileError,
+    collect_exprs_from_body, collect_kws_from_expr, compile_many_exprs, extract_srcloc, fresh_name,
+    get_all_defines, CompileError, GLOBAL_REF,
 };
-use crate::parse::make_pair_from_vec;
-use crate::vm::{run, Callframe, VM};
+use crate::parse::{make_pair_from_vec, SrcLoc};
+use crate::pattern_match::expand_match;
+use crate::syntax_rules::{make_syntax_rules, INTRODUCED};
+use crate::vm::{run, Callframe, HeapAddr, VM};
 use crate::{
     compile::MacroFn,
-    expr::Expr,
+    expr::{Bool, Expr, Num, RecordType},
     vm::{Chunk, VMInstruction},
 };
 
-pub fn make_macro(params: &[String], macro_definition: &Expr) -> MacroFn {
-    let params: Vec<String> = params.into();
-    Arc::new({
-        let macro_definition = macro_definition.clone();
-        let all_kws = params.clone();
+// The body is compiled here, once, so an expansion only binds the arguments
+// and runs it.
+pub fn make_macro(
+    params: &[String],
+    macro_definition: &Expr,
+    syntax_env: &SyntaxEnv,
+) -> Result<MacroFn, CompileError> {
+    let dot_kw = params
+        .iter()
+        .enumerate()
+        .find(|(_, kw)| *kw == ".")
+        .map(|(index, _)| index);
 
-        move |srcloc, args| {
-            let dot_kw = all_kws
-                .iter()
-                .enumerate()
-                .find(|(_, kw)| *kw == ".")
-                .map(|(index, _)| index);
-
-            if let Some(dot_index) = dot_kw {
-                // only valid if it's the second to last argument
-                if dot_index + 2 != all_kws.len() {
-                    return comp_err!(
-                        &macro_definition,
-                        "rest-dot can only occur as second-to-last argument, but found: ({})",
-                        all_kws.join(" ")
-                    );
-                }
-            };
+    if let Some(dot_index) = dot_kw {
+        // only valid if it's the second to last argument
+        if dot_index + 2 != params.len() {
+            return comp_err!(
+                macro_definition,
+                "rest-dot can only occur as second-to-last argument, but found: ({})",
+                params.join(" ")
+            );
+        }
+    };
 
-            let variadic = dot_kw.and_then(|index| all_kws.get(index + 1));
-            let (vars, _) = all_kws.split_at(dot_kw.unwrap_or(all_kws.len()));
+    let variadic = dot_kw.and_then(|index| params.get(index + 1)).cloned();
+    let vars = params[..dot_kw.unwrap_or(params.len())].to_vec();
 
-            let is_variadic = variadic.is_some();
+    let macro_exprs = collect_exprs_from_body(macro_definition)?;
+    if macro_exprs.is_empty() {
+        return comp_err!(macro_definition, "defmacro, expected a body");
+    }
 
-            if is_variadic && args.len() < vars.len() {
-                return Err(CompileError {
-                    srcloc,
-                    message: format!(
-                        "wrong number of args, expected at least {} ({}), got: ({})",
-                        &macro_definition,
-                        vars.len(),
-                        vars.join(" "),
-                    ),
-                });
-            }
+    let mut chunk = Chunk { code: vec![] };
+    let mut macro_env = {
+        let mut macro_env = syntax_env.env.keys().cloned().collect::<Vec<String>>();
+        macro_env.append(&mut vars.clone());
+        if let Some(rest_arg) = &variadic {
+            macro_env.push(rest_arg.clone());
+        }
+        macro_env
+    };
+    compile_many_exprs(macro_exprs.clone(), &mut chunk, &mut macro_env)?;
+    chunk.code.push(VMInstruction::Return);
+    let defines = get_all_defines(&macro_exprs);
+    let macro_definition = macro_definition.clone();
 
-            if !is_variadic && args.len() != vars.len() {
-                return Err(CompileError {
-                    srcloc,
-                    message: format!(
-                        "macro wrong number of args, expected {} ({}), got: ({})",
-                        vars.len(),
-                        vars.join(" "),
-                        args.iter()
-                            .map(|x| format!("{x}"))
-                            .collect::<Vec<String>>()
-                            .join(" ")
-                    ),
-                });
-            }
+    Ok(Rc::new(move |srcloc, args, syntax_env| {
+        let arity_matches = match variadic {
+            Some(_) => args.len() >= vars.len(),
+            None => args.len() == vars.len(),
+        };
+        if !arity_matches {
+            return Err(CompileError {
+                srcloc,
+                message: format!(
+                    "macro wrong number of args, expected {}{} ({}), got: ({})",
+                    if variadic.is_some() { "at least " } else { "" },
+                    vars.len(),
+                    vars.join(" "),
+                    args.iter()
+                        .map(|x| format!("{x}"))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ),
+            });
+        }
 
-            let mut map = vars
-                .iter()
-                .cloned()
-                .zip(args.clone())
-                .collect::<HashMap<String, Expr>>();
+        let mut callframe_env = syntax_env.env.clone();
+        let mut vm = VM {
+            heap: std::mem::take(&mut syntax_env.heap),
+            ..Default::default()
+        };
 
-            variadic.inspect(|arg_name| {
-                let (_, pairs) = args.split_at(vars.len());
-                map.insert(arg_name.clone().clone(), make_pair_from_vec(pairs.to_vec()));
-            });
+        // add params and args in vm envs (unevaluated)
+        let (args, rest) = args.split_at(vars.len());
+        let rest = variadic
+            .iter()
+            .map(|rest_arg| (rest_arg.clone(), make_pair_from_vec(rest.to_vec())));
+        for (k, v) in vars.iter().cloned().zip(args.iter().cloned()).chain(rest) {
+            let new_key = vm.heap.len();
+            vm.heap.insert(new_key, v);
+            callframe_env.insert(k, new_key);
+        }
 
-            let mut vm = VM::default();
+        for k in defines.iter() {
+            let new_key = vm.heap.len();
+            vm.heap.insert(new_key, Expr::Nil);
+            callframe_env.insert(k.clone(), new_key);
+        }
 
-            let mut chunk = Chunk { code: vec![] };
+        vm.callframes.push(Callframe {
+            ip: 0,
+            chunk: chunk.clone(),
+            env: callframe_env,
+            scopes: vec![],
+        });
 
-            let macro_exprs = collect_exprs_from_body(&macro_definition)?;
-            let mut macro_env = {
-                let mut macro_env = vec![];
-                macro_env.append(&mut vars.to_vec());
-                if let Some(rest_arg) = variadic {
-                    macro_env.push(rest_arg.clone());
-                }
-                macro_env
-            };
-            compile_many_exprs(macro_exprs.clone(), &mut chunk, &mut macro_env)?;
-            chunk.code.push(VMInstruction::Return);
+        let result = run(&mut vm);
+        syntax_env.heap = vm.heap;
+        if let Err(err) = result {
+            return comp_err!(
+                &macro_definition,
+                "Error when running macro expansion: {err}"
+            );
+        };
 
-            let mut callframe_env = HashMap::new();
+        match vm.stack.first() {
+            Some(top) if vm.stack.len() == 1 => Ok(top.clone()),
+            _ => comp_err!(
+                &macro_definition,
+                "expected one value on the stack, got {:#?}",
+                vm.stack
+            ),
+        }
+    }))
+}
 
-            for (k, v) in map {
-                let new_key = vm.heap.len();
-                vm.heap.insert(new_key, v);
-                callframe_env.insert(k, new_key);
-            }
+// What macro bodies run with. Expanding is a phase of its own: macros see
+// what was loaded before the program (the prelude) and what the program
+// defines with define-for-syntax, but none of the program's own definitions,
+// which only exist once it runs. define-for-syntax definitions are in turn
+// not visible to the running program.
+#[derive(Clone, Debug, Default)]
+pub struct SyntaxEnv {
+    pub env: HashMap<String, HeapAddr>,
+    pub heap: HashMap<HeapAddr, Expr>,
+}
 
-            let defines = get_all_defines(&macro_exprs);
-            for k in defines {
-                let new_key = vm.heap.len();
-                vm.heap.insert(new_key, Expr::Nil);
-                callframe_env.insert(k, new_key);
-            }
+// (define-for-syntax name expr) or (define-for-syntax (name . args) body...)
+fn define_for_syntax(definition: &Expr, syntax_env: &mut SyntaxEnv) -> Result<(), CompileError> {
+    let define = match definition {
+        Expr::Pair(box Expr::Keyword(_, srcloc), rest, pair_srcloc) => Expr::Pair(
+            Box::new(Expr::Keyword("define".to_string(), srcloc.clone())),
+            rest.clone(),
+            pair_srcloc.clone(),
+        ),
+        otherwise => return comp_err!(otherwise, "define-for-syntax, found: {}", otherwise),
+    };
+    let mut chunk = Chunk { code: vec![] };
+    let mut env = syntax_env.env.keys().cloned().collect::<Vec<String>>();
+    compile_many_exprs(vec![define.clone()], &mut chunk, &mut env)?;
 
-            let callframe = Callframe {
-                ip: 0,
-                chunk,
-                env: callframe_env,
-            };
-            // add params and args in vm envs (unevaluated)
-            vm.callframes.push(callframe);
-
-            match run(&mut vm) {
-                Ok(e) => e,
-                Err(err) => {
-                    return comp_err!(
-                        &macro_definition,
-                        "Error when running macro expansion: {err}"
-                    );
-                }
-            };
+    for name in get_all_defines(&[define]) {
+        let addr = syntax_env.heap.len();
+        syntax_env.heap.insert(addr, Expr::Nil);
+        syntax_env.env.insert(name, addr);
+    }
+    let mut vm = VM {
+        heap: std::mem::take(&mut syntax_env.heap),
+        ..Default::default()
+    };
+    vm.callframes.push(Callframe {
+        ip: 0,
+        chunk,
+        env: syntax_env.env.clone(),
+        scopes: vec![],
+    });
+    let result = run(&mut vm);
+    syntax_env.heap = vm.heap;
+    result.map_err(|err| CompileError {
+        srcloc: extract_srcloc(definition),
+        message: format!("Error when running define-for-syntax: {err}"),
+    })
+}
 
-            match vm.stack.first() {
-                Some(top) if vm.stack.len() == 1 => Ok(top.clone()),
-                _ => comp_err!(
-                    &macro_definition,
-                    "expected one value on the stack, got {:#?}",
-                    vm.stack
-                ),
-            }
+// Threaded through an expansion, trace is only kept when asked for.
+#[derive(Clone, Debug, Default)]
+pub struct ExpansionContext {
+    pub syntax_env: SyntaxEnv,
+    pub trace: Option<Vec<ExpansionStep>>,
+    // locals that a syntax-rules macro defined in their scope may refer to
+    pub captured: HashSet<String>,
+    // a captured local a binding form shadows keeps its value in an alias,
+    // as (name, alias, length of the scope the binding form is in)
+    pub aliases: Vec<(String, String, usize)>,
+}
+
+// One macro call and what it expanded to, before that was expanded further.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpansionStep {
+    pub macro_name: String,
+    pub input: Expr,
+    pub output: Expr,
+    pub srcloc: Option<SrcLoc>,
+}
+
+struct MacroCall<'a> {
+    name: &'a str,
+    found_macro: &'a MacroFn,
+    args: &'a Vec<Expr>,
+    srcloc: &'a Option<SrcLoc>,
+    // where the macro's name is written
+    site: &'a Option<SrcLoc>,
+}
+
+fn expand_macro_call(
+    call: MacroCall,
+    macros: &HashMap<String, MacroFn>,
+    scope: &[String],
+    context: &mut ExpansionContext,
+) -> Result<Expr, CompileError> {
+    let expanded = (call.found_macro)(call.srcloc.clone(), call.args, &mut context.syntax_env)?;
+    let expanded = resolve_introduced(&expanded, scope, &context.aliases, macros, false);
+    let expanded = match call.site {
+        Some(site) => {
+            let mut from_args = HashSet::new();
+            call.args
+                .iter()
+                .for_each(|arg| collect_srclocs(arg, &mut from_args));
+            let expansion = Rc::new((call.name.to_string(), site.clone()));
+            locate_expansion(&expanded, &expansion, &from_args)
         }
-    })
+        None => expanded,
+    };
+    if let Some(trace) = &mut context.trace {
+        let name = Expr::Keyword(call.name.to_string(), call.srcloc.clone());
+        trace.push(ExpansionStep {
+            macro_name: call.name.to_string(),
+            input: make_pair_from_vec([vec![name], call.args.clone()].concat()),
+            output: expanded.clone(),
+            srcloc: call.srcloc.clone(),
+        });
+    }
+    Ok(expanded)
 }
 
+fn srcloc_mut(expr: &mut Expr) -> Option<&mut Option<SrcLoc>> {
+    match expr {
+        Expr::Pair(_, _, srcloc)
+        | Expr::Keyword(_, srcloc)
+        | Expr::String(_, srcloc)
+        | Expr::Char(_, srcloc)
+        | Expr::Num(Num { srcloc, .. })
+        | Expr::Boolean(Bool { srcloc, .. }) => Some(srcloc),
+        _ => None,
+    }
+}
+
+fn collect_srclocs(expr: &Expr, srclocs: &mut HashSet<SrcLoc>) {
+    if let Some(srcloc) = extract_srcloc(expr) {
+        srclocs.insert(srcloc);
+    }
+    if let Expr::Pair(box l, box r, _) = expr {
+        collect_srclocs(l, srclocs);
+        collect_srclocs(r, srclocs);
+    }
+}
+
+// What the macro was given keeps its location. Everything else it produced
+// is marked as coming from this expansion, and what the macro built without
+// a location (with cons, list, ...) is located where the macro was used.
+fn locate_expansion(
+    expr: &Expr,
+    expansion: &Rc<(String, SrcLoc)>,
+    from_args: &HashSet<SrcLoc>,
+) -> Expr {
+    let mut expr = match expr {
+        Expr::Pair(box l, box r, srcloc) => Expr::Pair(
+            Box::new(locate_expansion(l, expansion, from_args)),
+            Box::new(locate_expansion(r, expansion, from_args)),
+            srcloc.clone(),
+        ),
+        otherwise => otherwise.clone(),
+    };
+    if let Some(srcloc) = srcloc_mut(&mut expr) {
+        *srcloc = match srcloc.take() {
+            Some(loc) if from_args.contains(&loc) || loc.expanded_from.is_some() => Some(loc),
+            loc => Some(SrcLoc {
+                expanded_from: Some(expansion.clone()),
+                ..loc.unwrap_or_else(|| expansion.1.clone())
+            }),
+        };
+    }
+    expr
+}
+
+// scope holds the names bound around expr, a hygienic macro has to know
+// which of the names it introduces are shadowed where it is used.
 pub fn macro_expand_one(
     expr: &Expr,
     macros: &mut HashMap<String, MacroFn>,
+    scope: &[String],
+    context: &mut ExpansionContext,
 ) -> Result<Expr, CompileError> {
     let argmacros = macros.clone();
     match expr {
-        expr @ Expr::Quote(..) => Ok(expr.clone()),
         expr @ Expr::Pair(box Expr::Keyword(quote, ..), ..) if quote == "quote" => Ok(expr.clone()),
-        Expr::Pair(box Expr::Keyword(kw, ..), box r, srcloc)
+        Expr::Pair(
+            quasiquote @ box Expr::Keyword(kw, ..),
+            box Expr::Pair(box template, rest, s1),
+            s2,
+        ) if kw == "quasiquote" => Ok(Expr::Pair(
+            quasiquote.clone(),
+            Box::new(Expr::Pair(
+                Box::new(macro_expand_template(template, 1, macros, scope, context)?),
+                rest.clone(),
+                s1.clone(),
+            )),
+            s2.clone(),
+        )),
+        Expr::Pair(box Expr::Keyword(kw, site), box r, srcloc)
             if let Some(found_macro) = argmacros.get(kw) =>
         {
-            let expanded_body = macro_expand_one(r, macros)?;
+            let expanded_body = macro_expand_one(r, macros, scope, context)?;
             let args = collect_exprs_from_body(&expanded_body).map_err(|_| CompileError {
                 srcloc: extract_srcloc(expr),
                 message: format!(
@@ -160,81 +330,348 @@ pub fn macro_expand_one(
                     r
                 ),
             })?;
-            found_macro(srcloc.clone(), &args)
-        }
-
-        pair @ Expr::Pair(
-            box Expr::Keyword(macroexpand, ..),
-            box Expr::Pair(
-                box Expr::Pair(
-                    box Expr::Keyword(quote, ..),
-                    box Expr::Pair(
-                        box Expr::Pair(box Expr::Keyword(kw, ..), box r, _),
-                        box Expr::Nil,
-                        _,
-                    ),
-                    _,
-                ),
-                box Expr::Nil,
+            let call = MacroCall {
+                name: kw,
+                found_macro,
+                args: &args,
                 srcloc,
-            ),
-            _,
-        ) if let (Some(found_macro), "macroexpand", "quote") =
-            (argmacros.get(kw), macroexpand.as_str(), quote.as_str()) =>
-        {
-            let expanded_body = macro_expand_one(r, macros)?;
-            let args = collect_exprs_from_body(&expanded_body).map_err(|_| CompileError {
-                srcloc: extract_srcloc(pair),
-                message: format!(
-                    "Error when collecting kws for macro expansion, found: {}",
-                    r
-                ),
-            })?;
-            found_macro(srcloc.clone(), &args)
-                .map(|x| Expr::Quote(Box::new(x.clone()), srcloc.clone()))
+                site,
+            };
+            let expanded = expand_macro_call(call, macros, scope, context)?;
+            // what a macro expands to can itself use macros
+            macro_expand_one(&expanded, macros, scope, context)
         }
 
-        Expr::Pair(
-            box Expr::Keyword(macroexpand, ..),
-            box Expr::Pair(
-                box Expr::Pair(
-                    box Expr::Keyword(quote, ..),
+        Expr::Pair(box Expr::Keyword(macroexpand, ..), box rest, srcloc)
+            if ["macroexpand", "macroexpand-1", "macroexpand-all"]
+                .contains(&macroexpand.as_str()) =>
+        {
+            let form = match rest {
+                Expr::Pair(
                     box Expr::Pair(
-                        box Expr::Pair(box Expr::Keyword(kw, ..), ..),
-                        box Expr::Nil,
+                        box Expr::Keyword(quote, ..),
+                        box Expr::Pair(box form, box Expr::Nil, ..),
                         ..,
                     ),
+                    box Expr::Nil,
                     ..,
-                ),
-                box Expr::Nil,
-                _,
-            ),
-            _,
-        ) if let (None, "macroexpand", "quote") =
-            (argmacros.get(kw), macroexpand.as_str(), quote.as_str()) =>
-        {
-            comp_err!(expr, "macro not found: {kw}")
+                ) if quote == "quote" => form,
+                _ => return comp_err!(expr, "can't call {macroexpand} on {rest}"),
+            };
+            let expanded = match (macroexpand.as_str(), form) {
+                ("macroexpand-all", form) => macro_expand_one(form, macros, scope, context)?,
+                (_, Expr::Pair(box Expr::Keyword(kw, site), box r, form_srcloc))
+                    if let Some(found_macro) = argmacros.get(kw) =>
+                {
+                    // macroexpand expands the arguments first, macroexpand-1
+                    // takes one step only
+                    let r = match macroexpand.as_str() {
+                        "macroexpand" => macro_expand_one(r, macros, scope, context)?,
+                        _ => r.clone(),
+                    };
+                    let args = collect_exprs_from_body(&r).map_err(|_| CompileError {
+                        srcloc: extract_srcloc(expr),
+                        message: format!(
+                            "Error when collecting kws for macro expansion, found: {}",
+                            r
+                        ),
+                    })?;
+                    let call = MacroCall {
+                        name: kw,
+                        found_macro,
+                        args: &args,
+                        srcloc: form_srcloc,
+                        site,
+                    };
+                    expand_macro_call(call, macros, scope, context)?
+                }
+                ("macroexpand", Expr::Pair(box Expr::Keyword(kw, ..), ..)) => {
+                    return comp_err!(expr, "macro not found: {kw}")
+                }
+                ("macroexpand", _) => return comp_err!(expr, "can't call macroexpand on {rest}"),
+                // macroexpand-1 leaves what is no macro call as it is
+                (_, form) => form.clone(),
+            };
+            Ok(make_pair_from_vec(vec![
+                Expr::Keyword("quote".to_string(), srcloc.clone()),
+                expanded,
+            ]))
         }
-        Expr::Pair(box Expr::Keyword(macroexpand, ..), rest, ..)
-            if let "macroexpand" = (macroexpand.as_str()) =>
+        Expr::Pair(box Expr::Keyword(kw, ..), box Expr::Pair(box bindings, box body, ..), ..)
+            if kw == "let-syntax" || kw == "letrec-syntax" =>
         {
-            comp_err!(expr, "can't call macroexpand on {rest}")
+            expand_let_syntax(
+                expr,
+                bindings,
+                &collect_exprs_from_body(body)?,
+                macros,
+                scope,
+                context,
+            )
+        }
+        Expr::Pair(box Expr::Keyword(kw, ..), ..) if kw == "define-record-type" => {
+            macro_expand_one(&expand_define_record_type(expr)?, macros, scope, context)
+        }
+        Expr::Pair(box Expr::Keyword(kw, ..), ..) if kw == "match" => {
+            macro_expand_one(&expand_match(expr)?, macros, scope, context)
         }
         pair @ Expr::Pair(..) => {
-            let exprs = collect_exprs_from_body(pair)?;
-            let expanded_exprs = exprs
+            let bound = bound_names(pair);
+            let aliases = bound
+                .iter()
+                .filter(|name| scope.contains(name) && context.captured.contains(*name))
+                .collect::<HashSet<&String>>()
                 .into_iter()
-                .map(|expr| macro_expand_one(&expr, macros))
-                .collect::<Result<Vec<Expr>, CompileError>>()?;
-            Ok(make_pair_from_vec(expanded_exprs))
+                .map(|name| (name.clone(), fresh_name(name), scope.len()))
+                .collect::<Vec<(String, String, usize)>>();
+            context.aliases.extend(aliases.iter().cloned());
+            let scope = [scope, &bound].concat();
+            let expanded = collect_exprs_from_body(pair).and_then(|exprs| {
+                let (head, body) = exprs.split_at(body_start(&exprs).unwrap_or(exprs.len()));
+                let mut expanded_exprs = head
+                    .iter()
+                    .map(|expr| macro_expand_one(expr, macros, &scope, context))
+                    .collect::<Result<Vec<Expr>, CompileError>>()?;
+                expanded_exprs.extend(macro_expand_body(
+                    body,
+                    &mut macros.clone(),
+                    &scope,
+                    context,
+                    false,
+                )?);
+                Ok(make_pair_from_vec(expanded_exprs))
+            });
+            context
+                .aliases
+                .truncate(context.aliases.len() - aliases.len());
+            Ok(bind_aliases(expanded?, &aliases))
+        }
+        otherwise => Ok(otherwise.clone()),
+    }
+}
+
+// Only the parts of a quasiquote template unquoted at depth 1 are code.
+fn macro_expand_template(
+    template: &Expr,
+    depth: usize,
+    macros: &mut HashMap<String, MacroFn>,
+    scope: &[String],
+    context: &mut ExpansionContext,
+) -> Result<Expr, CompileError> {
+    match template {
+        Expr::Pair(form @ box Expr::Keyword(kw, ..), box Expr::Pair(box inner, rest, s1), s2)
+            if ["quasiquote", "unquote", "unquote-splicing"].contains(&kw.as_str()) =>
+        {
+            let inner = match kw.as_str() {
+                "quasiquote" => macro_expand_template(inner, depth + 1, macros, scope, context)?,
+                _ if depth == 1 => macro_expand_one(inner, macros, scope, context)?,
+                _ => macro_expand_template(inner, depth - 1, macros, scope, context)?,
+            };
+            Ok(Expr::Pair(
+                form.clone(),
+                Box::new(Expr::Pair(Box::new(inner), rest.clone(), s1.clone())),
+                s2.clone(),
+            ))
         }
+        Expr::Pair(box car, box cdr, srcloc) => Ok(Expr::Pair(
+            Box::new(macro_expand_template(car, depth, macros, scope, context)?),
+            Box::new(macro_expand_template(cdr, depth, macros, scope, context)?),
+            srcloc.clone(),
+        )),
+        Expr::Vector(items) => Ok(Expr::Vector(Rc::new(RefCell::new(
+            items
+                .borrow()
+                .iter()
+                .map(|item| macro_expand_template(item, depth, macros, scope, context))
+                .collect::<Result<Vec<Expr>, CompileError>>()?,
+        )))),
         otherwise => Ok(otherwise.clone()),
     }
 }
 
+fn formals_names(formals: &Expr) -> Vec<String> {
+    match formals {
+        Expr::Keyword(kw, ..) if kw != "." => vec![kw.clone()],
+        Expr::Pair(box l, box r, ..) => [formals_names(l), formals_names(r)].concat(),
+        _ => vec![],
+    }
+}
+
+// The names a binding form binds for its body, internal definitions
+// included. Inits are counted as in scope too, which only ever makes a
+// hygienic expansion more careful than it has to be.
+pub fn bound_names(form: &Expr) -> Vec<String> {
+    let (head, items) = match form {
+        Expr::Pair(box Expr::Keyword(head, ..), box items, ..) => (
+            head.as_str(),
+            collect_exprs_from_body(items).unwrap_or_default(),
+        ),
+        _ => return vec![],
+    };
+    let binding_names = |bindings: &Expr| {
+        collect_exprs_from_body(bindings)
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|binding| match binding {
+                Expr::Pair(box formals, ..) => formals_names(formals),
+                _ => vec![],
+            })
+            .collect::<Vec<String>>()
+    };
+    let names = match (head, items.as_slice()) {
+        ("lambda" | "receive", [formals, ..]) => formals_names(formals),
+        ("define", [Expr::Pair(_, box formals, ..), ..]) => formals_names(formals),
+        ("let" | "let*" | "letrec" | "letrec*", [Expr::Keyword(name, ..), bindings, ..]) => {
+            [vec![name.clone()], binding_names(bindings)].concat()
+        }
+        (
+            "let" | "let*" | "letrec" | "letrec*" | "do" | "let-values" | "let*-values",
+            [bindings, ..],
+        ) => binding_names(bindings),
+        _ => return vec![],
+    };
+    [names, get_all_defines(&items)].concat()
+}
+
+// The locals in scope a macro's templates mention, they can be shadowed where
+// the macro is used.
+fn capture_locals(spec: &Expr, scope: &[String], context: &mut ExpansionContext) {
+    match spec {
+        Expr::Keyword(kw, ..) if scope.contains(kw) => {
+            context.captured.insert(kw.clone());
+        }
+        Expr::Pair(box l, box r, ..) => {
+            capture_locals(l, scope, context);
+            capture_locals(r, scope, context);
+        }
+        _ => {}
+    }
+}
+
+// Binds the aliases around the binding form that shadows their names:
+// (let ((alias name) ...) form), a procedure definition keeps defining its
+// name with (define name (let ((alias name) ...) (lambda formals body...))).
+fn bind_aliases(form: Expr, aliases: &[(String, String, usize)]) -> Expr {
+    if aliases.is_empty() {
+        return form;
+    }
+    let srcloc = extract_srcloc(&form);
+    let keyword = |name: &str| Expr::Keyword(name.to_string(), srcloc.clone());
+    let bindings = make_pair_from_vec(
+        aliases
+            .iter()
+            .map(|(name, alias, _)| make_pair_from_vec(vec![keyword(alias), keyword(name)]))
+            .collect(),
+    );
+    let bind = |expr| make_pair_from_vec(vec![keyword("let"), bindings.clone(), expr]);
+    match form {
+        Expr::Pair(
+            box Expr::Keyword(define, define_srcloc),
+            box Expr::Pair(box Expr::Pair(box name, box formals, _), box body, _),
+            srcloc,
+        ) if define == "define" => {
+            let lambda = Expr::Pair(
+                Box::new(keyword("lambda")),
+                Box::new(Expr::Pair(
+                    Box::new(formals),
+                    Box::new(body),
+                    srcloc.clone(),
+                )),
+                srcloc,
+            );
+            make_pair_from_vec(vec![
+                Expr::Keyword(define, define_srcloc),
+                name,
+                bind(lambda),
+            ])
+        }
+        form => bind(form),
+    }
+}
+
+// Replaces the identifiers a syntax-rules template introduced: they mean what
+// they mean where the macro was defined, so a local binding that shadows a
+// global one is skipped with #<global ref>, and one that shadows a local is
+// skipped with the local's alias.
+fn resolve_introduced(
+    expr: &Expr,
+    scope: &[String],
+    aliases: &[(String, String, usize)],
+    macros: &HashMap<String, MacroFn>,
+    quoted: bool,
+) -> Expr {
+    match expr {
+        Expr::Pair(
+            box Expr::Keyword(marker, ..),
+            box Expr::Pair(
+                box name @ Expr::Keyword(kw, srcloc),
+                box Expr::Pair(box Expr::Num(defined_in), box Expr::Nil, ..),
+                ..,
+            ),
+            ..,
+        ) if marker == INTRODUCED => {
+            // the macro was defined where the first defined_in names of scope
+            // were bound, a name it means locally is reached past a shadowing
+            // binding through the alias made at the first binding after those
+            let defined_in = defined_in
+                .value
+                .to_usize()
+                .unwrap_or_default()
+                .min(scope.len());
+            let (definition_scope, use_scope) = scope.split_at(defined_in);
+            if quoted || !use_scope.contains(kw) || macros.contains_key(kw) {
+                name.clone()
+            } else if definition_scope.contains(kw) {
+                aliases
+                    .iter()
+                    .find(|(name, _, scope_len)| name == kw && *scope_len >= defined_in)
+                    .map(|(_, alias, _)| Expr::Keyword(alias.clone(), srcloc.clone()))
+                    .unwrap_or_else(|| name.clone())
+            } else {
+                make_pair_from_vec(vec![
+                    Expr::Keyword(GLOBAL_REF.to_string(), srcloc.clone()),
+                    name.clone(),
+                ])
+            }
+        }
+        Expr::Pair(box head @ Expr::Keyword(kw, ..), box r, srcloc)
+            if ["quote", "quasiquote", "unquote", "unquote-splicing"].contains(&kw.as_str()) =>
+        {
+            let quoted = kw == "quote" || kw == "quasiquote";
+            Expr::Pair(
+                Box::new(head.clone()),
+                Box::new(resolve_introduced(r, scope, aliases, macros, quoted)),
+                srcloc.clone(),
+            )
+        }
+        Expr::Pair(box l, box r, srcloc) => {
+            let scope = [scope, &bound_names(expr)].concat();
+            Expr::Pair(
+                Box::new(resolve_introduced(l, &scope, aliases, macros, quoted)),
+                Box::new(resolve_introduced(r, &scope, aliases, macros, quoted)),
+                srcloc.clone(),
+            )
+        }
+        otherwise => otherwise.clone(),
+    }
+}
+
 pub fn macro_expand(
-    exprs: &Vec<Expr>,
+    exprs: &[Expr],
+    macros: &mut HashMap<String, MacroFn>,
+    context: &mut ExpansionContext,
+) -> Result<Vec<Expr>, CompileError> {
+    macro_expand_body(exprs, macros, &[], context, true)
+}
+
+// Macros defined in a body are visible in the rest of it, macros is the
+// caller's own map only at the top level.
+fn macro_expand_body(
+    exprs: &[Expr],
     macros: &mut HashMap<String, MacroFn>,
+    scope: &[String],
+    context: &mut ExpansionContext,
+    top_level: bool,
 ) -> Result<Vec<Expr>, CompileError> {
     let mut expanded_exprs = Vec::new();
     for expr in exprs {
@@ -249,20 +686,324 @@ pub fn macro_expand(
                 ),
                 ..,
             ) if kw == "defmacro" => {
-                let args = collect_kws_from_expr(&args).map_err(|_| CompileError {
+                let args = collect_kws_from_expr(args).map_err(|_| CompileError {
                     srcloc,
                     message: "Error when collecting kws for macro definition".to_string(),
                 })?;
-                let expanded_macro_body = macro_expand_one(&macro_body, macros)?;
-                let new_macro = make_macro(&args, &expanded_macro_body);
+                let expanded_macro_body = macro_expand_one(macro_body, macros, &args, context)?;
+                let new_macro = make_macro(&args, &expanded_macro_body, &context.syntax_env)?;
                 macros.insert(macro_name.clone(), new_macro);
             }
-            otherwise => expanded_exprs.push(macro_expand_one(&otherwise, macros)?),
+            Expr::Pair(
+                box Expr::Keyword(kw, ..),
+                box Expr::Pair(
+                    box Expr::Keyword(macro_name, ..),
+                    box Expr::Pair(box spec, box Expr::Nil, ..),
+                    ..,
+                ),
+                ..,
+            ) if kw == "define-syntax" => {
+                capture_locals(spec, scope, context);
+                let new_macro = make_syntax_rules(macro_name, spec, scope.len())?;
+                macros.insert(macro_name.clone(), new_macro);
+            }
+            Expr::Pair(box Expr::Keyword(kw, ..), ..) if kw == "define-for-syntax" => {
+                if !top_level {
+                    return comp_err!(expr, "define-for-syntax is only allowed at the top level");
+                }
+                define_for_syntax(
+                    &macro_expand_one(expr, macros, scope, context)?,
+                    &mut context.syntax_env,
+                )?;
+            }
+            otherwise => expanded_exprs.push(macro_expand_one(otherwise, macros, scope, context)?),
         }
     }
     Ok(expanded_exprs)
 }
 
+// Where the body starts in the forms that have one.
+fn body_start(form: &[Expr]) -> Option<usize> {
+    match form {
+        [Expr::Keyword(kw, ..), Expr::Keyword(..), ..]
+            if ["let", "let*", "letrec", "letrec*"].contains(&kw.as_str()) =>
+        {
+            Some(3)
+        }
+        [Expr::Keyword(kw, ..), Expr::Pair(..), ..] if kw == "define" => Some(2),
+        [Expr::Keyword(kw, ..), ..]
+            if [
+                "lambda",
+                "let",
+                "let*",
+                "letrec",
+                "letrec*",
+                "let-values",
+                "let*-values",
+            ]
+            .contains(&kw.as_str()) =>
+        {
+            Some(2)
+        }
+        [Expr::Keyword(kw, ..), ..] if kw == "receive" => Some(3),
+        _ => None,
+    }
+}
+
+// (let-syntax ((name (syntax-rules ...)) ...) body...) becomes (let () body...)
+// with the macros visible in body. In let-syntax the names a macro
+// introduces mean what they mean outside, in letrec-syntax the macros can use
+// each other.
+fn expand_let_syntax(
+    form: &Expr,
+    bindings: &Expr,
+    body: &[Expr],
+    macros: &mut HashMap<String, MacroFn>,
+    scope: &[String],
+    context: &mut ExpansionContext,
+) -> Result<Expr, CompileError> {
+    let recursive =
+        matches!(form, Expr::Pair(box Expr::Keyword(kw, ..), ..) if kw == "letrec-syntax");
+    let bindings = collect_exprs_from_body(bindings)?
+        .into_iter()
+        .map(|binding| match binding {
+            Expr::Pair(
+                box Expr::Keyword(name, ..),
+                box Expr::Pair(box spec, box Expr::Nil, ..),
+                ..,
+            ) => {
+                capture_locals(&spec, scope, context);
+                Ok((name.clone(), make_syntax_rules(&name, &spec, scope.len())?))
+            }
+            otherwise => comp_err!(
+                &otherwise,
+                "let-syntax, expected (name (syntax-rules ...)) but found: {}",
+                otherwise
+            ),
+        })
+        .collect::<Result<Vec<_>, CompileError>>()?;
+
+    let mut body_macros = macros.clone();
+    // the outer macros stay reachable under fresh names
+    let aliases = match recursive {
+        true => HashMap::new(),
+        false => bindings
+            .iter()
+            .filter_map(|(name, _)| {
+                let outer = macros.get(name)?;
+                let alias = fresh_name(name);
+                body_macros.insert(alias.clone(), outer.clone());
+                Some((name.clone(), alias))
+            })
+            .collect::<HashMap<String, String>>(),
+    };
+    for (name, new_macro) in bindings {
+        let new_macro = match aliases.is_empty() {
+            true => new_macro,
+            false => {
+                let aliases = aliases.clone();
+                Rc::new(
+                    move |srcloc, args: &Vec<Expr>, syntax_env: &mut SyntaxEnv| {
+                        new_macro(srcloc, args, syntax_env)
+                            .map(|expanded| rename_introduced(&expanded, &aliases))
+                    },
+                ) as MacroFn
+            }
+        };
+        body_macros.insert(name, new_macro);
+    }
+
+    let body = macro_expand_body(body, &mut body_macros, scope, context, false)?;
+    Ok(make_pair_from_vec(
+        [
+            vec![
+                Expr::Keyword("let".to_string(), extract_srcloc(form)),
+                Expr::Nil,
+            ],
+            body,
+        ]
+        .concat(),
+    ))
+}
+
+struct FieldSpec {
+    name: String,
+    accessor: Expr,
+    modifier: Option<Expr>,
+}
+
+// (define-record-type point (make-point x y) point? (x point-x set-point-x!) ...)
+// becomes a begin defining the type, its constructor, predicate, accessors
+// and modifiers.
+fn expand_define_record_type(form: &Expr) -> Result<Expr, CompileError> {
+    let (type_name, constructor, predicate, field_specs) =
+        match collect_exprs_from_body(form)?.as_slice() {
+            [_, type_name @ Expr::Keyword(..), constructor, predicate @ Expr::Keyword(..), field_specs @ ..] => (
+                type_name.clone(),
+                constructor.clone(),
+                predicate.clone(),
+                field_specs.to_vec(),
+            ),
+            _ => {
+                return comp_err!(
+                    form,
+                    "define-record-type, expected a name, a constructor, a predicate and fields, found: {form}"
+                )
+            }
+        };
+
+    let fields = field_specs
+        .iter()
+        .map(|spec| match collect_exprs_from_body(spec).as_deref() {
+            Ok([Expr::Keyword(name, ..), accessor @ Expr::Keyword(..), modifier @ ..])
+                if modifier.len() <= 1
+                    && modifier.iter().all(|m| matches!(m, Expr::Keyword(..))) =>
+            {
+                Ok(FieldSpec {
+                    name: name.clone(),
+                    accessor: accessor.clone(),
+                    modifier: modifier.first().cloned(),
+                })
+            }
+            _ => comp_err!(
+                spec,
+                "define-record-type, expected (field accessor [modifier]), found: {spec}"
+            ),
+        })
+        .collect::<Result<Vec<FieldSpec>, CompileError>>()?;
+    let field_names = fields
+        .iter()
+        .map(|field| field.name.clone())
+        .collect::<Vec<String>>();
+
+    // a constructor given by name alone takes all fields
+    let (constructor, params) = match &constructor {
+        Expr::Keyword(..) => (constructor.clone(), field_names.clone()),
+        _ => match collect_exprs_from_body(&constructor).as_deref() {
+            Ok([name @ Expr::Keyword(..), params @ ..]) => (
+                name.clone(),
+                params
+                    .iter()
+                    .map(|param| match param {
+                        Expr::Keyword(param, ..) if field_names.contains(param) => {
+                            Ok(param.clone())
+                        }
+                        _ => comp_err!(
+                            param,
+                            "define-record-type, {param} is not a field of {type_name}"
+                        ),
+                    })
+                    .collect::<Result<Vec<String>, CompileError>>()?,
+            ),
+            _ => {
+                return comp_err!(
+                    &constructor,
+                    "define-record-type, expected (constructor field...), found: {constructor}"
+                )
+            }
+        },
+    };
+
+    let srcloc = extract_srcloc(form);
+    let keyword = |kw: &str| Expr::Keyword(kw.to_string(), srcloc.clone());
+    let record_type = Expr::RecordType(Rc::new(RecordType {
+        name: type_name
+            .to_string()
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .to_string(),
+        fields: field_names.clone(),
+    }));
+    // the helpers are the global ones, whatever the fields are called
+    let global = |name: &str| make_pair_from_vec(vec![keyword(GLOBAL_REF), keyword(name)]);
+    let (record, value, obj) = (
+        keyword(&fresh_name("record")),
+        keyword(&fresh_name("value")),
+        keyword(&fresh_name("obj")),
+    );
+    let define = |signature: Vec<Expr>, body: Vec<Expr>| {
+        make_pair_from_vec(vec![
+            keyword("define"),
+            make_pair_from_vec(signature),
+            make_pair_from_vec(body),
+        ])
+    };
+
+    let mut definitions = vec![
+        make_pair_from_vec(vec![keyword("define"), type_name, record_type.clone()]),
+        define(
+            [
+                vec![constructor],
+                params.iter().map(|param| keyword(param)).collect(),
+            ]
+            .concat(),
+            [
+                vec![global("make-record"), record_type.clone()],
+                field_names
+                    .iter()
+                    .map(|field| match params.contains(field) {
+                        true => keyword(field),
+                        false => Expr::bool(false),
+                    })
+                    .collect(),
+            ]
+            .concat(),
+        ),
+        define(
+            vec![predicate, obj.clone()],
+            vec![global("record-of-type?"), record_type.clone(), obj],
+        ),
+    ];
+    for (index, field) in fields.into_iter().enumerate() {
+        let field_ref = |procedure: &str, name: &Expr| {
+            vec![
+                global(procedure),
+                record_type.clone(),
+                Expr::String(name.to_string(), None),
+                record.clone(),
+                Expr::int(index as i64),
+            ]
+        };
+        definitions.push(define(
+            vec![field.accessor.clone(), record.clone()],
+            field_ref("record-ref", &field.accessor),
+        ));
+        if let Some(modifier) = field.modifier {
+            definitions.push(define(
+                vec![modifier.clone(), record.clone(), value.clone()],
+                [field_ref("record-set!", &modifier), vec![value.clone()]].concat(),
+            ));
+        }
+    }
+    Ok(make_pair_from_vec(
+        [vec![keyword("begin")], definitions].concat(),
+    ))
+}
+
+fn rename_introduced(expr: &Expr, aliases: &HashMap<String, String>) -> Expr {
+    match expr {
+        Expr::Pair(
+            marker @ box Expr::Keyword(marker_kw, ..),
+            box Expr::Pair(box Expr::Keyword(kw, srcloc), rest, s1),
+            s2,
+        ) if marker_kw == INTRODUCED && aliases.contains_key(kw) => Expr::Pair(
+            marker.clone(),
+            Box::new(Expr::Pair(
+                Box::new(Expr::Keyword(aliases[kw].clone(), srcloc.clone())),
+                rest.clone(),
+                s1.clone(),
+            )),
+            s2.clone(),
+        ),
+        Expr::Pair(box l, box r, srcloc) => Expr::Pair(
+            Box::new(rename_introduced(l, aliases)),
+            Box::new(rename_introduced(r, aliases)),
+            srcloc.clone(),
+        ),
+        otherwise => otherwise.clone(),
+    }
+}
+
 #[test]
 fn expansion_noop_test() {
     use crate::parse::parse;
@@ -273,7 +1014,10 @@ fn expansion_noop_test() {
                 source: input,
                 file_name: Some("expansion_noop_test")
             })
-            .and_then(|parsed| macro_expand(&parsed, macros).map_err(|err| format!("{err}")))
+            .and_then(
+                |parsed| macro_expand(&parsed, macros, &mut ExpansionContext::default())
+                    .map_err(|err| format!("{err}"))
+            )
             .unwrap(),
             parse(&crate::parse::ParseInput {
                 source: input,
@@ -304,7 +1048,12 @@ fn expansion_test() {
                 source: input,
                 file_name: Some("expansion_test")
             })
-            .and_then(|parsed| macro_expand(&parsed, macros).map_err(|x| x.to_string())),
+            .and_then(|parsed| macro_expand(
+                &parsed,
+                macros,
+                &mut ExpansionContext::default()
+            )
+            .map_err(|x| x.to_string())),
             Ok(vec![expected.clone()])
         )
     }
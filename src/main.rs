@@ -1,13 +1,14 @@
 #![feature(box_patterns)]
-#![feature(map_try_insert)]
-#![feature(iterator_try_reduce)]
-#![feature(if_let_guard)]
-#![feature(assert_matches)]
 mod app;
 mod compile;
 mod expr;
+mod hash_table;
 mod macro_expand;
+mod number;
 mod parse;
+mod pattern_match;
+mod persistent;
+mod syntax_rules;
 mod tests;
 mod vm;
 use app::App;
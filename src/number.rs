@@ -0,0 +1,471 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Pow, Signed, ToPrimitive, Zero};
+
+// Exact integers are machine integers until they overflow, exact rationals
+// are kept in lowest terms and inexact numbers are floats. An operation is
+// only exact if all its operands are.
+#[derive(Clone, Debug)]
+pub enum Number {
+    Fixnum(i64),
+    Bignum(BigInt),
+    Rational(BigRational),
+    Real(f64),
+}
+
+impl Number {
+    pub fn integer(value: BigInt) -> Self {
+        match value.to_i64() {
+            Some(fixnum) => Number::Fixnum(fixnum),
+            None => Number::Bignum(value),
+        }
+    }
+
+    // A rational with denominator 1 is an integer.
+    pub fn rational(value: BigRational) -> Self {
+        match value.is_integer() {
+            true => Number::integer(value.to_integer()),
+            false => Number::Rational(value),
+        }
+    }
+
+    // Exact numbers are written without a point or an exponent, like 12 or
+    // -1/3.
+    pub fn parse_exact(text: &str) -> Option<Self> {
+        let is_integer = |text: &str| {
+            let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+            !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+        };
+        match text.split_once('/') {
+            Some((numerator, denominator))
+                if is_integer(numerator) && denominator.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                let numerator = numerator.parse::<BigInt>().ok()?;
+                let denominator = denominator.parse::<BigInt>().ok()?;
+                match denominator.is_zero() {
+                    true => None,
+                    false => Some(Number::rational(BigRational::new(numerator, denominator))),
+                }
+            }
+            None if is_integer(text) => text.parse::<BigInt>().ok().map(Number::integer),
+            _ => None,
+        }
+    }
+
+    // Reads what to-string writes, exact numbers also in other radixes.
+    pub fn parse(text: &str, radix: u32) -> Option<Self> {
+        match (text, radix) {
+            ("+inf.0", 10) => Some(Number::Real(f64::INFINITY)),
+            ("-inf.0", 10) => Some(Number::Real(f64::NEG_INFINITY)),
+            ("+nan.0", 10) => Some(Number::Real(f64::NAN)),
+            (text, 10) if let Some(exact) = Number::parse_exact(text) => Some(exact),
+            (text, 10)
+                if text
+                    .chars()
+                    .all(|c| "+-.eE".contains(c) || c.is_ascii_digit()) =>
+            {
+                text.parse::<f64>().ok().map(Number::Real)
+            }
+            (text, radix) => {
+                let integer = |text: &str| {
+                    let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+                    match !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+                        true => BigInt::parse_bytes(text.as_bytes(), radix),
+                        false => None,
+                    }
+                };
+                match text.split_once('/') {
+                    Some((numerator, denominator)) if !denominator.starts_with(['+', '-']) => {
+                        let denominator = integer(denominator)?;
+                        match denominator.is_zero() {
+                            true => None,
+                            false => Some(Number::rational(BigRational::new(
+                                integer(numerator)?,
+                                denominator,
+                            ))),
+                        }
+                    }
+                    Some(..) => None,
+                    None => integer(text).map(Number::integer),
+                }
+            }
+        }
+    }
+
+    // Only exact numbers can be written in another radix than 10.
+    pub fn to_string_radix(&self, radix: u32) -> Option<String> {
+        match (self, radix) {
+            (number, 10) => Some(number.to_string()),
+            (Number::Real(..), _) => None,
+            (Number::Rational(n), radix) => Some(format!(
+                "{}/{}",
+                n.numer().to_str_radix(radix),
+                n.denom().to_str_radix(radix)
+            )),
+            (integer, radix) => Some(integer.to_bigint()?.to_str_radix(radix)),
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Real(..))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Number::Real(x) => x.is_finite() && x.fract() == 0.0,
+            Number::Rational(..) => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Fixnum(n) => *n == 0,
+            Number::Bignum(n) => n.is_zero(),
+            Number::Rational(n) => n.is_zero(),
+            Number::Real(x) => *x == 0.0,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Fixnum(n) => *n as f64,
+            Number::Bignum(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Real(x) => *x,
+        }
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        match self {
+            Number::Fixnum(n) => usize::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Number::Fixnum(n) => Some(BigInt::from(*n)),
+            Number::Bignum(n) => Some(n.clone()),
+            Number::Rational(..) | Number::Real(..) => None,
+        }
+    }
+
+    fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Number::Rational(n) => Some(n.clone()),
+            Number::Real(..) => None,
+            integer => integer.to_bigint().map(BigRational::from_integer),
+        }
+    }
+
+    pub fn numerator(&self) -> Result<Number, String> {
+        match self {
+            Number::Real(..) => self.to_exact()?.numerator().map(|n| n.to_inexact()),
+            exact => Ok(Number::integer(
+                exact.to_rational().unwrap().numer().clone(),
+            )),
+        }
+    }
+
+    pub fn denominator(&self) -> Result<Number, String> {
+        match self {
+            Number::Real(..) => self.to_exact()?.denominator().map(|n| n.to_inexact()),
+            exact => Ok(Number::integer(
+                exact.to_rational().unwrap().denom().clone(),
+            )),
+        }
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Real(self.to_f64())
+    }
+
+    pub fn to_exact(&self) -> Result<Number, String> {
+        match self {
+            Number::Real(x) => BigRational::from_float(*x)
+                .map(Number::rational)
+                .ok_or(format!("exact: no exact representation of {self}")),
+            exact => Ok(exact.clone()),
+        }
+    }
+
+    fn arithmetic(
+        &self,
+        other: &Number,
+        fixnum: fn(i64, i64) -> Option<i64>,
+        rational: fn(BigRational, BigRational) -> BigRational,
+        real: fn(f64, f64) -> f64,
+    ) -> Number {
+        match (self, other) {
+            (Number::Fixnum(l), Number::Fixnum(r)) if let Some(result) = fixnum(*l, *r) => {
+                Number::Fixnum(result)
+            }
+            (Number::Real(..), _) | (_, Number::Real(..)) => {
+                Number::Real(real(self.to_f64(), other.to_f64()))
+            }
+            _ => match (self.to_rational(), other.to_rational()) {
+                (Some(l), Some(r)) => Number::rational(rational(l, r)),
+                _ => unreachable!("exact numbers are rationals"),
+            },
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        self.arithmetic(other, i64::checked_add, |l, r| l + r, |l, r| l + r)
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        self.arithmetic(other, i64::checked_sub, |l, r| l - r, |l, r| l - r)
+    }
+
+    pub fn mul(&self, other: &Number) -> Number {
+        self.arithmetic(other, i64::checked_mul, |l, r| l * r, |l, r| l * r)
+    }
+
+    pub fn div(&self, other: &Number) -> Result<Number, String> {
+        if self.is_exact() && other.is_exact() && other.is_zero() {
+            return Err("/: division by zero".to_string());
+        }
+        // a fixnum division is only exact without a remainder
+        let fixnum = |l: i64, r: i64| match l.checked_rem(r) {
+            Some(0) => l.checked_div(r),
+            _ => None,
+        };
+        Ok(self.arithmetic(other, fixnum, |l, r| l / r, |l, r| l / r))
+    }
+
+    // Has the sign of the dividend, like truncating division.
+    pub fn rem(&self, other: &Number) -> Result<Number, String> {
+        if self.is_exact() && other.is_exact() && other.is_zero() {
+            return Err("%: division by zero".to_string());
+        }
+        Ok(self.arithmetic(other, i64::checked_rem, |l, r| l % r, |l, r| l % r))
+    }
+
+    // Exact for an exact base and an integer exponent.
+    pub fn pow(&self, other: &Number, name: &str) -> Result<Number, String> {
+        match (self.to_rational(), other) {
+            (Some(base), exponent)
+                if base.is_zero() && exponent.is_exact() && *exponent < Number::Fixnum(0) =>
+            {
+                Err(format!("{name}: division by zero"))
+            }
+            (Some(base), Number::Fixnum(exponent))
+                if let Ok(exponent) = i32::try_from(*exponent) =>
+            {
+                Ok(Number::rational(Pow::pow(base, exponent)))
+            }
+            _ => Ok(Number::Real(self.to_f64().powf(other.to_f64()))),
+        }
+    }
+
+    pub fn abs(&self) -> Number {
+        match self {
+            Number::Fixnum(n) => match n.checked_abs() {
+                Some(n) => Number::Fixnum(n),
+                None => Number::integer(BigInt::from(*n).abs()),
+            },
+            Number::Bignum(n) => Number::integer(n.abs()),
+            Number::Rational(n) => Number::Rational(n.abs()),
+            Number::Real(x) => Number::Real(x.abs()),
+        }
+    }
+
+    fn rounded(&self, real: fn(f64) -> f64, rational: fn(&BigRational) -> BigRational) -> Number {
+        match self {
+            Number::Real(x) => Number::Real(real(*x)),
+            Number::Rational(n) => Number::rational(rational(n)),
+            integer => integer.clone(),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        self.rounded(f64::floor, BigRational::floor)
+    }
+
+    pub fn ceiling(&self) -> Number {
+        self.rounded(f64::ceil, BigRational::ceil)
+    }
+
+    pub fn truncate(&self) -> Number {
+        self.rounded(f64::trunc, BigRational::trunc)
+    }
+
+    // Halves round to the even neighbour.
+    pub fn round(&self) -> Number {
+        self.rounded(f64::round_ties_even, |n| {
+            let floor = n.floor();
+            let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+            match (n - &floor).cmp(&half) {
+                Ordering::Less => floor,
+                Ordering::Equal if floor.to_integer().is_even() => floor,
+                _ => floor + BigRational::one(),
+            }
+        })
+    }
+
+    // Exact for exact numbers that are squares.
+    pub fn sqrt(&self) -> Number {
+        match self.to_rational() {
+            Some(n) if !n.is_negative() => {
+                let (numer, denom) = (n.numer().sqrt(), n.denom().sqrt());
+                match &numer * &numer == *n.numer() && &denom * &denom == *n.denom() {
+                    true => Number::rational(BigRational::new(numer, denom)),
+                    false => Number::Real(self.to_f64().sqrt()),
+                }
+            }
+            _ => Number::Real(self.to_f64().sqrt()),
+        }
+    }
+
+    // The root of the largest square not above a non-negative exact integer,
+    // and what is left.
+    pub fn exact_integer_sqrt(&self) -> Option<(Number, Number)> {
+        match self.to_bigint() {
+            Some(n) if !n.is_negative() => {
+                let root = n.sqrt();
+                let rest = n - &root * &root;
+                Some((Number::integer(root), Number::integer(rest)))
+            }
+            _ => None,
+        }
+    }
+
+    // Integral floats count as integers, the result is then inexact.
+    fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Number::Real(x) if self.is_integer() => BigInt::from_f64(*x),
+            other => other.to_bigint(),
+        }
+    }
+
+    pub fn is_even(&self) -> Option<bool> {
+        self.to_integer().map(|n| n.is_even())
+    }
+
+    fn integer_op(
+        &self,
+        other: &Number,
+        name: &str,
+        op: fn(&BigInt, &BigInt) -> BigInt,
+    ) -> Result<Number, String> {
+        match (self.to_integer(), other.to_integer()) {
+            (Some(l), Some(r)) => {
+                let result = Number::integer(op(&l, &r));
+                match self.is_exact() && other.is_exact() {
+                    true => Ok(result),
+                    false => Ok(result.to_inexact()),
+                }
+            }
+            _ => Err(format!(
+                "{name}: expected integers, found: {self} and {other}"
+            )),
+        }
+    }
+
+    fn integer_division(
+        &self,
+        other: &Number,
+        name: &str,
+        op: fn(&BigInt, &BigInt) -> BigInt,
+    ) -> Result<Number, String> {
+        match other.is_zero() {
+            true => Err(format!("{name}: division by zero")),
+            false => self.integer_op(other, name, op),
+        }
+    }
+
+    // Rounds towards zero.
+    pub fn quotient(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other, "quotient", |l, r| l / r)
+    }
+
+    // Has the sign of the dividend.
+    pub fn remainder(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other, "remainder", |l, r| l % r)
+    }
+
+    // Has the sign of the divisor.
+    pub fn modulo(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other, "modulo", Integer::mod_floor)
+    }
+
+    pub fn gcd(&self, other: &Number) -> Result<Number, String> {
+        self.integer_op(other, "gcd", Integer::gcd)
+    }
+
+    pub fn lcm(&self, other: &Number) -> Result<Number, String> {
+        self.integer_op(other, "lcm", Integer::lcm)
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// Compares by value, exact and inexact numbers included.
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Fixnum(l), Number::Fixnum(r)) => Some(l.cmp(r)),
+            (Number::Real(l), Number::Real(r)) => l.partial_cmp(r),
+            (Number::Real(x), exact) => exact.partial_cmp(&Number::Real(*x)).map(Ordering::reverse),
+            (exact, Number::Real(x)) if x.is_finite() => {
+                Some(exact.to_rational()?.cmp(&BigRational::from_float(*x)?))
+            }
+            (exact, Number::Real(x)) => exact.to_f64().partial_cmp(x),
+            (l, r) => Some(l.to_rational()?.cmp(&r.to_rational()?)),
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Fixnum(n) => write!(f, "{n}"),
+            Number::Bignum(n) => write!(f, "{n}"),
+            Number::Rational(n) => write!(f, "{}/{}", n.numer(), n.denom()),
+            Number::Real(x) if x.is_nan() => write!(f, "+nan.0"),
+            Number::Real(x) if x.is_infinite() => {
+                write!(f, "{}inf.0", if *x > 0.0 { "+" } else { "-" })
+            }
+            // an inexact number always shows it is one, large ones with an
+            // exponent
+            Number::Real(x) if x.fract() == 0.0 => write!(f, "{x:?}"),
+            Number::Real(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+#[test]
+fn number_test() {
+    let big = Number::parse_exact("9223372036854775807").unwrap();
+    let one = Number::Fixnum(1);
+    assert_eq!(
+        format!("{}", big.add(&one)),
+        "9223372036854775808".to_string()
+    );
+    assert!(matches!(big.add(&one).sub(&one), Number::Fixnum(..)));
+    assert_eq!(Number::Fixnum(3), Number::Real(3.0));
+    assert!(Number::Fixnum(3) < Number::Real(3.5));
+    assert!(Number::Real(-2.5) < Number::Fixnum(-2));
+    assert_eq!(format!("{}", Number::Real(3.0)), "3.0".to_string());
+    assert_eq!(
+        format!("{}", Number::Fixnum(7).div(&Number::Fixnum(2)).unwrap()),
+        "7/2".to_string()
+    );
+    assert_eq!(Number::parse_exact("1.5"), None);
+    assert_eq!(Number::parse_exact("1/0"), None);
+    let half = Number::parse_exact("5/2").unwrap();
+    assert_eq!(half.round(), Number::Fixnum(2));
+    assert_eq!(half.floor(), Number::Fixnum(2));
+    assert_eq!(half.ceiling(), Number::Fixnum(3));
+    assert_eq!(Number::Real(-3.5).round(), Number::Real(-4.0));
+    assert!(Number::parse_exact("1/3").unwrap() < Number::Real(0.34));
+}
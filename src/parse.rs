@@ -1,30 +1,30 @@
 use crate::compile::extract_srcloc;
 use crate::expr::{Expr, Num};
+use crate::number::Number;
 use nom::bytes::complete::{is_not, tag};
-use nom::character::complete::multispace1;
-use nom::combinator::value;
+use nom::character::complete::{anychar, digit1, multispace0, multispace1, none_of, one_of};
+use nom::combinator::{consumed, map_opt, opt, recognize, value};
 use nom::multi::many1;
 use nom::number::complete::double;
-use nom::sequence::pair;
+use nom::sequence::{pair, preceded, tuple};
 use nom::{
-    branch::alt,
-    character::complete::char,
-    combinator::map,
-    error::VerboseError,
-    multi::many0,
-    sequence::{delimited, preceded},
-    IResult,
+    branch::alt, character::complete::char, combinator::map, error::VerboseError,
+    error::VerboseErrorKind, multi::many0, sequence::delimited, IResult,
 };
 use nom_locate::{self, position};
+use std::cell::RefCell;
 use std::fmt::Display;
+use std::rc::Rc;
 use std::str;
 type Span<'a> = nom_locate::LocatedSpan<&'a str, Option<&'a str>>;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SrcLoc {
     pub line: u32,
     pub column: usize,
     pub file_name: Option<String>,
+    // for code a macro produced: the macro and where it was used
+    pub expanded_from: Option<Rc<(String, SrcLoc)>>,
 }
 
 impl Display for SrcLoc {
@@ -39,6 +39,21 @@ impl Display for SrcLoc {
     }
 }
 
+// A rational that doesn't read, like 1/0, is an error rather than a number
+// followed by a name.
+fn parse_rational(i: Span) -> IResult<Span, Number, VerboseError<Span>> {
+    let (rest, text) = recognize(tuple((opt(one_of("+-")), digit1, char('/'), digit1)))(i)?;
+    match Number::parse_exact(text.fragment()) {
+        Some(value) => Ok((rest, value)),
+        None => Err(nom::Err::Failure(VerboseError {
+            errors: vec![(
+                text,
+                VerboseErrorKind::Context("invalid number, the denominator is zero"),
+            )],
+        })),
+    }
+}
+
 fn parse_number(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     let file_name = i.extra.map(|x| x.to_string());
     let pos = position::<Span, VerboseError<Span>>(i)?.1;
@@ -46,11 +61,16 @@ fn parse_number(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
         line: pos.location_line(),
         column: pos.get_column(),
         file_name,
+        expanded_from: None,
     };
 
-    map(double, move |val| {
+    // a rational like 1/3, or anything double reads
+    let number = map(consumed(double), |(text, val): (Span, f64)| {
+        Number::parse_exact(text.fragment()).unwrap_or(Number::Real(val))
+    });
+    map(alt((parse_rational, number)), move |value| {
         Expr::Num(Num {
-            value: val,
+            value,
             srcloc: Some(src_loc.clone()),
         })
     })(i)
@@ -63,6 +83,7 @@ fn parse_string(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
         line: pos.location_line(),
         column: pos.get_column(),
         file_name,
+        expanded_from: None,
     };
     map(
         delimited(char('"'), many0(is_not("\"")), char('"')),
@@ -77,6 +98,46 @@ fn parse_string(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     )(i)
 }
 
+// #\a, a named character like #\space or a hex code like #\x41
+fn parse_char(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
+    let file_name = i.extra.map(|x| x.to_string());
+    let pos = position::<Span, VerboseError<Span>>(i)?.1;
+    let src_loc = SrcLoc {
+        line: pos.location_line(),
+        column: pos.get_column(),
+        file_name,
+        expanded_from: None,
+    };
+
+    map_opt(
+        preceded(
+            tag("#\\"),
+            recognize(pair(anychar, many0(none_of(";\n\r\t )(")))),
+        ),
+        move |name: Span| {
+            let mut chars = name.fragment().chars();
+            let c = match (chars.next()?, chars.as_str()) {
+                (c, "") => c,
+                _ => match *name.fragment() {
+                    "space" => ' ',
+                    "newline" | "linefeed" => '\n',
+                    "tab" => '\t',
+                    "return" => '\r',
+                    "null" | "nul" => '\0',
+                    "alarm" => '\u{7}',
+                    "backspace" => '\u{8}',
+                    "escape" => '\u{1b}',
+                    "delete" => '\u{7f}',
+                    hex => u32::from_str_radix(hex.strip_prefix('x')?, 16)
+                        .ok()
+                        .and_then(char::from_u32)?,
+                },
+            };
+            Some(Expr::Char(c, Some(src_loc.clone())))
+        },
+    )(i)
+}
+
 fn parse_keyword(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     let pos = position::<Span, VerboseError<Span>>(i)?.1;
     let file_name = i.extra.map(|x| x.to_string());
@@ -84,6 +145,7 @@ fn parse_keyword(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
         line: pos.location_line(),
         column: pos.get_column(),
         file_name,
+        expanded_from: None,
     };
 
     map(many1(is_not(";\n\r )(")), move |char_vec| {
@@ -121,7 +183,12 @@ pub fn comment(i: Span) -> IResult<Span, (), VerboseError<Span>> {
 fn parse_pair(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     let (mut i, _) = tag("(")(i)?;
     let mut results: Vec<(SrcLoc, Expr)> = Vec::new();
-    while let Ok((new_s, expr)) = parse_expr(i) {
+    loop {
+        let (new_s, expr) = match parse_expr(i) {
+            Ok(parsed) => parsed,
+            Err(failure @ nom::Err::Failure(..)) => return Err(failure),
+            Err(..) => break,
+        };
         i = new_s;
         let pos = position::<Span, VerboseError<Span>>(i)?.0;
         let file_name = i.extra.map(|x| x.to_string());
@@ -129,6 +196,7 @@ fn parse_pair(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
             line: pos.location_line(),
             column: pos.get_column(),
             file_name,
+            expanded_from: None,
         };
         results.push((src_loc, expr))
     }
@@ -144,6 +212,20 @@ fn parse_pair(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     Ok((i, res))
 }
 
+// #(1 2 3), the items are read as data
+fn parse_vector(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
+    map(
+        delimited(
+            tag("#("),
+            many0(parse_expr),
+            preceded(multispace0, tag(")")),
+        ),
+        |items| Expr::Vector(Rc::new(RefCell::new(items))),
+    )(i)
+}
+
+// 'x, `x, ,x and ,@x are read as (quote x), (quasiquote x), (unquote x)
+// and (unquote-splicing x)
 fn parse_quote(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
     let pos = position::<Span, VerboseError<Span>>(i)?.1;
     let file_name = i.extra.map(|x| x.to_string());
@@ -151,27 +233,37 @@ fn parse_quote(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
         line: pos.location_line(),
         column: pos.get_column(),
         file_name: file_name.clone(),
+        expanded_from: None,
     };
 
     map(
-        preceded(
-            char('\''),
+        pair(
+            alt((tag("'"), tag("`"), tag(",@"), tag(","))),
             alt((
                 parse_quote,
                 parse_pair,
+                parse_vector,
                 parse_number,
                 parse_string,
+                parse_char,
                 parse_keyword,
             )),
         ),
-        move |exprs| {
+        move |(prefix, exprs)| {
+            let quote_kw = match *prefix.fragment() {
+                "'" => "quote",
+                "`" => "quasiquote",
+                ",@" => "unquote-splicing",
+                _ => "unquote",
+            };
             Expr::Pair(
                 Box::new(Expr::Keyword(
-                    "quote".to_string(),
+                    quote_kw.to_string(),
                     Some(SrcLoc {
                         line: src_loc.line,
                         column: src_loc.column,
                         file_name: file_name.clone(),
+                        expanded_from: None,
                     }),
                 )),
                 Box::new(Expr::Pair(
@@ -181,6 +273,7 @@ fn parse_quote(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
                         line: src_loc.line,
                         column: src_loc.column,
                         file_name: file_name.clone(),
+                        expanded_from: None,
                     }),
                 )),
                 Some(src_loc.clone()),
@@ -195,8 +288,10 @@ fn parse_expr(i: Span) -> IResult<Span, Expr, VerboseError<Span>> {
         alt((
             parse_quote,
             parse_pair,
+            parse_vector,
             parse_number,
             parse_string,
+            parse_char,
             parse_keyword,
         )),
         many0(alt((comment, value((), multispace1)))),
@@ -211,7 +306,19 @@ pub struct ParseInput<'a> {
 
 pub fn parse(input: &ParseInput) -> Result<Vec<Expr>, String> {
     many0(parse_expr)(Span::new_extra(input.source, input.file_name))
-        .map_err(|e| format!("{e:?}"))
+        .map_err(|e| match e {
+            nom::Err::Failure(VerboseError { errors }) => match errors.as_slice() {
+                [(text, VerboseErrorKind::Context(context)), ..] => format!(
+                    "{}:{}:{}: {context}: {}",
+                    input.file_name.unwrap_or("unknown"),
+                    text.location_line(),
+                    text.get_column(),
+                    text.fragment()
+                ),
+                _ => format!("{errors:?}"),
+            },
+            e => format!("{e:?}"),
+        })
         .and_then(|(remaining, exp)| match *remaining.fragment() {
             "" => Ok(exp),
             remainder => Err(format!("Unexpected end of input: {remainder}")),
@@ -258,6 +365,18 @@ fn test_parse_alphanumerics() {
             Expr::String("hello 2".to_string(), None),
         ])])
     );
+    assert_eq!(
+        parse(&ParseInput {
+            source: "(#\\a #\\space #\\x41 #\\()",
+            file_name: None
+        }),
+        Ok(vec![make_pair_from_vec(vec![
+            Expr::Char('a', None),
+            Expr::Char(' ', None),
+            Expr::Char('A', None),
+            Expr::Char('(', None),
+        ])])
+    );
     assert_eq!(
         parse(&ParseInput {
             source: "+",
@@ -326,16 +445,12 @@ fn test_parse_quote() {
     assert_eq!(res.as_ref().map(|x| x.len()), Ok(1));
 
     let borrowed = res.map(|x| x.first().cloned()).unwrap().unwrap();
-    matches!(
-        borrowed,
-        Expr::Quote(
-            box Expr::Nil,
-            Some(SrcLoc {
-                line: 0,
-                column: 0,
-                file_name: None,
-            }),
-        ),
+    assert_matches!(borrowed,
+        Expr::Pair(
+            box Expr::Keyword(quote, ..),
+            box Expr::Pair(box Expr::Nil, box Expr::Nil, ..),
+            ..,
+        ) if quote == *"quote",
     );
 
     let res2 = parse(&ParseInput {
@@ -370,7 +485,7 @@ fn test_parse_quote() {
     .unwrap()
     .unwrap();
 
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
     assert_matches!(res3,
         Expr::Pair(
             box Expr::Keyword(quote, ..),
@@ -378,6 +493,24 @@ fn test_parse_quote() {
             ..,
         ) if a == *"a" && quote == *"quote",
     );
+
+    let res4 = parse(&ParseInput {
+        source: "`(a ,b ,@c)",
+        file_name: None,
+    })
+    .unwrap();
+    let kw = |kw: &str| Expr::Keyword(kw.to_string(), None);
+    assert_eq!(
+        res4,
+        vec![make_pair_from_vec(vec![
+            kw("quasiquote"),
+            make_pair_from_vec(vec![
+                kw("a"),
+                make_pair_from_vec(vec![kw("unquote"), kw("b")]),
+                make_pair_from_vec(vec![kw("unquote-splicing"), kw("c")]),
+            ])
+        ])]
+    );
 }
 
 #[test]
@@ -437,7 +570,7 @@ fn test_parse_comment() {
 }
 #[test]
 fn test_parse_lists() {
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
     fn ok_list(strings: Vec<&str>) -> Result<Vec<Expr>, String> {
         let stuff: Vec<Expr> = strings
             .iter()
@@ -460,12 +593,13 @@ fn test_parse_lists() {
         .map(|x| x.split_first().map(|x| x.0.clone())),
         Ok(Some(Expr::Pair(
             box Expr::Num(Num {
-                value: 123.0,
+                value: Number::Fixnum(123),
                 srcloc:
                     Some(SrcLoc {
                         line: 1,
                         column: 2,
                         file_name: None,
+                        expanded_from: None,
                     }),
             }),
             box Expr::Nil,
@@ -0,0 +1,414 @@
+use crate::{
+    comp_err,
+    compile::{collect_exprs_from_body, extract_srcloc, fresh_name, CompileError, GLOBAL_REF},
+    expr::Expr,
+    parse::{make_pair_from_vec, SrcLoc},
+};
+
+// What matching a pattern takes, in order. Every value a test looks at is
+// bound to a fresh name first, the names of the pattern are only bound once
+// the whole pattern matched.
+enum Step {
+    Test(Expr),
+    Let(String, Expr),
+    // matches every item of a list, a variable of the item pattern is bound
+    // to the list of what it matched in each item
+    Each {
+        list: Expr,
+        item: String,
+        steps: Vec<Step>,
+        vars: Vec<(Expr /* in one item */, String /* for all items */)>,
+    },
+}
+
+type Bindings = Vec<(String, Expr)>;
+
+struct Builder {
+    srcloc: Option<SrcLoc>,
+}
+
+impl Builder {
+    fn keyword(&self, kw: &str) -> Expr {
+        Expr::Keyword(kw.to_string(), self.srcloc.clone())
+    }
+
+    fn call(&self, function: &str, args: Vec<Expr>) -> Expr {
+        make_pair_from_vec([vec![self.keyword(function)], args].concat())
+    }
+
+    // calls a global procedure, even where the user has shadowed its name
+    fn call_global(&self, function: &str, args: Vec<Expr>) -> Expr {
+        make_pair_from_vec(
+            [
+                vec![self.call(GLOBAL_REF, vec![self.keyword(function)])],
+                args,
+            ]
+            .concat(),
+        )
+    }
+
+    fn is_pair(&self, value: &Expr) -> Expr {
+        // pair? is also true for '()
+        self.call(
+            "and",
+            vec![
+                self.call_global("pair?", vec![value.clone()]),
+                self.call_global("not", vec![self.call_global("nil?", vec![value.clone()])]),
+            ],
+        )
+    }
+
+    fn bind(&self, name: String, value: Expr, body: Expr) -> Expr {
+        self.call(
+            "let",
+            vec![
+                make_pair_from_vec(vec![make_pair_from_vec(vec![self.keyword(&name), value])]),
+                body,
+            ],
+        )
+    }
+
+    fn temporary(&self, prefix: &str, value: Expr, steps: &mut Vec<Step>) -> Expr {
+        let name = fresh_name(prefix);
+        steps.push(Step::Let(name.clone(), value));
+        self.keyword(&name)
+    }
+
+    fn compile_pattern(
+        &self,
+        pattern: &Expr,
+        value: &Expr,
+        steps: &mut Vec<Step>,
+        bindings: &mut Bindings,
+    ) -> Result<(), CompileError> {
+        match pattern {
+            Expr::Keyword(kw, ..) if kw == "_" => {}
+            Expr::Keyword(kw, ..) if kw == "..." || kw == "." => {
+                return comp_err!(pattern, "match, {kw} can only follow a pattern in a list")
+            }
+            Expr::Keyword(kw, ..) => bindings.push((kw.clone(), value.clone())),
+            Expr::Nil => steps.push(Step::Test(self.call_global("nil?", vec![value.clone()]))),
+            Expr::Num(..) | Expr::String(..) | Expr::Char(..) | Expr::Boolean(..) => steps.push(
+                Step::Test(self.call_global("equal?", vec![value.clone(), pattern.clone()])),
+            ),
+            Expr::Pair(box Expr::Keyword(kw, ..), ..) if kw == "quote" => steps.push(Step::Test(
+                self.call_global("equal?", vec![value.clone(), pattern.clone()]),
+            )),
+            Expr::Pair(box Expr::Keyword(kw, ..), box rest, ..) if kw == "?" => {
+                match collect_exprs_from_body(rest)?.as_slice() {
+                    [predicate, patterns @ ..] => {
+                        steps.push(Step::Test(make_pair_from_vec(vec![
+                            predicate.clone(),
+                            value.clone(),
+                        ])));
+                        for pattern in patterns {
+                            self.compile_pattern(pattern, value, steps, bindings)?;
+                        }
+                    }
+                    [] => return comp_err!(pattern, "match, expected (? predicate pattern...)"),
+                }
+            }
+            Expr::Pair(box Expr::Keyword(kw, ..), box rest, ..) if kw == "$" => {
+                match collect_exprs_from_body(rest)?.as_slice() {
+                    [record_type, patterns @ ..] => {
+                        steps.push(Step::Test(self.call_global(
+                            "record-of-type?",
+                            vec![record_type.clone(), value.clone()],
+                        )));
+                        for (index, pattern) in patterns.iter().enumerate() {
+                            let field = self.call_global(
+                                "record-ref",
+                                vec![
+                                    record_type.clone(),
+                                    Expr::String("match".to_string(), None),
+                                    value.clone(),
+                                    Expr::int(index as i64),
+                                ],
+                            );
+                            let field = self.temporary("match-field", field, steps);
+                            self.compile_pattern(pattern, &field, steps, bindings)?;
+                        }
+                    }
+                    [] => return comp_err!(pattern, "match, expected ($ record-type pattern...)"),
+                }
+            }
+            Expr::Pair(..) => self.compile_list(pattern, value, steps, bindings)?,
+            _ => return comp_err!(pattern, "match, unexpected pattern: {pattern}"),
+        }
+        Ok(())
+    }
+
+    // Matches the first patterns of a list one by one, the rest of the list
+    // goes to what follows them.
+    fn compile_items(
+        &self,
+        patterns: &[Expr],
+        value: &Expr,
+        steps: &mut Vec<Step>,
+        bindings: &mut Bindings,
+    ) -> Result<Expr, CompileError> {
+        patterns.iter().try_fold(value.clone(), |value, pattern| {
+            steps.push(Step::Test(self.is_pair(&value)));
+            let item = self.temporary(
+                "match-car",
+                self.call_global("car", vec![value.clone()]),
+                steps,
+            );
+            let rest = self.temporary("match-cdr", self.call_global("cdr", vec![value]), steps);
+            self.compile_pattern(pattern, &item, steps, bindings)?;
+            Ok(rest)
+        })
+    }
+
+    fn compile_list(
+        &self,
+        pattern: &Expr,
+        value: &Expr,
+        steps: &mut Vec<Step>,
+        bindings: &mut Bindings,
+    ) -> Result<(), CompileError> {
+        let items = collect_exprs_from_body(pattern)?;
+        let is = |item: &Expr, name: &str| matches!(item, Expr::Keyword(kw, ..) if kw == name);
+
+        if let Some(dot) = items.iter().position(|item| is(item, ".")) {
+            return match &items[dot..] {
+                [_, tail] if dot > 0 && !items.iter().any(|item| is(item, "...")) => {
+                    let rest = self.compile_items(&items[..dot], value, steps, bindings)?;
+                    self.compile_pattern(tail, &rest, steps, bindings)
+                }
+                _ => comp_err!(pattern, "match, unexpected dotted pattern: {pattern}"),
+            };
+        }
+
+        let ellipsis = match items.iter().position(|item| is(item, "...")) {
+            Some(ellipsis) if ellipsis > 0 => ellipsis,
+            Some(_) => return comp_err!(pattern, "match, ... has to follow a pattern"),
+            None => {
+                let rest = self.compile_items(&items, value, steps, bindings)?;
+                steps.push(Step::Test(self.call_global("nil?", vec![rest])));
+                return Ok(());
+            }
+        };
+        let (before, repeated, after) = (
+            &items[..ellipsis - 1],
+            &items[ellipsis - 1],
+            &items[ellipsis + 1..],
+        );
+        if after.iter().any(|item| is(item, "...")) {
+            return comp_err!(
+                pattern,
+                "match, only one ... is allowed in a list: {pattern}"
+            );
+        }
+
+        let mut rest = self.compile_items(before, value, steps, bindings)?;
+        if !after.is_empty() {
+            // the patterns after the ellipsis match the end of the list, from
+            // the back
+            steps.push(Step::Test(self.call_global("list?", vec![rest.clone()])));
+            let reversed = self.call_global("reverse", vec![rest]);
+            let reversed = self.temporary("match-reversed", reversed, steps);
+            let after = after.iter().rev().cloned().collect::<Vec<Expr>>();
+            let repeated_reversed = self.compile_items(&after, &reversed, steps, bindings)?;
+            rest = self.call_global("reverse", vec![repeated_reversed]);
+        }
+
+        let item = fresh_name("match-item");
+        let (mut item_steps, mut item_bindings) = (vec![], vec![]);
+        self.compile_pattern(
+            repeated,
+            &self.keyword(&item),
+            &mut item_steps,
+            &mut item_bindings,
+        )?;
+        let vars = item_bindings
+            .into_iter()
+            .map(|(name, value)| {
+                let all = fresh_name(&name);
+                bindings.push((name, self.keyword(&all)));
+                (value, all)
+            })
+            .collect();
+        steps.push(Step::Each {
+            list: rest,
+            item,
+            steps: item_steps,
+            vars,
+        });
+        Ok(())
+    }
+
+    fn emit(&self, steps: &[Step], on_match: Expr, fail: &Expr) -> Expr {
+        steps
+            .iter()
+            .rev()
+            .fold(on_match, |on_match, step| match step {
+                Step::Test(test) => self.call("if", vec![test.clone(), on_match, fail.clone()]),
+                Step::Let(name, value) => self.bind(name.clone(), value.clone(), on_match),
+                Step::Each {
+                    list,
+                    item,
+                    steps,
+                    vars,
+                } => {
+                    // (let loop ((items list) (acc '()) ...)
+                    //   (if (nil? items)
+                    //       (let ((all (reverse acc)) ...) on_match)
+                    //       (if (pair? items) (let ((item (car items))) match...) fail)))
+                    let recur = fresh_name("match-loop");
+                    let items = self.keyword(&fresh_name("match-items"));
+                    let accumulators = vars
+                        .iter()
+                        .map(|(_, all)| self.keyword(&fresh_name(&format!("{all}-acc"))))
+                        .collect::<Vec<Expr>>();
+                    let next = make_pair_from_vec(
+                        [
+                            vec![
+                                self.keyword(&recur),
+                                self.call_global("cdr", vec![items.clone()]),
+                            ],
+                            vars.iter()
+                                .zip(&accumulators)
+                                .map(|((value, _), acc)| {
+                                    self.call_global("cons", vec![value.clone(), acc.clone()])
+                                })
+                                .collect(),
+                        ]
+                        .concat(),
+                    );
+                    let on_end = vars.iter().zip(&accumulators).rev().fold(
+                        on_match,
+                        |on_match, ((_, all), acc)| {
+                            let reversed = self.call_global("reverse", vec![acc.clone()]);
+                            self.bind(all.clone(), reversed, on_match)
+                        },
+                    );
+                    let on_item = self.bind(
+                        item.clone(),
+                        self.call_global("car", vec![items.clone()]),
+                        self.emit(steps, next, fail),
+                    );
+                    let body = self.call(
+                        "if",
+                        vec![
+                            self.call_global("nil?", vec![items.clone()]),
+                            on_end,
+                            self.call("if", vec![self.is_pair(&items), on_item, fail.clone()]),
+                        ],
+                    );
+                    let loop_bindings = [
+                        vec![make_pair_from_vec(vec![items.clone(), list.clone()])],
+                        accumulators
+                            .iter()
+                            .map(|acc| make_pair_from_vec(vec![acc.clone(), Expr::Nil]))
+                            .collect(),
+                    ]
+                    .concat();
+                    self.call(
+                        "let",
+                        vec![
+                            self.keyword(&recur),
+                            make_pair_from_vec(loop_bindings),
+                            body,
+                        ],
+                    )
+                }
+            })
+    }
+
+    // (pattern body...) or (pattern (guard test) body...)
+    fn compile_clause(
+        &self,
+        clause: &Expr,
+        value: &Expr,
+        fail: &Expr,
+    ) -> Result<Expr, CompileError> {
+        let (pattern, guard, body) = match collect_exprs_from_body(clause).as_deref() {
+            Ok([pattern, Expr::Pair(box Expr::Keyword(kw, ..), box guard, ..), body @ ..])
+                if kw == "guard" =>
+            {
+                match collect_exprs_from_body(guard).as_deref() {
+                    Ok([guard]) => (pattern.clone(), Some(guard.clone()), body.to_vec()),
+                    _ => return comp_err!(clause, "match, expected (guard test), found: {clause}"),
+                }
+            }
+            Ok([pattern, body @ ..]) => (pattern.clone(), None, body.to_vec()),
+            _ => return comp_err!(clause, "match, expected (pattern body...), found: {clause}"),
+        };
+        if body.is_empty() {
+            return comp_err!(clause, "match, expected a body in: {clause}");
+        }
+
+        let (mut steps, mut bindings) = (vec![], vec![]);
+        self.compile_pattern(&pattern, value, &mut steps, &mut bindings)?;
+        for (index, (name, _)) in bindings.iter().enumerate() {
+            if bindings[..index].iter().any(|(bound, _)| bound == name) {
+                return comp_err!(&pattern, "match, {name} occurs twice in pattern: {pattern}");
+            }
+        }
+
+        let body = make_pair_from_vec([vec![self.keyword("let"), Expr::Nil], body].concat());
+        let body = match guard {
+            Some(guard) => self.call("if", vec![guard, body, fail.clone()]),
+            None => body,
+        };
+        let on_match = self.call(
+            "let",
+            vec![
+                make_pair_from_vec(
+                    bindings
+                        .into_iter()
+                        .map(|(name, value)| make_pair_from_vec(vec![self.keyword(&name), value]))
+                        .collect(),
+                ),
+                body,
+            ],
+        );
+        Ok(self.emit(&steps, on_match, fail))
+    }
+}
+
+// (match expr clause...) tries the clauses in order. Each clause becomes a
+// chain of tests that calls the next clause, as a thunk, when a test fails.
+pub fn expand_match(form: &Expr) -> Result<Expr, CompileError> {
+    let (expr, clauses) = match collect_exprs_from_body(form)?.as_slice() {
+        [_, expr, clauses @ ..] => (expr.clone(), clauses.to_vec()),
+        _ => {
+            return comp_err!(
+                form,
+                "match, expected an expression and clauses, found: {form}"
+            )
+        }
+    };
+    let builder = Builder {
+        srcloc: extract_srcloc(form),
+    };
+    let value_name = fresh_name("match-value");
+    let value = builder.keyword(&value_name);
+
+    // the message is located where the match is
+    let no_match = builder.call_global(
+        "error",
+        vec![
+            Expr::String(
+                "match, no clause matches:".to_string(),
+                builder.srcloc.clone(),
+            ),
+            value.clone(),
+        ],
+    );
+    let fail_thunk = |body: Expr| builder.call("lambda", vec![Expr::Nil, body]);
+    let matching = clauses
+        .iter()
+        .rev()
+        .try_fold(no_match, |otherwise, clause| {
+            let fail = fresh_name("match-fail");
+            let clause = builder.compile_clause(
+                clause,
+                &value,
+                &make_pair_from_vec(vec![builder.keyword(&fail)]),
+            )?;
+            Ok(builder.bind(fail, fail_thunk(otherwise), clause))
+        })?;
+    Ok(builder.bind(value_name, expr, matching))
+}
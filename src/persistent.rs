@@ -0,0 +1,88 @@
+use std::cmp::Ordering;
+
+use crate::expr::{Bool, Expr, Num};
+
+// A key of a persistent map or set. Keys are ordered, so only values that
+// can't change and have an order can be keys: booleans, numbers, chars,
+// strings, symbols and lists and sets of those. Like equal?, 1 and 1.0 are
+// different keys.
+#[derive(Clone, Debug)]
+pub struct MapKey(pub Expr);
+
+impl MapKey {
+    pub fn new(name: &str, key: &Expr) -> Result<MapKey, String> {
+        match is_orderable(key) {
+            true => Ok(MapKey(key.clone())),
+            false => Err(format!(
+                "{name}: {key} can't be a key, keys are booleans, numbers, chars, strings, symbols, lists or sets"
+            )),
+        }
+    }
+}
+
+fn is_orderable(expr: &Expr) -> bool {
+    match expr {
+        Expr::Num(Num { value, .. }) => !value.to_f64().is_nan(),
+        Expr::Pair(car, cdr, ..) => is_orderable(car) && is_orderable(cdr),
+        Expr::Boolean(..)
+        | Expr::Char(..)
+        | Expr::String(..)
+        | Expr::Keyword(..)
+        | Expr::Nil
+        | Expr::Set(..) => true,
+        _ => false,
+    }
+}
+
+fn rank(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Boolean(..) => 0,
+        Expr::Num(..) => 1,
+        Expr::Char(..) => 2,
+        Expr::String(..) => 3,
+        Expr::Keyword(..) => 4,
+        Expr::Nil => 5,
+        Expr::Pair(..) => 6,
+        _ => 7,
+    }
+}
+
+fn compare(l: &Expr, r: &Expr) -> Ordering {
+    match (l, r) {
+        (Expr::Boolean(Bool { value: l, .. }), Expr::Boolean(Bool { value: r, .. })) => l.cmp(r),
+        // exact numbers come before inexact ones with the same value
+        (Expr::Num(Num { value: l, .. }), Expr::Num(Num { value: r, .. })) => l
+            .partial_cmp(r)
+            .unwrap_or(Ordering::Equal)
+            .then(r.is_exact().cmp(&l.is_exact())),
+        (Expr::Char(l, _), Expr::Char(r, _)) => l.cmp(r),
+        (Expr::String(l, _), Expr::String(r, _)) | (Expr::Keyword(l, _), Expr::Keyword(r, _)) => {
+            l.cmp(r)
+        }
+        (Expr::Pair(l_car, l_cdr, ..), Expr::Pair(r_car, r_cdr, ..)) => {
+            compare(l_car, r_car).then_with(|| compare(l_cdr, r_cdr))
+        }
+        (Expr::Set(l), Expr::Set(r)) => l.cmp(r),
+        _ => rank(l).cmp(&rank(r)),
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &MapKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &MapKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &MapKey) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    comp_err,
+    compile::{fresh_name, CompileError, MacroFn, SPECIAL_FORMS},
+    expr::Expr,
+    macro_expand::bound_names,
+    parse::SrcLoc,
+};
+
+// (introduced-identifier name scope-len) marks a name that a template
+// introduced without binding it, macro_expand decides what it refers to.
+pub const INTRODUCED: &str = "introduced-identifier";
+
+#[derive(Clone, Debug)]
+struct SyntaxRules {
+    ellipsis: String,
+    literals: Vec<String>,
+    rules: Vec<(Expr /* pattern */, Expr /* template */)>,
+    // how many names were bound where the macro was defined
+    scope_len: usize,
+}
+
+// What a pattern variable matched, variables under an ellipsis match once
+// per repetition.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(Expr),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// Splits a list into its items and what ends it, nil for proper lists.
+fn split_list(expr: &Expr) -> (Vec<Expr>, Expr) {
+    let mut items = vec![];
+    let mut rest = expr;
+    while let Expr::Pair(box item, box next, ..) = rest {
+        items.push(item.clone());
+        rest = next;
+    }
+    (items, rest.clone())
+}
+
+fn make_list_with_tail(items: Vec<Expr>, tail: Expr) -> Expr {
+    items.into_iter().rev().fold(tail, |acc, item| {
+        let srcloc = crate::compile::extract_srcloc(&item);
+        Expr::Pair(Box::new(item), Box::new(acc), srcloc)
+    })
+}
+
+fn template_binders(template: &Expr) -> Vec<String> {
+    match template {
+        Expr::Pair(box Expr::Keyword(quote, ..), ..) if quote == "quote" => vec![],
+        Expr::Pair(box l, box r, ..) => [
+            bound_names(template),
+            template_binders(l),
+            template_binders(r),
+        ]
+        .concat(),
+        _ => vec![],
+    }
+}
+
+// (define-syntax name (syntax-rules (literal...) (pattern template)...)), an
+// identifier before the literals replaces ... as the ellipsis.
+pub fn make_syntax_rules(
+    name: &str,
+    spec: &Expr,
+    scope_len: usize,
+) -> Result<MacroFn, CompileError> {
+    let syntax_rules = parse_syntax_rules(spec, scope_len)?;
+    let name = name.to_string();
+    Ok(Rc::new(move |srcloc, args, _| {
+        syntax_rules.expand_form(&name, srcloc, args)
+    }))
+}
+
+fn parse_syntax_rules(spec: &Expr, scope_len: usize) -> Result<SyntaxRules, CompileError> {
+    let (items, _) = split_list(spec);
+    let (ellipsis, literals, rules) = match items.as_slice() {
+        [Expr::Keyword(kw, ..), Expr::Keyword(ellipsis, ..), literals, rules @ ..]
+            if kw == "syntax-rules" =>
+        {
+            (ellipsis.clone(), literals, rules)
+        }
+        [Expr::Keyword(kw, ..), literals, rules @ ..] if kw == "syntax-rules" => {
+            ("...".to_string(), literals, rules)
+        }
+        _ => {
+            return comp_err!(
+                spec,
+                "define-syntax, expected (syntax-rules (literal...) rule...) but found: {}",
+                spec
+            )
+        }
+    };
+    let literals = match split_list(literals) {
+        (literals, Expr::Nil) => literals
+            .iter()
+            .map(|literal| match literal {
+                Expr::Keyword(literal, ..) => Ok(literal.clone()),
+                otherwise => comp_err!(
+                    otherwise,
+                    "syntax-rules, expected identifier as literal but found: {}",
+                    otherwise
+                ),
+            })
+            .collect::<Result<Vec<String>, CompileError>>()?,
+        _ => {
+            return comp_err!(
+                literals,
+                "syntax-rules, expected a list of literals but found: {}",
+                literals
+            )
+        }
+    };
+    let rules = rules
+        .iter()
+        .map(|rule| match split_list(rule) {
+            (rule, Expr::Nil) => match rule.as_slice() {
+                [pattern @ Expr::Pair(..), template] => Ok((pattern.clone(), template.clone())),
+                _ => comp_err!(
+                    rule.first().unwrap_or(&Expr::Nil),
+                    "syntax-rules, expected (pattern template) but found: {}",
+                    make_list_with_tail(rule.clone(), Expr::Nil)
+                ),
+            },
+            _ => comp_err!(
+                rule,
+                "syntax-rules, expected (pattern template) but found: {}",
+                rule
+            ),
+        })
+        .collect::<Result<Vec<_>, CompileError>>()?;
+    Ok(SyntaxRules {
+        ellipsis,
+        literals,
+        rules,
+        scope_len,
+    })
+}
+
+impl SyntaxRules {
+    fn is_ellipsis(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Keyword(kw, ..) if *kw == self.ellipsis)
+    }
+
+    fn expand_form(
+        &self,
+        name: &str,
+        srcloc: Option<SrcLoc>,
+        args: &[Expr],
+    ) -> Result<Expr, CompileError> {
+        let input = make_list_with_tail(args.to_vec(), Expr::Nil);
+        for (pattern, template) in self.rules.iter() {
+            // the keyword position of the pattern is ignored
+            let pattern = match pattern {
+                Expr::Pair(_, box pattern, ..) => pattern,
+                _ => continue,
+            };
+            let mut bindings = Bindings::new();
+            if self.match_pattern(pattern, &input, &mut bindings) {
+                // names the template binds are renamed so they can't capture
+                // the user's names
+                for name in template_binders(template)
+                    .into_iter()
+                    .filter(|name| *name != self.ellipsis)
+                {
+                    bindings
+                        .entry(name.clone())
+                        .or_insert_with(|| Binding::One(Expr::Keyword(fresh_name(&name), None)));
+                }
+                return self
+                    .expand_template(template, &bindings)
+                    .map_err(|message| CompileError {
+                        srcloc,
+                        message: format!("{name}: {message}"),
+                    });
+            }
+        }
+        Err(CompileError {
+            srcloc,
+            message: format!(
+                "no syntax-rules pattern of {name} matches: {}",
+                make_list_with_tail(
+                    [vec![Expr::Keyword(name.to_string(), None)], args.to_vec()].concat(),
+                    Expr::Nil
+                )
+            ),
+        })
+    }
+
+    fn match_pattern(&self, pattern: &Expr, input: &Expr, bindings: &mut Bindings) -> bool {
+        match pattern {
+            Expr::Keyword(kw, ..) if self.literals.contains(kw) => {
+                matches!(input, Expr::Keyword(input_kw, ..) if input_kw == kw)
+            }
+            Expr::Keyword(kw, ..) if kw == "_" => true,
+            Expr::Keyword(kw, ..) => {
+                bindings.insert(kw.clone(), Binding::One(input.clone()));
+                true
+            }
+            Expr::Pair(..) => self.match_list(pattern, input, bindings),
+            datum => datum == input,
+        }
+    }
+
+    // (p ... pe <ellipsis> q ... . tail), the ellipsis and the dotted tail
+    // are both optional
+    fn match_list(&self, pattern: &Expr, input: &Expr, bindings: &mut Bindings) -> bool {
+        let (mut patterns, _) = split_list(pattern);
+        let tail_pattern = match patterns.as_slice() {
+            [.., Expr::Keyword(dot, ..), _] if dot == "." => {
+                let tail_pattern = patterns.pop();
+                patterns.pop();
+                tail_pattern
+            }
+            _ => None,
+        };
+        let (items, input_tail) = split_list(input);
+        let (before, repeated, after) = match patterns.iter().position(|p| self.is_ellipsis(p)) {
+            Some(index) if index > 0 => (
+                &patterns[..index - 1],
+                Some(&patterns[index - 1]),
+                &patterns[index + 1..],
+            ),
+            _ => (patterns.as_slice(), None, &[] as &[Expr]),
+        };
+
+        let fixed_len = before.len() + after.len();
+        let repeated_len = match (repeated, &tail_pattern) {
+            (Some(_), _) if items.len() >= fixed_len => items.len() - fixed_len,
+            (None, None) if items.len() == fixed_len => 0,
+            (None, Some(_)) if items.len() >= fixed_len => 0,
+            _ => return false,
+        };
+        let (before_items, rest) = items.split_at(before.len());
+        let (repeated_items, rest) = rest.split_at(repeated_len);
+        let (after_items, tail_items) = rest.split_at(rest.len().min(after.len()));
+
+        let fixed_match = before
+            .iter()
+            .zip(before_items)
+            .chain(after.iter().zip(after_items))
+            .all(|(pattern, item)| self.match_pattern(pattern, item, bindings));
+        if !fixed_match {
+            return false;
+        }
+
+        if let Some(repeated) = repeated {
+            let mut matches = vec![];
+            for item in repeated_items {
+                let mut item_bindings = Bindings::new();
+                if !self.match_pattern(repeated, item, &mut item_bindings) {
+                    return false;
+                }
+                matches.push(item_bindings);
+            }
+            for var in self.pattern_vars(repeated) {
+                let repetitions = matches
+                    .iter_mut()
+                    .filter_map(|item_bindings| item_bindings.remove(&var))
+                    .collect();
+                bindings.insert(var, Binding::Many(repetitions));
+            }
+        }
+
+        let rest = make_list_with_tail(tail_items.to_vec(), input_tail);
+        match tail_pattern {
+            Some(tail_pattern) => self.match_pattern(&tail_pattern, &rest, bindings),
+            None => matches!(rest, Expr::Nil),
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &Expr) -> Vec<String> {
+        match pattern {
+            Expr::Keyword(kw, ..)
+                if !self.literals.contains(kw)
+                    && !self.is_ellipsis(pattern)
+                    && kw != "_"
+                    && kw != "." =>
+            {
+                vec![kw.clone()]
+            }
+            Expr::Pair(box l, box r, ..) => [self.pattern_vars(l), self.pattern_vars(r)].concat(),
+            _ => vec![],
+        }
+    }
+
+    fn expand_template(&self, template: &Expr, bindings: &Bindings) -> Result<Expr, String> {
+        match template {
+            Expr::Keyword(kw, ..) => match bindings.get(kw) {
+                Some(Binding::One(expr)) => Ok(expr.clone()),
+                Some(Binding::Many(..)) => Err(format!(
+                    "{kw} has to be followed by {} in the template",
+                    self.ellipsis
+                )),
+                None if self.is_ellipsis(template)
+                    || kw == "."
+                    || SPECIAL_FORMS.contains_key(kw) =>
+                {
+                    Ok(template.clone())
+                }
+                None => Ok(make_list_with_tail(
+                    vec![
+                        Expr::Keyword(INTRODUCED.to_string(), None),
+                        template.clone(),
+                        Expr::int(self.scope_len as i64),
+                    ],
+                    Expr::Nil,
+                )),
+            },
+            // (... template) leaves the ellipses in template as they are
+            Expr::Pair(box first, box Expr::Pair(box escaped, box Expr::Nil, ..), ..)
+                if self.is_ellipsis(first) =>
+            {
+                let escaping = SyntaxRules {
+                    ellipsis: String::new(),
+                    ..self.clone()
+                };
+                escaping.expand_template(escaped, bindings)
+            }
+            Expr::Pair(..) => {
+                let (mut items, tail) = split_list(template);
+                let mut tail = self.expand_template(&tail, bindings)?;
+                // (x . tail) splices tail in when it expands to a list
+                if let [.., Expr::Keyword(dot, ..), tail_template] = items.as_slice() {
+                    let expanded_tail = self.expand_template(tail_template, bindings)?;
+                    if dot == "." && matches!(expanded_tail, Expr::Pair(..) | Expr::Nil) {
+                        items.truncate(items.len() - 2);
+                        tail = expanded_tail;
+                    }
+                }
+                let mut expanded = vec![];
+                let mut index = 0;
+                while let Some(item) = items.get(index) {
+                    let depth = items[index + 1..]
+                        .iter()
+                        .take_while(|next| self.is_ellipsis(next))
+                        .count();
+                    if depth == 0 {
+                        expanded.push(self.expand_template(item, bindings)?);
+                    } else {
+                        expanded.extend(self.expand_repeated(item, depth, bindings)?);
+                    }
+                    index += 1 + depth;
+                }
+                Ok(make_list_with_tail(expanded, tail))
+            }
+            otherwise => Ok(otherwise.clone()),
+        }
+    }
+
+    fn expand_repeated(
+        &self,
+        template: &Expr,
+        depth: usize,
+        bindings: &Bindings,
+    ) -> Result<Vec<Expr>, String> {
+        let repeated_vars = self
+            .pattern_vars(template)
+            .into_iter()
+            .filter_map(|var| match bindings.get(&var) {
+                Some(Binding::Many(repetitions)) => Some((var, repetitions)),
+                _ => None,
+            })
+            .collect::<HashMap<String, &Vec<Binding>>>();
+        let repetitions = match repeated_vars.values().map(|r| r.len()).min() {
+            Some(min) if repeated_vars.values().all(|r| r.len() == min) => min,
+            Some(_) => {
+                return Err(format!(
+                    "the variables in {template} {} matched a different number of times",
+                    self.ellipsis
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "{template} is followed by {} but contains no variable matched under one",
+                    self.ellipsis
+                ))
+            }
+        };
+        let mut expanded = vec![];
+        for index in 0..repetitions {
+            let mut repetition_bindings = bindings.clone();
+            for (var, repetitions) in repeated_vars.iter() {
+                repetition_bindings.insert(var.clone(), repetitions[index].clone());
+            }
+            if depth > 1 {
+                expanded.extend(self.expand_repeated(template, depth - 1, &repetition_bindings)?);
+            } else {
+                expanded.push(self.expand_template(template, &repetition_bindings)?);
+            }
+        }
+        Ok(expanded)
+    }
+}
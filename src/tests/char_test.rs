@@ -0,0 +1,55 @@
+#[test]
+fn char_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(r#"(list (char? #\a) (char? "a") (char->integer #\A) (char->integer #\x3bb))"#),
+        jit_run("'(true false 65 955)")
+    );
+    assert_eq!(
+        jit_run(
+            r"(list (integer->char 97) (char-upcase #\a) (char-downcase #\Q) (char-upcase #\1))"
+        ),
+        jit_run(r"(list #\a #\A #\q #\1)")
+    );
+    assert_eq!(
+        jit_run(
+            r"(list (char->integer #\space) (char->integer #\newline) (char->integer #\tab) (char->integer #\)))"
+        ),
+        jit_run("'(32 10 9 41)")
+    );
+    assert_eq!(
+        jit_run(
+            r"(list (char-alphabetic? #\a) (char-numeric? #\7) (char-whitespace? #\space)
+                    (char-upper-case? #\a) (char-lower-case? #\a) (char-alphabetic? #\1))"
+        ),
+        jit_run("'(true true true false true false)")
+    );
+    assert_eq!(
+        jit_run(
+            r"(list (char=? #\a #\a) (char<? #\a #\b) (char>? #\a #\b) (eqv? #\x41 #\A) (= #\a #\b))"
+        ),
+        jit_run("'(true true false true false)")
+    );
+    // indexes count characters, not bytes
+    assert_eq!(
+        jit_run(r#"(list (string-ref "hello" 1) (string-ref "λx" 1))"#),
+        jit_run(r"(list #\e #\x)")
+    );
+    assert_eq!(
+        jit_run(r#"(match (string-ref "abc" 0) (#\a 'first) (_ 'other))"#),
+        jit_run("'first")
+    );
+    assert_eq!(
+        jit_run(r#"(string-ref "abc" 3)"#),
+        Err(r#"string-ref: index 3 out of range for "abc""#.to_string())
+    );
+    assert_eq!(
+        jit_run("(char->integer 1)"),
+        Err("char->integer: expected char but found: 1".to_string())
+    );
+    assert_eq!(
+        jit_run("(integer->char 55296)"),
+        Err("integer->char: expected a character code but found: 55296".to_string())
+    );
+}
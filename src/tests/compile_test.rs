@@ -1,5 +1,5 @@
 #[cfg(test)]
-use std::assert_matches::assert_matches;
+use std::assert_matches;
 
 #[cfg(test)]
 use crate::comp_err;
@@ -355,7 +355,7 @@ fn local_vars_test() {
 #[test]
 fn close_variables_test() {
     pub fn find_closed_vars_in_fn(
-        parent_scope: &Vec<String>,
+        parent_scope: &[String],
         fn_args: &Expr,
         fn_body: &Expr,
     ) -> Result<Vec<String>, CompileError> {
@@ -366,9 +366,9 @@ fn close_variables_test() {
         let child_scope = [lambda_args, locals].concat();
 
         let lambda_parent = parent_scope
-            .clone()
-            .into_iter()
+            .iter()
             .filter(|x| !child_scope.contains(x))
+            .cloned()
             .collect::<Vec<String>>();
 
         // remove the globals that exist as args
@@ -392,7 +392,7 @@ fn close_variables_test() {
                 box Expr::Keyword(lambda_kw, ..),
                 box Expr::Pair(kw_pairs, box lambda_body, ..),
                 ..,
-            )) if *lambda_kw == "lambda".to_string() => {
+            )) if lambda_kw == "lambda" => {
                 find_closed_vars_in_fn(&parent_variables, kw_pairs, lambda_body)
             }
             Some(Expr::Pair(
@@ -403,7 +403,7 @@ fn close_variables_test() {
                     ..,
                 ),
                 ..,
-            )) if *define_kw == "define".to_string() => {
+            )) if define_kw == "define" => {
                 find_closed_vars_in_fn(&parent_variables, kw_pairs, lambda_body)
             }
             Some(last) => {
@@ -502,7 +502,8 @@ fn close_variables_test() {
             srcloc: Some(SrcLoc {
                 line: 6,
                 column: 16,
-                file_name: Some("close_test".to_string())
+                file_name: Some("close_test".to_string()),
+                expanded_from: None,
             }),
             message: "not_defined is not defined".to_string()
         })
@@ -0,0 +1,226 @@
+#[test]
+fn begin_when_unless_test() {
+    use crate::expr::Expr;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    assert_eq!(jit_run("(begin 1 2 3)"), Ok(Expr::num(3.0)));
+    assert_eq!(
+        jit_run("(begin (define a 1) (define b 2)) (+ a b)"),
+        Ok(Expr::num(3.0))
+    );
+    assert_eq!(
+        jit_run("(define (f) (begin (define a 1)) a) (f)"),
+        Ok(Expr::num(1.0))
+    );
+    assert_eq!(
+        jit_run_vm("(begin (display 1) (display 2))").map(|vm| vm.log),
+        Ok(vec!["1".to_string(), "2".to_string()])
+    );
+    assert_eq!(
+        jit_run("(when (> 2 1) 'a 'b)"),
+        Ok(Expr::Keyword("b".to_string(), None))
+    );
+    assert_eq!(jit_run("(when (< 2 1) 'a)"), Ok(Expr::Nil));
+    assert_eq!(
+        jit_run("(unless (< 2 1) 'a 'b)"),
+        Ok(Expr::Keyword("b".to_string(), None))
+    );
+    assert_eq!(jit_run("(unless (> 2 1) 'a)"), Ok(Expr::Nil));
+}
+
+#[test]
+fn case_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    let symbol = |name: &str| Ok(Expr::Keyword(name.to_string(), None));
+    assert_eq!(
+        jit_run(
+            "
+            (case (* 2 3)
+              ((2 3 5 7) 'prime)
+              ((1 4 6 8 9) 'composite))"
+        ),
+        symbol("composite")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (classify x)
+              (case x
+                ((a e i o u) 'vowel)
+                ((w y) 'semivowel)
+                (else 'consonant)))
+            (list (classify 'e) (classify 'y) (classify 'k))"
+        ),
+        jit_run("'(vowel semivowel consonant)")
+    );
+    assert_eq!(
+        jit_run("(case 5 ((5) => (lambda (x) (* x 2))) (else 0))"),
+        Ok(Expr::num(10.0))
+    );
+    assert_eq!(
+        jit_run("(case 'x ((a) 1) (else => (lambda (s) s)))"),
+        symbol("x")
+    );
+    assert_eq!(jit_run("(case 1 ((2) 'two))"), Ok(Expr::Nil));
+    assert_eq!(
+        jit_run("(case 1 (else 1) ((1) 2))"),
+        Err("jit_run_vm:1:10: case, else has to be the last clause".to_string())
+    );
+    assert_eq!(jit_run("(eqv? 'a 'a)"), Ok(Expr::bool(true)));
+    assert_eq!(jit_run("(eqv? 1 2)"), Ok(Expr::bool(false)));
+    assert_eq!(
+        jit_run("(eqv? '(1) '(1))"),
+        Err("eqv?: pairs have no identity to compare, use equal? instead: (1)".to_string())
+    );
+    assert_eq!(
+        jit_run("(case (list 1) (((1)) 'one))"),
+        Err("case: pairs have no identity to compare, use equal? instead: (1)".to_string())
+    );
+    // procedures are eqv? to themselves
+    assert_eq!(
+        jit_run(
+            "(define (f x) x)
+             (define (make-adder n) (lambda (x) (+ x n)))
+             (list (eqv? f f) (eqv? f (lambda (x) (+ x 0))) (eqv? car car) (eqv? (make-adder 1) (make-adder 1)))"
+        ),
+        jit_run("'(true false true false)")
+    );
+}
+
+#[test]
+fn do_test() {
+    use crate::expr::Expr;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    assert_eq!(
+        jit_run("(do ((i 0 (+ i 1)) (acc '() (cons i acc))) ((= i 3) acc))"),
+        jit_run("'(2 1 0)")
+    );
+    assert_eq!(
+        jit_run_vm("(do ((i 0 (+ i 1))) ((= i 3)) (display i))").map(|vm| vm.log),
+        Ok(vec!["0".to_string(), "1".to_string(), "2".to_string()])
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (sum-to n)
+              (do ((i 0 (+ i 1))
+                   (sum 0 (+ sum i))
+                   (limit n))
+                ((> i limit) sum)))
+            (sum-to 10000)"
+        ),
+        Ok(Expr::num(50005000.0))
+    );
+}
+
+#[test]
+fn compiles_to_jumps_test() {
+    use crate::vm::{prepare_vm, VMInstruction};
+
+    fn compile(source: &str) -> Vec<VMInstruction> {
+        let (vm, _) = prepare_vm(
+            &crate::parse::ParseInput {
+                source,
+                file_name: None,
+            },
+            None,
+        )
+        .unwrap();
+        vm.callframes.first().unwrap().chunk.code.clone()
+    }
+    fn makes_lambda(code: &[VMInstruction]) -> bool {
+        code.iter()
+            .any(|instruction| matches!(instruction, VMInstruction::MakeLambda(..)))
+    }
+
+    assert!(!makes_lambda(&compile("(begin 1 2)")));
+    assert!(!makes_lambda(&compile("(when 1 2)")));
+    assert!(!makes_lambda(&compile("(case 1 ((1) 2) (else 3))")));
+    assert!(!makes_lambda(&compile("(do ((i 0 (+ i 1))) ((= i 3) i))")));
+
+    // the named let loops through when and case in tail position
+    let code = compile(
+        "
+        (let loop ((i 0))
+          (when (< i 10)
+            (case i
+              ((5) (loop (+ i 2)))
+              (else (loop (+ i 1))))))",
+    );
+    assert!(!makes_lambda(&code));
+    assert!(code.contains(&VMInstruction::Recur("loop".to_string())));
+
+    let code = compile(
+        "
+        (let loop ((i 0))
+          (cond ((> i 10) i)
+                ((= i 5) (loop (+ i 2)))
+                (else (and true (loop (+ i 1))))))",
+    );
+    assert!(!makes_lambda(&code));
+    assert!(code.contains(&VMInstruction::Recur("loop".to_string())));
+}
+
+#[test]
+fn and_or_test() {
+    use crate::expr::Expr;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    assert_eq!(jit_run("(and)"), Ok(Expr::bool(true)));
+    assert_eq!(jit_run("(or)"), Ok(Expr::bool(false)));
+    assert_eq!(jit_run("(and 1)"), Ok(Expr::num(1.0)));
+    assert_eq!(jit_run("(and 1 2 3)"), Ok(Expr::num(3.0)));
+    assert_eq!(jit_run("(and 1 false 3)"), Ok(Expr::bool(false)));
+    assert_eq!(jit_run("(or false 2 3)"), Ok(Expr::num(2.0)));
+    assert_eq!(jit_run("(or false false false)"), Ok(Expr::bool(false)));
+    // evaluation stops at the first operand that decides the result
+    assert_eq!(
+        jit_run_vm("(and (display 1) 0 (display 2))").map(|vm| vm.log),
+        Ok(vec!["1".to_string()])
+    );
+    assert_eq!(
+        jit_run_vm("(or false (begin (display 1) 1) (display 2))").map(|vm| vm.log),
+        Ok(vec!["1".to_string()])
+    );
+}
+
+#[test]
+fn cond_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(cond (false 1) ((= 1 1) 2 3) (else 4))"),
+        Ok(Expr::num(3.0))
+    );
+    assert_eq!(jit_run("(cond (false 1) (else 4))"), Ok(Expr::num(4.0)));
+    assert_eq!(jit_run("(cond (false 1))"), Ok(Expr::Nil));
+    // a clause without expressions returns the value of its test
+    assert_eq!(jit_run("(cond (false) (5) (else 4))"), Ok(Expr::num(5.0)));
+    assert_eq!(
+        jit_run("(cond ((memq 'b '(a b c)) => cdr) (else false))"),
+        jit_run("'(c)")
+    );
+    assert_eq!(
+        jit_run("(cond ((memq 'd '(a b c)) => cdr) (else 'none))"),
+        Ok(Expr::Keyword("none".to_string(), None))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (sign x)
+              (cond ((< x 0) 'negative)
+                    ((> x 0) 'positive)
+                    (else 'zero)))
+            (list (sign -2) (sign 0) (sign 3))"
+        ),
+        jit_run("'(negative zero positive)")
+    );
+    assert_eq!(
+        jit_run("(cond (else 1) (true 2))"),
+        Err("jit_run_vm:1:8: cond, else has to be the last clause".to_string())
+    );
+}
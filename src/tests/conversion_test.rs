@@ -0,0 +1,63 @@
+#[test]
+fn symbol_conversion_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            r#"(list (symbol->string 'abc) (string->symbol "abc") (eqv? (string->symbol "x") 'x))"#
+        ),
+        jit_run(r#"'("abc" abc true)"#)
+    );
+    // building new names, as in SICP's symbolic exercises
+    assert_eq!(
+        jit_run(
+            r#"(string->symbol (string-append (symbol->string 'make-) (symbol->string 'point)))"#
+        ),
+        jit_run("'make-point")
+    );
+    assert_eq!(
+        jit_run(r#"(symbol->string "abc")"#),
+        Err("symbol->string: expected symbol but found: abc".to_string())
+    );
+}
+
+#[test]
+fn number_conversion_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (number->string 42) (number->string 255 16) (number->string -5 2) (number->string 1/3 3) (number->string 2.5))"),
+        jit_run(r#"'("42" "ff" "-101" "1/10" "2.5")"#)
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string->number "42") (string->number "ff" 16) (string->number "-101" 2) (string->number "1/3") (string->number "2.5e1"))"#
+        ),
+        jit_run("(list 42 255 -5 1/3 25.0)")
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (exact? (string->number "10")) (exact? (string->number "10.0")) (string->number "+inf.0"))"#
+        ),
+        jit_run("(list true false (/ 1 0.0))")
+    );
+    // false on failure
+    assert_eq!(
+        jit_run(
+            r#"(list (string->number "abc") (string->number "12" 2) (string->number "") (string->number "1/0") (string->number "inf") (string->number "1_0" 16))"#
+        ),
+        jit_run("'(false false false false false false)")
+    );
+    assert_eq!(
+        jit_run("(string->number (number->string 123456789012345678901234567890 36) 36)"),
+        jit_run("123456789012345678901234567890")
+    );
+    assert_eq!(
+        jit_run("(number->string 1.5 2)"),
+        Err("number->string: 1.5 can only be written in radix 10".to_string())
+    );
+    assert_eq!(
+        jit_run(r#"(string->number "1" 37)"#),
+        Err("string->number: expected a radix from 2 to 36 but found: 37".to_string())
+    );
+}
@@ -0,0 +1,116 @@
+#[test]
+fn dynamic_wind_test() {
+    use crate::expr::Expr;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    assert_eq!(
+        jit_run_vm(
+            r#"
+            (dynamic-wind
+              (lambda () (display "before"))
+              (lambda () (display "during"))
+              (lambda () (display "after")))
+            "#
+        )
+        .map(|vm| vm.log),
+        Ok(vec![
+            "before".to_string(),
+            "during".to_string(),
+            "after".to_string()
+        ])
+    );
+
+    assert_eq!(
+        jit_run("(dynamic-wind (lambda () 1) (lambda () 2) (lambda () 3))"),
+        Ok(Expr::num(2.0))
+    );
+}
+
+#[test]
+fn dynamic_wind_runs_after_thunk_on_error() {
+    use crate::vm::{get_prelude, prepare_vm, run};
+
+    let (mut vm, _) = get_prelude()
+        .and_then(|env| {
+            prepare_vm(
+                &crate::parse::ParseInput {
+                    source: r#"
+                    (define (cleanup) (display "cleanup"))
+                    (dynamic-wind
+                      (lambda () (display "outer before"))
+                      (lambda ()
+                        (dynamic-wind
+                          (lambda () (display "inner before"))
+                          (lambda () (car 1))
+                          cleanup))
+                      (lambda () (display "outer after")))
+                    "#,
+                    file_name: Some("dynamic_wind_test"),
+                },
+                Some(env),
+            )
+        })
+        .unwrap();
+
+    assert_eq!(run(&mut vm), Err("car expected pair, found: 1".to_string()));
+    assert_eq!(
+        vm.log,
+        vec!["outer before", "inner before", "cleanup", "outer after"]
+    );
+    assert!(vm.winders.is_empty());
+}
+
+#[test]
+fn parameterize_test() {
+    use crate::expr::Expr;
+    use crate::parse::make_pair_from_vec;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define p (make-parameter 10))
+            (define (get-p) (p))
+            (list (p) (parameterize ((p 20)) (get-p)) (p))
+            "
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(10.0),
+            Expr::num(20.0),
+            Expr::num(10.0)
+        ]))
+    );
+
+    assert_eq!(
+        jit_run(
+            "
+            (define p (make-parameter 1 (lambda (x) (* x 2))))
+            (list
+              (p)
+              (parameterize ((p 5))
+                (parameterize ((p 7)) (p))))
+            "
+        ),
+        Ok(make_pair_from_vec(vec![Expr::num(2.0), Expr::num(14.0)]))
+    );
+
+    // both are procedures too
+    assert_eq!(
+        jit_run(
+            "
+            (define dw dynamic-wind)
+            (define log (make-vector 1 '()))
+            (define (note x) (vector-set! log 0 (cons x (vector-ref log 0))))
+            (dw (lambda () (note 'before)) (lambda () (note 'during)) (lambda () (note 'after)))
+            (define params (map make-parameter '(1 2)))
+            (list (reverse (vector-ref log 0)) (map (lambda (p) (p)) params))
+            "
+        ),
+        jit_run("'((before during after) (1 2))")
+    );
+
+    assert_eq!(
+        jit_run("(parameterize ((1 2)) 3)"),
+        Err("parameterize: expected parameter, found: 1".to_string())
+    );
+}
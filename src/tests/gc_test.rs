@@ -77,11 +77,9 @@ fn gc_test() {
         .unwrap();
     let mut cycles_left = 100000 / 3;
 
-    fn find_envs(expr: &Expr, vec: &mut Vec<usize>) -> () {
+    #[allow(dead_code)]
+    fn find_envs(expr: &Expr, vec: &mut Vec<usize>) {
         match expr {
-            Expr::Quote(box expr, ..) => {
-                find_envs(expr, vec);
-            }
             Expr::Pair(l, r, ..) => {
                 find_envs(l, vec);
                 find_envs(r, vec);
@@ -126,9 +124,9 @@ fn gc_test() {
     //         .for_each(|expr| find_envs(expr, &mut expr_refs_in_envs));
     // });
 
-    assert_eq!(vm.callframes.len(), 33);
+    assert_eq!(vm.callframes.len(), 13);
     // assert_eq!(expr_refs_in_envs.len(), 704);
     // assert_eq!(lambda_refs.len(), 0);
     assert_eq!(cycles_left, 0);
-    assert_eq!(5200, vm.heap.len());
+    assert_eq!(5169, vm.heap.len());
 }
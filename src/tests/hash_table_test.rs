@@ -0,0 +1,110 @@
+#[test]
+fn hash_table_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "(define table (make-hash-table))
+             (hash-table-set! table 'a 1)
+             (hash-table-set! table \"b\" 2)
+             (hash-table-set! table '(1 2) 3)
+             (hash-table-set! table 'a 4)
+             (list (hash-table-ref table 'a) (hash-table-ref table \"b\") (hash-table-ref table (list 1 2))
+                   (hash-table-ref table 'c (lambda () 0)) (hash-table-ref/default table 'a 0)
+                   (hash-table-ref/default table 'c 0) (hash-table-count table))"
+        ),
+        jit_run("'(4 2 3 0 4 0 3)")
+    );
+    assert_eq!(
+        jit_run(
+            "(define table (make-hash-table))
+             (hash-table-set! table 1 'one)
+             (hash-table-set! table 2 'two)
+             (hash-table-delete! table 1)
+             (hash-table-delete! table 3)
+             (list (hash-table-contains? table 1) (hash-table-contains? table 2)
+                   (hash-table-keys table) (hash-table-values table) (hash-table->alist table))"
+        ),
+        jit_run("(list false true '(2) '(two) (list (cons 2 'two)))")
+    );
+    // eqv? tables tell apart vectors with the same contents, and 1 from 1.0
+    assert_eq!(
+        jit_run(
+            "(define v #(1 2))
+             (define eqv-table (make-hash-table eqv?))
+             (define equal-table (make-hash-table equal?))
+             (hash-table-set! eqv-table v 'v)
+             (hash-table-set! equal-table v 'v)
+             (hash-table-set! eqv-table 1 'exact)
+             (list (hash-table-ref eqv-table v) (hash-table-ref/default eqv-table (vector 1 2) 'none)
+                   (hash-table-ref equal-table (vector 1 2)) (hash-table-ref/default eqv-table 1.0 'none)
+                   (hash-table-ref eqv-table 2/2))"
+        ),
+        jit_run("'(v none v none exact)")
+    );
+    // procedures are found again, pairs can't be told apart by eqv?
+    assert_eq!(
+        jit_run(
+            "(define h (make-hash-table eqv?))
+             (define (f x) x)
+             (hash-table-set! h f 'f)
+             (hash-table-set! h car 'car)
+             (list (hash-table-ref h f) (hash-table-ref h car) (hash-table-count h))"
+        ),
+        jit_run("'(f car 2)")
+    );
+    assert_eq!(
+        jit_run("(define h (make-hash-table eqv?)) (hash-table-set! h (list 1) 1)"),
+        Err(
+            "hash-table-set!: pairs have no identity to compare, use equal? instead: (1)"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        jit_run(
+            "(define counts (make-hash-table))
+             (define (count word) (hash-table-update! counts word (lambda (n) (+ n 1)) (lambda () 0)))
+             (count 'a) (count 'b) (count 'a)
+             (define total (make-vector 1 0))
+             (hash-table-walk counts (lambda (key n) (vector-set! total 0 (+ n (vector-ref total 0)))))
+             (list (hash-table-ref counts 'a) (hash-table-ref counts 'b) (vector-ref total 0))"
+        ),
+        jit_run("'(2 1 3)")
+    );
+    // memoization
+    assert_eq!(
+        jit_run(
+            "(define memo (make-hash-table))
+             (define (fib n)
+               (if (< n 2)
+                 n
+                 (if (hash-table-contains? memo n)
+                   (hash-table-ref memo n)
+                   (begin
+                     (hash-table-set! memo n (+ (fib (- n 1)) (fib (- n 2))))
+                     (hash-table-ref memo n)))))
+             (fib 80)"
+        ),
+        jit_run("23416728348467685")
+    );
+    assert_eq!(
+        jit_run("(list (equal? '(1 #(2 \"x\")) (list 1 (vector 2 \"x\"))) (equal? 1 1.0) (equal? '(1) '(2)))"),
+        jit_run("'(true false false)")
+    );
+    assert_eq!(
+        jit_run("(hash-table-ref (make-hash-table) 'missing)"),
+        Err("unknown: hash-table-ref: no value for key missing".to_string())
+    );
+    assert_eq!(
+        jit_run(
+            "(define h (make-hash-table))
+             (hash-table-update! h 'a (lambda (x) x) (lambda () 0))
+             (hash-table-ref h 'a (lambda () (error \"not called\")))"
+        ),
+        jit_run("0")
+    );
+    assert_eq!(
+        jit_run("(hash-table-set! '() 1 2)"),
+        Err("hash-table-set!: expected hash table but found: '()".to_string())
+    );
+}
@@ -0,0 +1,99 @@
+#[test]
+fn promise_test() {
+    use crate::expr::Expr;
+    use crate::parse::make_pair_from_vec;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    assert_eq!(jit_run("(force (delay (+ 1 2)))"), Ok(Expr::num(3.0)));
+    assert_eq!(jit_run("(force 5)"), Ok(Expr::num(5.0)));
+    assert_eq!(jit_run("(force (make-promise 5))"), Ok(Expr::num(5.0)));
+    assert_eq!(jit_run("(promise? (delay 1))"), Ok(Expr::bool(true)));
+    assert_eq!(jit_run("(promise? 1)"), Ok(Expr::bool(false)));
+
+    // the thunk only runs once
+    assert_eq!(
+        jit_run_vm(
+            r#"
+            (define p (delay (display "forced")))
+            (force p)
+            (force p)
+            "#
+        )
+        .map(|vm| vm.log),
+        Ok(vec!["forced".to_string()])
+    );
+
+    assert_eq!(
+        jit_run(
+            "
+            (define (loop n)
+              (if (= n 0)
+                (delay 'done)
+                (delay-force (loop (- n 1)))))
+            (list (force (loop 1000)) (force (delay-force (delay 1))))
+            "
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::Keyword("done".to_string(), None),
+            Expr::num(1.0)
+        ]))
+    );
+    // a delay-force is the promise it forces, that one is forced once only
+    assert_eq!(
+        jit_run(
+            "
+            (define count (vector 0))
+            (define inner (delay (begin (vector-set! count 0 (+ 1 (vector-ref count 0))) 'x)))
+            (define outer (delay-force inner))
+            (list (force outer) (force inner) (vector-ref count 0))"
+        ),
+        jit_run("'(x x 1)")
+    );
+    assert_eq!(
+        jit_run("(map force (list (delay 1) (delay (+ 1 1)) 3))"),
+        jit_run("'(1 2 3)")
+    );
+}
+
+#[test]
+fn stream_test() {
+    use crate::expr::Expr;
+    use crate::parse::make_pair_from_vec;
+    use crate::vm::jit_run;
+
+    let integers = "
+        (define (integers-starting-from n)
+          (cons-stream n (integers-starting-from (+ n 1))))
+        (define integers (integers-starting-from 1))
+        ";
+
+    assert_eq!(
+        jit_run(&format!("{integers} (stream-ref integers 500)")),
+        Ok(Expr::num(501.0))
+    );
+    assert_eq!(
+        jit_run(&format!(
+            "{integers}
+            (define (stream-map proc s)
+              (if (stream-null? s)
+                the-empty-stream
+                (cons-stream (proc (stream-car s)) (stream-map proc (stream-cdr s)))))
+            (define doubled (stream-map (lambda (x) (* x 2)) integers))
+            (list (stream-ref doubled 0) (stream-ref doubled 1) (stream-ref doubled 10))"
+        )),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(2.0),
+            Expr::num(4.0),
+            Expr::num(22.0)
+        ]))
+    );
+    // cons-stream conses with the global cons
+    assert_eq!(
+        jit_run("(define (f cons) (stream-car (cons-stream 1 2))) (f 0)"),
+        Ok(Expr::num(1.0))
+    );
+    assert_eq!(
+        jit_run(&format!("{integers} (to-string integers)")),
+        Ok(Expr::String("(1 . #<promise>)".to_string(), None))
+    );
+}
@@ -0,0 +1,147 @@
+#[test]
+fn let_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(jit_run("(let ((x 1) (y 2)) (+ x y))"), Ok(Expr::num(3.0)));
+    // the inits are evaluated before any of the names are bound
+    assert_eq!(
+        jit_run("(define x 1) (let ((x 2) (y x)) y)"),
+        Ok(Expr::num(1.0))
+    );
+    // shadowed bindings are back once the let is done
+    assert_eq!(
+        jit_run("(define x 1) (+ (let ((x 10)) x) x)"),
+        Ok(Expr::num(11.0))
+    );
+    assert_eq!(
+        jit_run("(let ((z 1)) z) z"),
+        Err("jit_run_vm:1:17: z is not defined".to_string())
+    );
+    assert_eq!(
+        jit_run("(let () (define x 2) (define y 3) (* x y))"),
+        Ok(Expr::num(6.0))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (make-adder n)
+              (let ((m n))
+                (lambda (x) (+ x m))))
+            ((make-adder 2) 3)"
+        ),
+        Ok(Expr::num(5.0))
+    );
+    assert_eq!(
+        jit_run("(let ((x)) x)"),
+        Err("jit_run_vm:1:8: let, expected (name expr) but found: (x)".to_string())
+    );
+}
+
+#[test]
+fn let_star_and_letrec_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(let* ((x 1) (y (+ x 1)) (x (* y 10))) (+ x y))"),
+        Ok(Expr::num(22.0))
+    );
+    assert_eq!(jit_run("(let* () 5)"), Ok(Expr::num(5.0)));
+    assert_eq!(
+        jit_run(
+            "
+            (letrec ((even? (lambda (n) (if (= n 0) true (odd? (- n 1)))))
+                     (odd? (lambda (n) (if (= n 0) false (even? (- n 1))))))
+              (even? 100))"
+        ),
+        Ok(Expr::bool(true))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f)
+              (letrec* ((a 1) (b (+ a 1)))
+                (lambda () (+ a b))))
+            ((f))"
+        ),
+        Ok(Expr::num(3.0))
+    );
+}
+
+#[test]
+fn named_let_test() {
+    use crate::expr::Expr;
+    use crate::parse::make_pair_from_vec;
+    use crate::vm::{jit_run, prepare_vm, VMInstruction};
+
+    assert_eq!(
+        jit_run(
+            "
+            (let loop ((i 0) (acc 0))
+              (if (> i 10000)
+                acc
+                (loop (+ i 1) (+ acc i))))"
+        ),
+        Ok(Expr::num(50005000.0))
+    );
+
+    // only tail calls, so no procedure is made for the loop
+    let (vm, _) = prepare_vm(
+        &crate::parse::ParseInput {
+            source: "(let loop ((i 0)) (if (> i 10) i (loop (+ i 1))))",
+            file_name: None,
+        },
+        None,
+    )
+    .unwrap();
+    let code = &vm.callframes.first().unwrap().chunk.code;
+    assert!(code.contains(&VMInstruction::Recur("loop".to_string())));
+    assert!(!code
+        .iter()
+        .any(|instruction| matches!(instruction, VMInstruction::MakeLambda(..))));
+
+    // every iteration gets fresh bindings
+    assert_eq!(
+        jit_run(
+            "
+            (let loop ((i 0) (thunks '()))
+              (if (= i 3)
+                (map (lambda (thunk) (thunk)) thunks)
+                (loop (+ i 1) (cons (lambda () i) thunks))))"
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(2.0),
+            Expr::num(1.0),
+            Expr::num(0.0)
+        ]))
+    );
+
+    // not a tail call, so the loop is a procedure
+    assert_eq!(
+        jit_run(
+            "
+            (let build ((i 0))
+              (if (= i 3)
+                '()
+                (cons i (build (+ i 1)))))"
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(0.0),
+            Expr::num(1.0),
+            Expr::num(2.0)
+        ]))
+    );
+
+    // the loop's tail calls are compiled by a form programs can't call
+    assert_eq!(
+        jit_run("(define (named-let#recur x) x) (named-let#recur 5)"),
+        Ok(Expr::num(5.0))
+    );
+
+    // the name is not visible in the inits
+    assert_eq!(
+        jit_run("(define loop 5) (let loop ((i loop)) (if (= i 0) 0 (loop (- i 1))))"),
+        Ok(Expr::num(0.0))
+    );
+}
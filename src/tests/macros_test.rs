@@ -46,6 +46,359 @@ fn macro_definition_doesnt_leak_out_of_scope() {
             (add)
             "
         ),
-        Err("jit_run_vm:2:25: defmacro is not defined".to_string())
+        Err(
+            "jit_run_vm:3:34: three is not defined\n  in expansion of macro add at jit_run_vm:4:14"
+                .to_string()
+        )
     )
 }
+
+#[test]
+fn scoped_macros_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (defmacro (twice e) `(+ ,e ,e))
+              (twice x))
+            (f 3)"
+        ),
+        Ok(Expr::num(6.0))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (define-syntax twice (syntax-rules () ((_ e) (+ e e))))
+              (let ((y (twice x)))
+                (lambda () (twice y))))
+            ((f 3))"
+        ),
+        Ok(Expr::num(12.0))
+    );
+    // a macro defined in a body shadows an outer one only in that body
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax m (syntax-rules () ((_) 'outer)))
+            (define (f)
+              (define-syntax m (syntax-rules () ((_) 'inner)))
+              (m))
+            (list (f) (m))"
+        ),
+        jit_run("'(inner outer)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (let ()
+              (define-syntax m (syntax-rules () ((_) 1)))
+              (m))
+            (m)"
+        ),
+        Err("jit_run_vm:5:14: m is not defined".to_string())
+    );
+    // the names a local macro introduces mean what they meant where it was
+    // defined
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (define-syntax get-x (syntax-rules () ((_) x)))
+              (define (g) (get-x))
+              (g))
+            (f 7)"
+        ),
+        Ok(Expr::num(7.0))
+    );
+}
+
+#[test]
+fn let_syntax_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (let-syntax ((double (syntax-rules () ((_ e) (* 2 e)))))
+              (double 4))"
+        ),
+        Ok(Expr::num(8.0))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f)
+              (let-syntax ((double (syntax-rules () ((_ e) (* 2 e)))))
+                (double 4)))
+            (f)
+            (double 1)"
+        ),
+        Err("jit_run_vm:6:14: double is not defined".to_string())
+    );
+    // in let-syntax the other macros' names mean the outer macros
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax m (syntax-rules () ((_) 'outer)))
+            (let-syntax ((m (syntax-rules () ((_) 'inner)))
+                         (n (syntax-rules () ((_) (m)))))
+              (list (m) (n)))"
+        ),
+        jit_run("'(inner outer)")
+    );
+    // in letrec-syntax they mean each other
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax m (syntax-rules () ((_) 'outer)))
+            (letrec-syntax ((m (syntax-rules () ((_) 'inner)))
+                            (n (syntax-rules () ((_) (m)))))
+              (list (m) (n)))"
+        ),
+        jit_run("'(inner inner)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (letrec-syntax
+                ((my-and (syntax-rules ()
+                           ((_) true)
+                           ((_ e) e)
+                           ((_ e r ...) (if e (my-and r ...) false)))))
+              (list (my-and) (my-and 1 2) (my-and 1 false 3)))"
+        ),
+        jit_run("'(true 2 false)")
+    );
+}
+
+#[test]
+fn gensym_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(jit_run("(= (gensym) (gensym))"), Ok(Expr::bool(false)));
+    assert_eq!(
+        jit_run("(= (generate-temporary 'x) (generate-temporary 'x))"),
+        Ok(Expr::bool(false))
+    );
+    // a temporary from gensym can't capture the caller's variable
+    assert_eq!(
+        jit_run(
+            "
+            (defmacro (add-twice a b)
+              (define tmp (gensym \"tmp\"))
+              `(let ((,tmp ,a)) (+ ,tmp ,tmp ,b)))
+            (define tmp 1)
+            (add-twice 2 tmp)
+            "
+        ),
+        Ok(Expr::num(5.0))
+    );
+}
+
+#[test]
+fn expansion_time_environment_test() {
+    use crate::expr::Expr;
+    use crate::vm::{jit_run, jit_run_vm};
+
+    // the prelude is there while expanding
+    assert_eq!(
+        jit_run(
+            "
+            (defmacro (sum-squares . xs)
+              (fold-right (lambda (x acc) `(+ (* ,x ,x) ,acc)) 0 xs))
+            (sum-squares 1 2 3)"
+        ),
+        Ok(Expr::num(14.0))
+    );
+    assert_eq!(
+        jit_run_vm("(dprint \"x:\" (+ 1 2))").map(|vm| vm.log),
+        Ok(vec![
+            "===dprint===".to_string(),
+            "x:".to_string(),
+            "(+ 1 2) = 3".to_string(),
+            "=========".to_string()
+        ])
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-for-syntax (square x) (* x x))
+            (define-for-syntax ten 10)
+            (defmacro (square-at-compile-time n) (+ ten (square n)))
+            (square-at-compile-time 4)"
+        ),
+        Ok(Expr::num(26.0))
+    );
+    // definitions of one phase are not visible in the other
+    assert_eq!(
+        jit_run(
+            "
+            (define-for-syntax (square x) (* x x))
+            (square 2)"
+        ),
+        Err("jit_run_vm:3:14: square is not defined".to_string())
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (square x) (* x x))
+            (defmacro (square-at-compile-time n) (square n))
+            (square-at-compile-time 4)"
+        ),
+        Err("jit_run_vm:3:51: square is not defined".to_string())
+    );
+    assert_eq!(
+        jit_run("(define (f) (define-for-syntax x 1) x)"),
+        Err("jit_run_vm:1:32: define-for-syntax is only allowed at the top level".to_string())
+    );
+}
+
+#[test]
+fn macro_definition_errors_test() {
+    use crate::vm::jit_run;
+
+    // reported where the macro is defined, even if it is never used
+    assert_eq!(
+        jit_run("(defmacro (m a . b c) a) 1"),
+        Err("jit_run_vm:1:23: rest-dot can only occur as second-to-last argument, but found: (a . b c)".to_string())
+    );
+    assert_eq!(
+        jit_run("(defmacro (m a) (undefined-fn a)) 1"),
+        Err("jit_run_vm:1:18: undefined-fn is not defined".to_string())
+    );
+    assert_eq!(
+        jit_run("(defmacro (m a . rest) a) (m)"),
+        Err(
+            "jit_run_vm:1:29: macro wrong number of args, expected at least 1 (a), got: ()"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        jit_run("(defmacro (m a b) a) (m 1)"),
+        Err("jit_run_vm:1:25: macro wrong number of args, expected 2 (a b), got: (1)".to_string())
+    );
+}
+
+#[test]
+fn macroexpand_test() {
+    use crate::vm::jit_run;
+
+    let defs = "
+        (defmacro (swap-args f a b) (list f b a))
+        (defmacro (twice e) (list 'begin e e))";
+    // macroexpand-1 takes a single step and leaves the arguments alone
+    assert_eq!(
+        jit_run(&format!(
+            "{defs} (macroexpand-1 '(twice (swap-args - 1 2)))"
+        )),
+        jit_run("'(begin (swap-args - 1 2) (swap-args - 1 2))")
+    );
+    assert_eq!(
+        jit_run(&format!("{defs} (macroexpand-1 '(+ 1 2))")),
+        jit_run("'(+ 1 2)")
+    );
+    assert_eq!(
+        jit_run(&format!("{defs} (macroexpand '(twice (swap-args - 1 2)))")),
+        jit_run("'(begin (- 2 1) (- 2 1))")
+    );
+    assert_eq!(
+        jit_run(&format!(
+            "{defs} (macroexpand-all '(list (twice 1) (swap-args - 1 2)))"
+        )),
+        jit_run("'(list (begin 1 1) (- 2 1))")
+    );
+    assert_eq!(
+        jit_run("(macroexpand '(+ 1 2))"),
+        Err("jit_run_vm:1:14: macro not found: +".to_string())
+    );
+    assert_eq!(
+        jit_run("(macroexpand-1 (+ 1 2))"),
+        Err("jit_run_vm:1:16: can't call macroexpand-1 on ((+ 1 2))".to_string())
+    );
+}
+
+#[test]
+fn trace_expansion_test() {
+    use crate::parse::ParseInput;
+    use crate::vm::{get_prelude, trace_expansion};
+
+    let trace = trace_expansion(
+        &ParseInput {
+            source: "(defmacro (twice e) (list 'begin e e))\n(twice (twice 1))",
+            file_name: Some("trace"),
+        },
+        get_prelude().ok(),
+    )
+    .unwrap();
+    let steps: Vec<_> = trace
+        .iter()
+        .map(|step| {
+            format!(
+                "{} {}: {} => {}",
+                step.srcloc.as_ref().unwrap(),
+                step.macro_name,
+                step.input,
+                step.output
+            )
+        })
+        .collect();
+    // the arguments are expanded before the macro call
+    assert_eq!(
+        steps,
+        vec![
+            "trace:2:15 twice: (twice 1) => (begin 1 1)",
+            "trace:2:8 twice: (twice (begin 1 1)) => (begin (begin 1 1) (begin 1 1))",
+        ]
+    );
+}
+
+#[test]
+fn expansion_srcloc_test() {
+    use crate::vm::jit_run;
+
+    // what a macro produced points back at where the macro is used
+    assert_eq!(
+        jit_run(
+            "
+            (defmacro (empty-if) (list 'if))
+            (+ 1 (empty-if))"
+        ),
+        Err(
+            "jit_run_vm:2:41: if, expected pred, cons, alt but found: '()\n  in expansion of macro empty-if at jit_run_vm:3:19"
+                .to_string()
+        )
+    );
+    // the user's code in the arguments keeps its location
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax twice (syntax-rules () ((_ e) (begin e e))))
+            (twice (+ 1 oops))"
+        ),
+        Err("jit_run_vm:3:25: oops is not defined".to_string())
+    );
+    // and a chain leads from nested expansions back to the user's code
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax inner (syntax-rules () ((_) (undefined-fn))))
+            (define-syntax outer (syntax-rules () ((_) (list (inner)))))
+            (outer)"
+        ),
+        Err("jit_run_vm:2:57: undefined-fn is not defined
+  in expansion of macro inner at jit_run_vm:3:63
+  in expansion of macro outer at jit_run_vm:4:14"
+            .to_string())
+    );
+    // errors about () are reported at the form around it
+    assert_eq!(
+        jit_run("(+ 1\n (if))"),
+        Err("jit_run_vm:2:3: if, expected pred, cons, alt but found: '()".to_string())
+    );
+}
@@ -0,0 +1,130 @@
+#[test]
+fn match_test() {
+    use crate::vm::jit_run;
+
+    let classify = "
+        (define (classify x)
+          (match x
+            (1 'one)
+            (\"s\" 'string)
+            ('a 'quoted-a)
+            (() 'empty)
+            ((a b) (list 'two a b))
+            ((a . rest) (list 'pair a rest))
+            (_ 'other)))";
+    assert_eq!(
+        jit_run(&format!(
+            "{classify}
+            (list (classify 1) (classify \"s\") (classify 'a) (classify '())
+                  (classify '(1 2)) (classify '(1 2 3)) (classify 5))"
+        )),
+        jit_run("'(one string quoted-a empty (two 1 2) (pair 1 (2 3)) other)")
+    );
+    // literals compare with equal?, and the expansion doesn't see local names
+    assert_eq!(
+        jit_run(
+            "
+            (define (f car cdr not)
+              (match (list '(a b) 1.0)
+                (('(a b) 1) 'exact)
+                (('(a b) x) (list 'other x))))
+            (f 1 2 3)"
+        ),
+        jit_run("'(other 1.0)")
+    );
+    assert_eq!(
+        jit_run("(match '(1 (2 3)) ((a (b c)) (+ a b c)))"),
+        jit_run("6")
+    );
+    assert_eq!(
+        jit_run("(match 5 ((? number? n) (* n 2)) (_ 'not-a-number))"),
+        jit_run("10")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (size xs)
+              (match xs
+                ((x ...) (guard (> (length x) 2)) 'long)
+                ((x ...) 'short)))
+            (list (size '(1 2)) (size '(1 2 3)))"
+        ),
+        jit_run("'(short long)")
+    );
+    // sicp 2.58 without cadr and caddr
+    assert_eq!(
+        jit_run(
+            "
+            (define (deriv exp var)
+              (match exp
+                ((? number?) 0)
+                ((? symbol? x) (if (eqv? x var) 1 0))
+                ((a '+ b) (list (deriv a var) '+ (deriv b var)))
+                ((a '* b) (list (list a '* (deriv b var)) '+ (list (deriv a var) '* b)))))
+            (deriv '(x + (3 * x)) 'x)"
+        ),
+        jit_run("'(1 + ((3 * 1) + (0 * x)))")
+    );
+}
+
+#[test]
+fn match_ellipsis_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(match '((a 1) (b 2) (c 3)) (((name value) ...) (list name value)))"),
+        jit_run("'((a b c) (1 2 3))")
+    );
+    // patterns after the ellipsis match the end of the list
+    assert_eq!(
+        jit_run("(match '(1 2 3 4 5) ((first middle ... last) (list first middle last)))"),
+        jit_run("'(1 (2 3 4) 5)")
+    );
+    assert_eq!(
+        jit_run("(match '((1 2) (3 4 5)) (((a ...) ...) a))"),
+        jit_run("'((1 2) (3 4 5))")
+    );
+    // every item has to match
+    assert_eq!(
+        jit_run("(match '(1 x 3) (((? number? n) ...) n) (_ 'not-all-numbers))"),
+        jit_run("'not-all-numbers")
+    );
+}
+
+#[test]
+fn match_record_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+            (define (on-axis? p)
+              (match p
+                (($ point 0 _) true)
+                (($ point _ 0) true)
+                (_ false)))
+            (list (on-axis? (make-point 0 5)) (on-axis? (make-point 2 0))
+                  (on-axis? (make-point 1 1)) (on-axis? '(0 0)))"
+        ),
+        jit_run("'(true true false false)")
+    );
+}
+
+#[test]
+fn match_errors_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(match '(1 2 3) ((a b) a))"),
+        Err("jit_run_vm:1:8: match, no clause matches: (1 2 3)".to_string())
+    );
+    assert_eq!(
+        jit_run("(match '(1 2) ((x x) x))"),
+        Err("jit_run_vm:1:19: match, x occurs twice in pattern: (x x)".to_string())
+    );
+    assert_eq!(
+        jit_run("(match '(1 2) ((x ... y ...) x))"),
+        Err("jit_run_vm:1:19: match, only one ... is allowed in a list: (x ... y ...)".to_string())
+    );
+}
@@ -0,0 +1,114 @@
+#[test]
+fn math_functions_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (sqrt 16) (sqrt 1/4) (exact? (sqrt 16)) (sqrt 2.25) (exact? (sqrt 2)))"),
+        jit_run("(list 4 1/2 true 1.5 false)")
+    );
+    assert_eq!(
+        jit_run(
+            "(list (exp 0) (log 1) (log 8 2) (sin 0) (cos 0) (tan 0) (atan 1 1) (* 4 (atan 1)))"
+        ),
+        jit_run("(list 1.0 0.0 3.0 0.0 1.0 0.0 (atan 1) 3.141592653589793)")
+    );
+    assert_eq!(
+        jit_run("(call-with-values (lambda () (exact-integer-sqrt 17)) list)"),
+        jit_run("'(4 1)")
+    );
+    assert_eq!(
+        jit_run("(list (expt 2 10) (expt 2/3 2) (expt 4 0.5) (expt 2 -1))"),
+        jit_run("(list 1024 4/9 2.0 1/2)")
+    );
+}
+
+#[test]
+fn rounding_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "(list (floor 2.5) (ceiling 2.5) (round 2.5) (round 3.5) (truncate -2.5) (floor -2.5))"
+        ),
+        jit_run("(list 2.0 3.0 2.0 4.0 -2.0 -3.0)")
+    );
+    // exact arguments give exact results
+    assert_eq!(
+        jit_run("(list (floor 7/2) (ceiling 7/2) (round 7/2) (round 5/2) (truncate -7/2) (floor 5) (exact? (round 7/2)))"),
+        jit_run("'(3 4 4 2 -3 5 true)")
+    );
+}
+
+#[test]
+fn integer_division_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (quotient 17 5) (quotient -17 5) (remainder 17 -5) (remainder -17 5) (modulo -17 5) (modulo 17 -5))"),
+        jit_run("'(3 -3 2 -2 3 -3)")
+    );
+    assert_eq!(
+        jit_run("(list (quotient 17.0 5) (modulo -7 2.0) (exact? (quotient 17.0 5)))"),
+        jit_run("(list 3.0 1.0 false)")
+    );
+    assert_eq!(
+        jit_run("(list (gcd 12 18) (gcd -12 18 8) (gcd) (lcm 4 6) (lcm -4 6 5) (lcm))"),
+        jit_run("'(6 2 0 12 60 1)")
+    );
+    assert_eq!(
+        jit_run("(list (even? 4) (odd? 4) (even? -3) (odd? -3) (even? 0) (odd? 2.0))"),
+        jit_run("'(true false false true true false)")
+    );
+    assert_eq!(
+        jit_run("(quotient 1 0)"),
+        Err("quotient: division by zero".to_string())
+    );
+    assert_eq!(
+        jit_run("(modulo 1.5 2)"),
+        Err("modulo: expected integers, found: 1.5 and 2".to_string())
+    );
+}
+
+#[test]
+fn comparison_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (min 3 1 2) (max 3 1 2) (max 1 2.0) (min 1 2.0) (max 1/2 1/3))"),
+        jit_run("(list 1 3 2.0 1.0 1/2)")
+    );
+    assert_eq!(
+        jit_run("(list (<= 1 1) (<= 2 1) (>= 1 1.0) (>= 1 2) (zero? 0) (zero? 0.0) (zero? 1) (positive? 1/2) (negative? -0.5) (positive? 0))"),
+        jit_run("'(true false true false true true false true true false)")
+    );
+}
+
+#[test]
+fn math_type_error_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(sqrt 'x)"),
+        Err("sqrt: expected num but found: x".to_string())
+    );
+    assert_eq!(
+        jit_run("(max 1 \"2\")"),
+        Err("max: expected num but found: 2".to_string())
+    );
+    assert_eq!(
+        jit_run("(<= 1 'a)"),
+        Err("<=: expected numbers, found: 1 and a".to_string())
+    );
+    assert_eq!(
+        jit_run("(even? 1.5)"),
+        Err("even?: expected integer but found: 1.5".to_string())
+    );
+    assert_eq!(
+        jit_run("(exact-integer-sqrt -1)"),
+        Err("exact-integer-sqrt: expected a non-negative exact integer but found: -1".to_string())
+    );
+    assert_eq!(
+        jit_run("(atan 1 2 3)"),
+        Err("atan: expected one or two numbers, found: (1 2 3)".to_string())
+    );
+}
@@ -1,7 +1,24 @@
+mod char_test;
 mod compile_test;
+mod control_test;
+mod conversion_test;
+mod dynamic_wind_test;
 mod gc_test;
+mod hash_table_test;
+mod lazy_test;
+mod let_test;
 mod macros_test;
+mod match_test;
+mod math_test;
+mod number_test;
+mod persistent_test;
 mod prelude_test;
 mod print_test;
+mod quasiquote_test;
+mod record_test;
 mod run_test;
 mod sicp_test;
+mod string_test;
+mod syntax_rules_test;
+mod values_test;
+mod vector_test;
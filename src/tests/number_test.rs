@@ -0,0 +1,112 @@
+#[test]
+fn exact_integer_test() {
+    use crate::vm::jit_run;
+
+    let factorial = "(define (factorial n) (if (= n 0) 1 (* n (factorial (- n 1)))))";
+    assert_eq!(
+        jit_run(&format!("{factorial} (to-string (factorial 30))")),
+        jit_run("\"265252859812191058636308480000000\"")
+    );
+    // back to machine integers when small again
+    assert_eq!(
+        jit_run(&format!(
+            "{factorial} (/ (factorial 30) (* 30 (factorial 29)))"
+        )),
+        jit_run("1")
+    );
+    assert_eq!(
+        jit_run("(to-string (^ 2 100))"),
+        jit_run("\"1267650600228229401496703205376\"")
+    );
+    assert_eq!(
+        jit_run("(to-string (+ 9223372036854775807 1))"),
+        jit_run("\"9223372036854775808\"")
+    );
+    assert_eq!(
+        jit_run("(to-string (abs -9223372036854775808))"),
+        jit_run("\"9223372036854775808\"")
+    );
+}
+
+#[test]
+fn exactness_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (exact? 1) (exact? 1.5) (inexact? 1.0) (inexact? (+ 1 2)))"),
+        jit_run("'(true false true false)")
+    );
+    // inexact operands make the result inexact
+    assert_eq!(
+        jit_run("(list (to-string (+ 1 2.0)) (to-string (* 2 0.5)) (to-string (/ 6 3)) (to-string (/ 1 2.0)))"),
+        jit_run("'(\"3.0\" \"1.0\" \"2\" \"0.5\")")
+    );
+    assert_eq!(
+        jit_run(
+            "(list (to-string (exact 3.0)) (to-string (inexact 3)) (to-string (exact->inexact 1)))"
+        ),
+        jit_run("'(\"3\" \"3.0\" \"1.0\")")
+    );
+    // = compares by value, eqv? also by exactness
+    assert_eq!(
+        jit_run("(list (= 1 1.0) (eqv? 1 1.0) (eqv? 1 1) (< 1 1.5) (> 2 1.5))"),
+        jit_run("'(true false true true true)")
+    );
+    assert_eq!(
+        jit_run("(< 9007199254740993 9007199254740992.0)"),
+        jit_run("false")
+    );
+    assert_eq!(
+        jit_run("(exact (/ 1 0.0))"),
+        Err("exact: no exact representation of +inf.0".to_string())
+    );
+    assert_eq!(jit_run("(/ 1 0)"), Err("/: division by zero".to_string()));
+    assert_eq!(
+        jit_run("(expt 0 -1)"),
+        Err("expt: division by zero".to_string())
+    );
+    assert_eq!(jit_run("(^ 0 -1)"), Err("^: division by zero".to_string()));
+    assert_eq!(
+        jit_run(
+            "(list (to-string (expt 0.0 -1)) (to-string 1e300) (to-string -1e16) (to-string 1e15))"
+        ),
+        jit_run("'(\"+inf.0\" \"1e300\" \"-1e16\" \"1000000000000000.0\")")
+    );
+    assert_eq!(jit_run("(to-string (/ 1 0.0))"), jit_run("\"+inf.0\""));
+}
+
+#[test]
+fn exact_rational_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(list (to-string (/ 1 3)) (to-string (/ 6 4)) (to-string (/ -6 4)) (to-string (/ 4 -6)))"),
+        jit_run("'(\"1/3\" \"3/2\" \"-3/2\" \"-2/3\")")
+    );
+    // read in lowest terms, integers when the denominator is 1
+    assert_eq!(
+        jit_run("(list (to-string 2/4) (to-string -10/5) (exact? 1/3) (integer? 4/2))"),
+        jit_run("'(\"1/2\" \"-2\" true true)")
+    );
+    assert_eq!(
+        jit_run("(list (to-string (+ 1/3 1/6)) (to-string (* 2/3 3/2)) (to-string (- 1/2 1)) (to-string (^ 2/3 2)) (to-string (^ 2 -2)))"),
+        jit_run("'(\"1/2\" \"1\" \"-1/2\" \"4/9\" \"1/4\")")
+    );
+    assert_eq!(
+        jit_run("(list (numerator 6/4) (denominator 6/4) (numerator 5) (denominator 5) (to-string (denominator 0.5)))"),
+        jit_run("'(3 2 5 1 \"2.0\")")
+    );
+    // mixing with floats is inexact
+    assert_eq!(
+        jit_run("(list (to-string (+ 1/2 0.25)) (= 1/2 0.5) (< 1/3 0.34) (eqv? 1/2 0.5) (eqv? 1/2 2/4))"),
+        jit_run("'(\"0.75\" true true false true)")
+    );
+    assert_eq!(
+        jit_run("(list (to-string (exact 0.5)) (to-string (inexact 1/4)) (to-string (abs -1/2)))"),
+        jit_run("'(\"1/2\" \"0.25\" \"1/2\")")
+    );
+    assert_eq!(
+        jit_run("(+ 1 1/0)"),
+        Err("jit_run_vm:1:6: invalid number, the denominator is zero: 1/0".to_string())
+    );
+}
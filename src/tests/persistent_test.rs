@@ -0,0 +1,71 @@
+#[test]
+fn persistent_map_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "(define m (persistent-map 'b 2 'a 1))
+             (define m2 (map-assoc m 'c 3))
+             (define m3 (map-dissoc m2 'a))
+             (list (map-get m 'a) (map-get m 'c) (map-get m 'c 0) (map-get m2 'c) (map-get m3 'a 'gone)
+                   (map-count m) (map-count m2) (map-count m3))"
+        ),
+        jit_run("'(1 false 0 3 gone 2 3 2)")
+    );
+    // keys are kept in order
+    assert_eq!(
+        jit_run(
+            "(define m (alist->map (list (cons 3 'c) (cons 1 'a) (cons 2 'b))))
+             (list (map-keys m) (map-values m) (to-string m) (to-string (map-assoc m 1 'x)))"
+        ),
+        jit_run("'((1 2 3) (a b c) \"{1 a 2 b 3 c}\" \"{1 x 2 b 3 c}\")")
+    );
+    assert_eq!(
+        jit_run(
+            "(list (= (persistent-map 'a 1 'b 2) (map-assoc (persistent-map 'b 2) 'a 1))
+                   (equal? (persistent-map 'a 1) (persistent-map 'a 2))
+                   (map-contains? (persistent-map '(1 2) 'x) (list 1 2))
+                   (map-get (persistent-map 1 'exact 1.0 'inexact) 1.0)
+                   (persistent-map? (persistent-map)))"
+        ),
+        jit_run("'(true false true inexact true)")
+    );
+    assert_eq!(
+        jit_run("(map-assoc (persistent-map) (vector 1) 1)"),
+        Err("map-assoc: #(1) can't be a key, keys are booleans, numbers, chars, strings, symbols, lists or sets".to_string())
+    );
+    assert_eq!(
+        jit_run("(persistent-map 'a)"),
+        Err("persistent-map: expected keys and values, found: (a)".to_string())
+    );
+}
+
+#[test]
+fn persistent_set_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "(define s (list->set '(3 1 2 1)))
+             (define t (persistent-set 2 3 4))
+             (list (set->list s) (set-count s) (set-contains? s 1) (set-contains? t 1)
+                   (set->list (set-union s t)) (set->list (set-intersection s t))
+                   (set->list (set-difference s t)) (to-string (set-add s 0))
+                   (set->list (set-remove s 2)) (set->list s))"
+        ),
+        jit_run("'((1 2 3) 3 true false (1 2 3 4) (2 3) (1) \"#{0 1 2 3}\" (1 3) (1 2 3))")
+    );
+    // sets of sets
+    assert_eq!(
+        jit_run(
+            "(list (= (persistent-set 1 2) (set-add (persistent-set 2) 1))
+                   (set-count (persistent-set (persistent-set 1 2) (persistent-set 2 1)))
+                   (persistent-set? (persistent-set)))"
+        ),
+        jit_run("'(true 1 true)")
+    );
+    assert_eq!(
+        jit_run("(set-union (persistent-set) '(1))"),
+        Err("set-union: expected set but found: (1)".to_string())
+    );
+}
@@ -12,11 +12,24 @@ fn call_function_defined_in_prelude() {
     assert_eq!(res, Ok(Expr::num(15.0)));
 }
 
+// the prelude doesn't shadow builtins
+#[test]
+fn append_takes_any_number_of_lists() {
+    assert_eq!(
+        crate::vm::jit_run("(list (append) (append '(1)) (append '(1) '(2) '(3)))"),
+        crate::vm::jit_run("'(() (1) (1 2 3))")
+    );
+}
+
 #[test]
 fn call_macro_defined_in_prelude() {
     use crate::expr::Expr;
-    let res = crate::vm::jit_run("(cond (false 2) (true 3))");
-    assert_eq!(res, Ok(Expr::num(3.0)));
+    use crate::parse::make_pair_from_vec;
+    let res = crate::vm::jit_run("(syntax-list 2 3)");
+    assert_eq!(
+        res,
+        Ok(make_pair_from_vec(vec![Expr::num(2.0), Expr::num(3.0)]))
+    );
 }
 
 #[test]
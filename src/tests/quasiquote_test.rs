@@ -0,0 +1,58 @@
+#[test]
+fn quasiquote_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(jit_run("`(a b)"), jit_run("'(a b)"));
+    assert_eq!(jit_run("`(1 ,(+ 1 1) ,@(list 3 4))"), jit_run("'(1 2 3 4)"));
+    assert_eq!(
+        jit_run("`(1 ,@(list 2 3) 4 ,@(list))"),
+        jit_run("'(1 2 3 4)")
+    );
+    assert_eq!(jit_run("`(a . ,(+ 1 2))"), jit_run("(cons 'a 3)"));
+    assert_eq!(jit_run("`,(+ 1 2)"), Ok(Expr::num(3.0)));
+    // only the innermost unquote belongs to the outer quasiquote
+    assert_eq!(
+        jit_run("`(a `(b ,(c ,(+ 1 2))))"),
+        jit_run("'(a (quasiquote (b (unquote (c 3)))))")
+    );
+    assert_eq!(
+        jit_run("(define (f x) `(x is ,x)) (f 1)"),
+        jit_run("'(x is 1)")
+    );
+    // unquotes in vectors are evaluated too
+    assert_eq!(
+        jit_run("(define (f x) `#(1 ,(+ x 1) ,@(list x) (,x))) (f 1)"),
+        jit_run("(vector 1 2 1 '(1))")
+    );
+    // the template is data, not a macro call
+    assert_eq!(jit_run("`(assert 1 ,2)"), jit_run("'(assert 1 2)"));
+    assert_eq!(
+        jit_run("`,@(list 1)"),
+        Err("jit_run_vm:1:2: unquote-splicing has to be inside a list, but found: (unquote-splicing (list 1))".to_string())
+    );
+}
+
+#[test]
+fn quasiquote_in_macros_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (defmacro (my-if test then else)
+              `(cond (,test ,then) (else ,else)))
+            (list (my-if true 1 2) (my-if false 1 2))"
+        ),
+        jit_run("'(1 2)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (defmacro (my-list . xs)
+              `(list ,@xs))
+            (my-list 1 (+ 1 1) (my-list 3))"
+        ),
+        jit_run("'(1 2 (3))")
+    );
+}
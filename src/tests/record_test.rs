@@ -0,0 +1,112 @@
+#[test]
+fn record_test() {
+    use crate::vm::jit_run;
+
+    let point = "
+        (define-record-type <point>
+          (make-point x y)
+          point?
+          (x point-x set-point-x!)
+          (y point-y))";
+    assert_eq!(
+        jit_run(&format!("{point} (to-string (make-point 1 2))")),
+        jit_run("\"#<point x: 1 y: 2>\"")
+    );
+    assert_eq!(
+        jit_run(&format!(
+            "{point}
+            (define p (make-point 1 2))
+            (set-point-x! p 5)
+            (list (point-x p) (point-y p))"
+        )),
+        jit_run("'(5 2)")
+    );
+    // the predicate only accepts records of its own type
+    assert_eq!(
+        jit_run(&format!(
+            "{point}
+            (define-record-type other (make-other x y) other? (x other-x) (y other-y))
+            (list (point? (make-point 1 2)) (point? '(1 2)) (point? (make-other 1 2)))"
+        )),
+        jit_run("'(true false false)")
+    );
+    // = compares fields, eqv? identity
+    assert_eq!(
+        jit_run(&format!(
+            "{point}
+            (define p (make-point 1 2))
+            (list (= p (make-point 1 2)) (= p (make-point 1 3)) (eqv? p p) (eqv? p (make-point 1 2)))"
+        )),
+        jit_run("'(true false true false)")
+    );
+    // a record can contain itself
+    assert_eq!(
+        jit_run(&format!(
+            "{point}
+            (define p (make-point 1 2))
+            (set-point-x! p p)
+            (list (to-string p) (= p p))"
+        )),
+        jit_run("(list \"#<point x: #<cycle> y: 2>\" true)")
+    );
+    // the constructor can take some of the fields, in any order
+    assert_eq!(
+        jit_run(
+            "
+            (define-record-type node (make-node value) node? (value node-value) (next node-next))
+            (to-string (make-node 1))"
+        ),
+        jit_run("\"#<node value: 1 next: false>\"")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f)
+              (define-record-type pair (make-pair b a) my-pair? (a pair-a) (b pair-b))
+              (pair-a (make-pair 1 2)))
+            (f)"
+        ),
+        jit_run("2")
+    );
+    // fields can have the names of the procedures the definitions use
+    assert_eq!(
+        jit_run(
+            "
+            (define-record-type box
+              (make-box make-record record value)
+              box?
+              (make-record box-a)
+              (record box-b set-box-b!)
+              (value box-c))
+            (define b (make-box 1 2 3))
+            (set-box-b! b 5)
+            (list (box? b) (box-a b) (box-b b) (box-c b))"
+        ),
+        jit_run("'(true 1 5 3)")
+    );
+}
+
+#[test]
+fn record_errors_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define-record-type point (make-point x y) point? (x point-x) (y point-y))
+            (point-x '(1 2))"
+        ),
+        Err("point-x: expected a point, found: (1 2)".to_string())
+    );
+    assert_eq!(
+        jit_run("(define-record-type point (make-point x z) point? (x point-x))"),
+        Err("jit_run_vm:1:41: define-record-type, z is not a field of point".to_string())
+    );
+    assert_eq!(
+        jit_run("(define-record-type point (make-point x) point? (x))"),
+        Err(
+            "jit_run_vm:1:51: define-record-type, expected (field accessor [modifier]), found: (x)"
+                .to_string()
+        )
+    );
+}
@@ -47,13 +47,12 @@ fn test_sicp() {
         successful_files.push((file, vm.unwrap()))
     }
 
-    assert_eq!(
+    assert!(
         dir.files()
             .map(|x| (x.path().to_str().unwrap(), x.contents_utf8().unwrap()))
             .collect::<Vec<(&str, &str)>>()
             .len()
-            > 34,
-        true
+            > 34
     );
 
     let expected_logs = HashMap::from([(
@@ -0,0 +1,64 @@
+#[test]
+fn string_test() {
+    use crate::vm::jit_run;
+
+    // lengths and indexes count characters, not bytes
+    assert_eq!(
+        jit_run(
+            r#"(list (string-length "héllo") (substring "héllo" 1 3) (substring "héllo" 2) (string-ref "λμ" 1))"#
+        ),
+        jit_run(r#"(list 5 "él" "llo" #\μ)"#)
+    );
+    assert_eq!(
+        jit_run(r#"(list (string-append) (string-append "a") (string-append "a" "b" "c"))"#),
+        jit_run(r#"'("" "a" "abc")"#)
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string-split "  a b  c ") (string-split "a,b,,c" #\,) (string-split "a::b" "::"))"#
+        ),
+        jit_run(r#"'(("a" "b" "c") ("a" "b" "" "c") ("a" "b"))"#)
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string-join '("a" "b" "c")) (string-join '("a" "b") ", ") (string-join '()))"#
+        ),
+        jit_run(r#"'("a b c" "a, b" "")"#)
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string-index "héllo" #\l) (string-index "hello" #\z) (string-contains "héllo" "lo") (string-contains "hello" "xyz"))"#
+        ),
+        jit_run("'(2 false 3 false)")
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string-upcase "straße") (string-downcase "ÀB") (string-trim "  a b ") (string-trim-left " a ") (string-trim-right " a "))"#
+        ),
+        jit_run(r#"'("STRASSE" "àb" "a b" "a " " a")"#)
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string=? "a" "a") (string<? "abc" "abd") (string<? "b" "a") (string>? "b" "a"))"#
+        ),
+        jit_run("'(true true false true)")
+    );
+    assert_eq!(
+        jit_run(
+            r#"(list (string->list "aλ") (list->string (list #\h #\i)) (list->string (reverse (string->list "abc"))))"#
+        ),
+        jit_run(r#"(list (list #\a #\λ) "hi" "cba")"#)
+    );
+    assert_eq!(
+        jit_run(r#"(substring "abc" 2 4)"#),
+        Err(r#"substring: range out of bounds for "abc": (2 4)"#.to_string())
+    );
+    assert_eq!(
+        jit_run(r#"(string-append "a" 1)"#),
+        Err("string-append: expected string but found: 1".to_string())
+    );
+    assert_eq!(
+        jit_run("(string-length 'abc)"),
+        Err("string-length: expected string but found: abc".to_string())
+    );
+}
@@ -0,0 +1,257 @@
+#[test]
+fn syntax_rules_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-let
+              (syntax-rules ()
+                ((_ ((name val) ...) body1 body2 ...)
+                 ((lambda (name ...) body1 body2 ...) val ...))))
+            (my-let ((x 1) (y 2)) (list y x))"
+        ),
+        jit_run("'(2 1)")
+    );
+    // rules are tried in order and the expansion is expanded again
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) false)
+                ((_ e) e)
+                ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+            (list (my-or) (my-or false 2) (my-or false false 3))"
+        ),
+        jit_run("'(false 2 3)")
+    );
+    // literals only match themselves
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-if
+              (syntax-rules (then else)
+                ((_ c then t else e) (if c t e))))
+            (list (my-if true then 1 else 2) (my-if false then 1 else 2))"
+        ),
+        jit_run("'(1 2)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-if
+              (syntax-rules (then else)
+                ((_ c then t else e) (if c t e))))
+            (my-if true 1 else 2)"
+        ),
+        Err(
+            "jit_run_vm:5:20: no syntax-rules pattern of my-if matches: (my-if true 1 else 2)"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        jit_run("(define-syntax ten (syntax-rules () ((_) 10))) (+ (ten) (ten))"),
+        Ok(Expr::num(20.0))
+    );
+}
+
+#[test]
+fn syntax_rules_ellipsis_test() {
+    use crate::vm::jit_run;
+
+    // nested ellipses keep their structure
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-let*
+              (syntax-rules ()
+                ((_ () body ...) (let () body ...))
+                ((_ ((x v) rest ...) body ...)
+                 (let ((x v)) (my-let* (rest ...) body ...)))))
+            (my-let* ((a 1) (b (+ a 1))) (list a b))"
+        ),
+        jit_run("'(1 2)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax flatten
+              (syntax-rules ()
+                ((_ (a b ...) ...) '(a ... b ... ...))))
+            (flatten (1 2 3) (4) (5 6))"
+        ),
+        jit_run("'(1 4 5 2 3 6)")
+    );
+    // patterns after the ellipsis match the end of the form
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax last
+              (syntax-rules ()
+                ((_ x ... y) 'y)))
+            (last 1 2 3)"
+        ),
+        jit_run("'3")
+    );
+    // a custom ellipsis, and (... ...) for a literal ellipsis
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-list
+              (syntax-rules ::: ()
+                ((_ x :::) (list x ::: '...))))
+            (my-list 1 2)"
+        ),
+        jit_run("'(1 2 ...)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax quoted-ellipsis
+              (syntax-rules ()
+                ((_ x ...) '(x ... (... ...)))))
+            (quoted-ellipsis 1 2)"
+        ),
+        jit_run("'(1 2 ...)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax pairs
+              (syntax-rules ()
+                ((_ (a ...) (b ...)) '((a b) ...))))
+            (pairs (1 2) (3))"
+        ),
+        Err("jit_run_vm:5:20: pairs: the variables in (a b) ... matched a different number of times".to_string())
+    );
+}
+
+#[test]
+fn syntax_rules_dotted_pattern_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax first-and-rest
+              (syntax-rules ()
+                ((_ first . rest) '(first rest))))
+            (first-and-rest 1 2 3)"
+        ),
+        jit_run("'(1 (2 3))")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-lambda
+              (syntax-rules ()
+                ((_ args . body) (lambda args . body))))
+            ((my-lambda (a . b) (cons b a)) 1 2 3)"
+        ),
+        jit_run("(cons '(2 3) 1)")
+    );
+}
+
+#[test]
+fn syntax_rules_hygiene_test() {
+    use crate::expr::Expr;
+    use crate::vm::jit_run;
+
+    let my_or = "
+        (define-syntax my-or
+          (syntax-rules ()
+            ((_) false)
+            ((_ e) e)
+            ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))";
+    // the t the template binds is not the user's t
+    assert_eq!(
+        jit_run(&format!("{my_or} (define t 5) (my-or false t)")),
+        Ok(Expr::num(5.0))
+    );
+    assert_eq!(
+        jit_run(&format!("{my_or} (let ((t 5)) (my-or false t))")),
+        Ok(Expr::num(5.0))
+    );
+
+    // the cons the template uses is the global one, wherever it is used
+    let my_cons = "(define-syntax my-cons (syntax-rules () ((_ a b) (cons a b))))";
+    assert_eq!(
+        jit_run(&format!(
+            "{my_cons} (let ((cons (lambda (a b) 'shadowed))) (my-cons 1 2))"
+        )),
+        jit_run("(cons 1 2)")
+    );
+    assert_eq!(
+        jit_run(&format!("{my_cons} (define (f cons) (my-cons 1 2)) (f 0)")),
+        jit_run("(cons 1 2)")
+    );
+    assert_eq!(
+        jit_run(&format!(
+            "{my_cons} (define (f) (define cons 0) (my-cons 1 2)) (f)"
+        )),
+        jit_run("(cons 1 2)")
+    );
+    // also when the user's name is bound by the template itself
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax bind-then-cons
+              (syntax-rules ()
+                ((_ name value rest) (let ((name value)) (cons name rest)))))
+            (bind-then-cons cons 1 2)"
+        ),
+        jit_run("(cons 1 2)")
+    );
+    // the form that skips the local bindings can't be called by programs
+    assert_eq!(
+        jit_run(&format!(
+            "{my_cons} (define (global-ref x) (* x 10)) (list (global-ref 5) (let ((cons 0)) (my-cons 1 2)))"
+        )),
+        jit_run("(list 50 (cons 1 2))")
+    );
+    // prelude functions too
+    assert_eq!(
+        jit_run(
+            "
+            (define-syntax my-list (syntax-rules () ((_ a ...) (list a ...))))
+            (define (f list) (my-list list 2))
+            (f 1)"
+        ),
+        jit_run("'(1 2)")
+    );
+    // a local the macro captured is the one from where it was defined
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (define-syntax getx (syntax-rules () ((_) x)))
+              (let ((x 2)) (getx)))
+            (f 1)"
+        ),
+        Ok(Expr::num(1.0))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (define-syntax getx (syntax-rules () ((_) x)))
+              (define (g x) (let ((x 3)) (list x (getx))))
+              (g 2))
+            (f 1)"
+        ),
+        jit_run("'(3 1)")
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f x)
+              (let ((x 2))
+                (define-syntax getx (syntax-rules () ((_) x)))
+                ((lambda (x) (getx)) 3)))
+            (f 1)"
+        ),
+        Ok(Expr::num(2.0))
+    );
+}
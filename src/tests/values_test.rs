@@ -0,0 +1,110 @@
+#[test]
+fn values_test() {
+    use crate::expr::Expr;
+    use crate::parse::make_pair_from_vec;
+    use crate::vm::jit_run;
+
+    assert_eq!(jit_run("(values 1)"), Ok(Expr::num(1.0)));
+    assert_eq!(
+        jit_run("(values 1 2)"),
+        Ok(Expr::Values(vec![Expr::num(1.0), Expr::num(2.0)]))
+    );
+    assert_eq!(
+        jit_run("(call-with-values (lambda () (values 1 2)) +)"),
+        Ok(Expr::num(3.0))
+    );
+    assert_eq!(
+        jit_run("(call-with-values (lambda () 5) (lambda (x) (* x x)))"),
+        Ok(Expr::num(25.0))
+    );
+
+    let div_mod = "
+        (define (div-mod n d)
+          (values (/ (- n (% n d)) d) (% n d)))
+        ";
+    assert_eq!(
+        jit_run(&format!(
+            "{div_mod} (receive (q r) (div-mod 17 5) (list q r))"
+        )),
+        Ok(make_pair_from_vec(vec![Expr::num(3.0), Expr::num(2.0)]))
+    );
+    assert_eq!(
+        jit_run(&format!("{div_mod} (receive all (div-mod 17 5) all)")),
+        Ok(make_pair_from_vec(vec![Expr::num(3.0), Expr::num(2.0)]))
+    );
+    assert_eq!(
+        jit_run("(receive (a . rest) (values 1 2 3) (list a rest))"),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(1.0),
+            make_pair_from_vec(vec![Expr::num(2.0), Expr::num(3.0)])
+        ]))
+    );
+    assert_eq!(
+        jit_run(&format!(
+            "{div_mod}
+            (define-values (q r) (div-mod 17 5))
+            (list q r)"
+        )),
+        Ok(make_pair_from_vec(vec![Expr::num(3.0), Expr::num(2.0)]))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define (f)
+              (define-values (a . rest) (values 1 2 3))
+              (cons a rest))
+            (f)"
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(1.0),
+            Expr::num(2.0),
+            Expr::num(3.0)
+        ]))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define x 10)
+            (let-values (((a b) (values 1 2))
+                         ((c) (values (+ x 3))))
+              (list a b c))"
+        ),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(1.0),
+            Expr::num(2.0),
+            Expr::num(13.0)
+        ]))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define x 1)
+            (let-values (((x) (values 2)) ((y) (values x))) y)"
+        ),
+        Ok(Expr::num(1.0))
+    );
+    assert_eq!(
+        jit_run(
+            "
+            (define x 1)
+            (let*-values (((x) (values 2)) ((y) (values x))) y)"
+        ),
+        Ok(Expr::num(2.0))
+    );
+    assert_eq!(
+        jit_run("(let-values (((a . rest) (values 1 2 3)) (all (values 4 5))) (list a rest all))"),
+        Ok(make_pair_from_vec(vec![
+            Expr::num(1.0),
+            make_pair_from_vec(vec![Expr::num(2.0), Expr::num(3.0)]),
+            make_pair_from_vec(vec![Expr::num(4.0), Expr::num(5.0)])
+        ]))
+    );
+    assert_eq!(
+        jit_run("(list (values 1 2))"),
+        Err("expected a single value, but found 2 values".to_string())
+    );
+    assert_eq!(
+        jit_run("(define-values (a b) (values 1 2 3))"),
+        Err("define-values, expected 2 values, but found: 3".to_string())
+    );
+}
@@ -0,0 +1,79 @@
+#[test]
+fn vector_test() {
+    use crate::vm::jit_run;
+
+    assert_eq!(
+        jit_run("(to-string #(1 (2 3) x \"s\"))"),
+        jit_run("\"#(1 (2 3) x s)\"")
+    );
+    assert_eq!(
+        jit_run("(list (vector? #(1)) (vector? '(1)) (vector-length #()) (vector-length (vector 1 2 3)))"),
+        jit_run("'(true false 0 3)")
+    );
+    assert_eq!(
+        jit_run("(list (vector-ref #(a b c) 1) (to-string (make-vector 2 'x)) (to-string (make-vector 2)))"),
+        jit_run("'(b \"#(x x)\" \"#(false false)\")")
+    );
+    assert_eq!(
+        jit_run(
+            "(define v (make-vector 3 0))
+             (vector-set! v 0 'a)
+             (vector-set! v 2 'c)
+             (vector->list v)"
+        ),
+        jit_run("'(a 0 c)")
+    );
+    assert_eq!(
+        jit_run(
+            "(define v (list->vector '(1 2 3)))
+             (define w v)
+             (vector-fill! w 7)
+             (list (vector->list v) (= v #(7 7 7)) (eqv? v w) (eqv? v #(7 7 7)))"
+        ),
+        jit_run("'((7 7 7) true true false)")
+    );
+    assert_eq!(
+        jit_run(
+            "(define out (make-vector 3 0))
+             (vector-for-each (lambda (i) (vector-set! out i (* i 10))) #(0 1 2))
+             (list (vector->list out) (vector->list (vector-map (lambda (x) (* x x)) #(1 2 3))))"
+        ),
+        jit_run("'((0 10 20) (1 4 9))")
+    );
+    // a literal is a new vector each time it is evaluated
+    assert_eq!(
+        jit_run(
+            "(define (f) #(1 2 3))
+             (define (g) '(#(1)))
+             (vector-set! (f) 0 'changed)
+             (vector-set! (car (g)) 0 'changed)
+             (list (f) (g))"
+        ),
+        jit_run("(list (vector 1 2 3) (list (vector 1)))")
+    );
+    // a vector can contain itself
+    assert_eq!(
+        jit_run(
+            "(define v (vector 1 2))
+             (define w (vector 1 2))
+             (vector-set! v 0 v)
+             (vector-set! w 0 w)
+             (define table (make-hash-table))
+             (hash-table-set! table v 'v)
+             (list (to-string v) (equal? v w) (= v w) (hash-table-ref table w))"
+        ),
+        jit_run("(list \"#(#<cycle> 2)\" true true 'v)")
+    );
+    assert_eq!(
+        jit_run("(vector-ref #(1 2) 1.5)"),
+        Err("vector-ref: expected an exact integer index, found: 1.5".to_string())
+    );
+    assert_eq!(
+        jit_run("(vector-ref #(1 2) 2)"),
+        Err("vector-ref: index 2 out of range for #(1 2)".to_string())
+    );
+    assert_eq!(
+        jit_run("(vector-set! '(1 2) 0 1)"),
+        Err("vector-set!: expected a vector and an index, found: (1 2) and 0".to_string())
+    );
+}
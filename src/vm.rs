@@ -1,10 +1,10 @@
 use crate::{
-    compile::{collect_exprs_from_body, get_all_defines, MacroFn, BUILTIN_FNS},
-    expr::{Bool, Num},
-    macro_expand::macro_expand,
+    compile::{collect_exprs_from_body, eqv_arg, get_all_defines, is_eqv, MacroFn, BUILTIN_FNS},
+    expr::{Bool, Num, Promise},
+    macro_expand::{macro_expand, ExpansionContext, ExpansionStep, SyntaxEnv},
     parse::{make_pair_from_vec, ParseInput},
 };
-use std::{collections::HashMap, fmt::Display};
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
 use crate::{
     compile::{compile_many_exprs, BuiltIn},
@@ -15,6 +15,7 @@ use crate::{
 #[derive(Clone, Debug, PartialEq)]
 pub enum VMInstruction {
     Lookup(String),
+    LookupGlobal(String),
     MakeLambda(
         Chunk,
         Option<String>, /* variadic? */
@@ -31,21 +32,64 @@ pub enum VMInstruction {
     Return,
     Display,
     Constant(Expr),
+    // a quoted datum with vectors in it, each evaluation gets its own copy
+    Literal(Expr),
+    MakeParameter(bool /* has converter */),
+    Parameterize(usize),
+    PopParameterize,
+    PushWind,
+    PopWind,
+    CallWithValues,
+    SpreadValues(usize, bool /* rest */),
+    MakePromise(bool /* delay-force */),
+    Force,
+    EnterScope(Option<String> /* loop name */),
+    Bind(String),
+    ExitScope,
+    Recur(String),
+    Eqv(Vec<Expr>),
+    Swap,
 }
 
 impl Display for VMInstruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             VMInstruction::Lookup(s) => write!(f, "Lookup({s})"),
+            VMInstruction::LookupGlobal(s) => write!(f, "LookupGlobal({s})"),
             VMInstruction::Define(s) => write!(f, "Define({s})"),
             VMInstruction::CondJumpPop(u) => write!(f, "CondJumpPop({u})"),
             VMInstruction::CondJump(u) => write!(f, "CondJump({u})"),
             VMInstruction::Call(usize) => write!(f, "Call({usize})"),
             VMInstruction::Constant(c) => write!(f, "Constant({c})"),
+            VMInstruction::Literal(c) => write!(f, "Literal({c})"),
             VMInstruction::Return => write!(f, "Return"),
             VMInstruction::Display => write!(f, "Display"),
             VMInstruction::PopStack => write!(f, "PopStack"),
             VMInstruction::Apply => write!(f, "Apply"),
+            VMInstruction::MakeParameter(c) => write!(f, "MakeParameter({c})"),
+            VMInstruction::Parameterize(u) => write!(f, "Parameterize({u})"),
+            VMInstruction::PopParameterize => write!(f, "PopParameterize"),
+            VMInstruction::PushWind => write!(f, "PushWind"),
+            VMInstruction::PopWind => write!(f, "PopWind"),
+            VMInstruction::CallWithValues => write!(f, "CallWithValues"),
+            VMInstruction::MakePromise(delay_force) => write!(f, "MakePromise({delay_force})"),
+            VMInstruction::Force => write!(f, "Force"),
+            VMInstruction::SpreadValues(u, rest) => write!(f, "SpreadValues({u}, {rest})"),
+            VMInstruction::EnterScope(Some(name)) => write!(f, "EnterScope({name})"),
+            VMInstruction::EnterScope(None) => write!(f, "EnterScope"),
+            VMInstruction::Bind(s) => write!(f, "Bind({s})"),
+            VMInstruction::ExitScope => write!(f, "ExitScope"),
+            VMInstruction::Recur(s) => write!(f, "Recur({s})"),
+            VMInstruction::Eqv(datums) => write!(
+                f,
+                "Eqv({})",
+                datums
+                    .iter()
+                    .map(|datum| format!("{datum}"))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            VMInstruction::Swap => write!(f, "Swap"),
             VMInstruction::MakeLambda(_, _, params, locals, closeds) => {
                 write!(
                     f,
@@ -64,13 +108,36 @@ pub struct Callframe {
     pub ip: usize,
     pub chunk: Chunk,
     pub env: HashMap<String, HeapAddr>,
+    pub scopes: Vec<Scope>,
 }
 
 pub type HeapAddr = usize;
 
+/// A scope opened by `let` and friends within a callframe, it remembers the
+/// bindings it shadows so they can be restored when the scope is left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    pub start: usize,
+    pub loop_name: Option<String>,
+    pub shadowed: Vec<(String, Option<HeapAddr>)>,
+}
+
+/// An entry of the dynamic extent, pushed by `dynamic-wind` and `parameterize`
+/// and popped again when their body returns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Winder {
+    DynamicWind {
+        after: Expr,
+        callframes_len: usize,
+        stack_len: usize,
+    },
+    Parameterize(HashMap<HeapAddr, Expr>),
+}
+
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct VM {
     pub callframes: Vec<Callframe>,
+    pub winders: Vec<Winder>,
     pub stack: Vec<Expr>,
     pub heap: HashMap<HeapAddr, Expr>,
     pub exports: HashMap<String, HeapAddr>,
@@ -85,13 +152,125 @@ pub struct Chunk {
 pub fn run(vm: &mut VM) -> Result<(), String> {
     loop {
         match step(vm) {
-            Err(err) => return Err(err),
+            Err(err) => {
+                unwind(vm);
+                return Err(err);
+            }
             Ok(()) if vm.callframes.is_empty() => return Ok(()),
             Ok(()) => {}
         }
     }
 }
 
+// Runs the after-thunks of all active dynamic-winds (innermost first) when an
+// error aborts execution. Errors raised by the after-thunks themselves are
+// dropped, the original error is the one reported.
+fn unwind(vm: &mut VM) {
+    while let Some(winder) = vm.winders.pop() {
+        if let Winder::DynamicWind {
+            after,
+            callframes_len,
+            stack_len,
+        } = winder
+        {
+            vm.callframes.truncate(callframes_len);
+            vm.stack.truncate(stack_len);
+            let _ = call_procedure(vm, after, vec![]);
+        }
+    }
+}
+
+fn copy_vectors(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Vector(items) => Expr::Vector(Rc::new(RefCell::new(
+            items.borrow().iter().map(copy_vectors).collect(),
+        ))),
+        Expr::Pair(car, cdr, srcloc) => Expr::Pair(
+            Box::new(copy_vectors(car)),
+            Box::new(copy_vectors(cdr)),
+            srcloc.clone(),
+        ),
+        otherwise => otherwise.clone(),
+    }
+}
+
+// Calls a function from within the VM and runs it until it has returned,
+// used when the VM itself needs the result of a user function.
+pub fn call_procedure(vm: &mut VM, function: Expr, args: Vec<Expr>) -> Result<Expr, String> {
+    let callframes_len = vm.callframes.len();
+    let arity = args.len();
+    let mut instructions = vec![VMInstruction::Constant(function)];
+    instructions.extend(args.into_iter().map(VMInstruction::Constant));
+    instructions.push(VMInstruction::Call(arity));
+    instructions.push(VMInstruction::Return);
+    vm.stack.push(Expr::Lambda(
+        Chunk { code: instructions },
+        vec![],
+        vec![],
+        None,
+        HashMap::new(),
+    ));
+    call(vm, 0)?;
+    while vm.callframes.len() > callframes_len {
+        step(vm)?;
+    }
+    vm.stack
+        .pop()
+        .ok_or("no return value on stack after call".to_string())
+}
+
+// Forces a promise, memoizing its value. A delay-force promise takes over the
+// state of the promise its thunk returns, so chains of them are forced
+// iteratively instead of recursively.
+fn force(vm: &mut VM, promise: Rc<RefCell<Promise>>) -> Result<Expr, String> {
+    // the promises a chain of delay-forces went through, all get the value
+    let mut chain: Vec<Rc<RefCell<Promise>>> = vec![];
+    let mut promise = promise;
+    loop {
+        let state = promise.borrow().clone();
+        match state {
+            Promise::Forced(value) => {
+                for shared in chain {
+                    *shared.borrow_mut() = Promise::Forced(value.clone());
+                }
+                return Ok(value);
+            }
+            Promise::Shared(inner) => {
+                chain.push(promise);
+                promise = inner;
+            }
+            // the thunk may have forced this promise itself, the first value wins
+            Promise::Delayed(thunk) => {
+                let value = call_procedure(vm, thunk, vec![])?;
+                if matches!(*promise.borrow(), Promise::Delayed(..)) {
+                    *promise.borrow_mut() = Promise::Forced(value);
+                }
+            }
+            Promise::DelayForce(thunk) => {
+                let inner = call_procedure(vm, thunk, vec![])?;
+                if matches!(*promise.borrow(), Promise::DelayForce(..)) {
+                    *promise.borrow_mut() = match inner {
+                        Expr::Promise(inner) => Promise::Shared(inner),
+                        value => Promise::Forced(value),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn parameter_value(vm: &VM, addr: HeapAddr) -> Result<Expr, String> {
+    vm.winders
+        .iter()
+        .rev()
+        .find_map(|winder| match winder {
+            Winder::Parameterize(bindings) => bindings.get(&addr).cloned(),
+            Winder::DynamicWind { .. } => None,
+        })
+        .or_else(|| vm.heap.get(&addr).cloned())
+        .ok_or(format!("parameter not found at heap addr: {addr}"))
+}
+
 pub fn step(vm: &mut VM) -> Result<(), String> {
     // let callframes = &mut vm.callframes;
     // let len = callframes.len();
@@ -123,9 +302,8 @@ pub fn step(vm: &mut VM) -> Result<(), String> {
                 }
             };
             match pred {
-                Expr::Boolean(Bool { value: false, .. })
-                | Expr::Nil
-                | Expr::Num(Num { value: 0.0, .. }) => (),
+                Expr::Boolean(Bool { value: false, .. }) | Expr::Nil => (),
+                Expr::Num(Num { value, .. }) if value.is_zero() => (),
                 _ => callframe.ip += *instruction,
             }
         }
@@ -137,9 +315,8 @@ pub fn step(vm: &mut VM) -> Result<(), String> {
                 }
             };
             match pred {
-                Expr::Boolean(Bool { value: false, .. })
-                | Expr::Nil
-                | Expr::Num(Num { value: 0.0, .. }) => (),
+                Expr::Boolean(Bool { value: false, .. }) | Expr::Nil => (),
+                Expr::Num(Num { value, .. }) if value.is_zero() => (),
                 _ => callframe.ip += *instruction,
             }
         }
@@ -234,134 +411,33 @@ pub fn step(vm: &mut VM) -> Result<(), String> {
                 None => return Err(format!("not found: {name}, {:#?}", callframe.env)),
             };
         }
-        VMInstruction::Call(arity) => {
-            let stack_len = vm.stack.len();
-            let first = vm.stack.get(stack_len - arity - 1).cloned();
-
-            match first {
-                Some(Expr::Keyword(str, ..)) if let Some(builtin) = BUILTIN_FNS.get(&str) => {
-                    match builtin {
-                        BuiltIn::OneArg(func) => {
-                            if *arity != 1 {
-                                return Err(format!(
-                                    "{}, expected one arg but found: {}",
-                                    str, arity
-                                ));
-                            }
-                            let top = match vm.stack.pop() {
-                                Some(top) => top,
-                                None => {
-                                    return Err("Expected item on stack, but found none".to_string())
-                                }
-                            };
-                            vm.stack.pop();
-                            vm.stack.push(func(&top)?);
-                        }
-                        BuiltIn::TwoArg(func) => {
-                            if *arity != 2 {
-                                return Err(format!(
-                                    "{}, expected two args but found: {}",
-                                    str, arity
-                                ));
-                            }
-                            let (second, first) = match (vm.stack.pop(), vm.stack.pop()) {
-                                (Some(first), Some(second)) => (first, second),
-                                _ => {
-                                    return Err("Expected item on stack, but found none".to_string())
-                                }
-                            };
-                            vm.stack.pop();
-                            vm.stack.push(func(&first, &second)?);
-                        }
-                        BuiltIn::Variadic(func) => {
-                            let args = vm
-                                .stack
-                                .drain(stack_len - arity..stack_len)
-                                .collect::<Vec<Expr>>();
-                            vm.stack.pop();
-                            vm.stack.push(func(&args)?)
-                        }
-                    }
-                }
-                Some(Expr::Lambda(chunk, vars, locals, variadic, closeds)) => {
-                    let is_variadic = variadic.is_some();
-
-                    let args = vm
-                        .stack
-                        .drain(stack_len - arity..stack_len)
-                        .collect::<Vec<Expr>>();
-                    if is_variadic && args.len() < vars.len() {
-                        return Err(format!(
-                            "wrong number of args, expected at least {} ({}), got: ({})",
-                            vars.len(),
-                            vars.join(" "),
-                            args.into_iter()
-                                .map(|x| format!("{x}"))
-                                .collect::<Vec<String>>()
-                                .join(" ")
-                        ));
-                    }
-
-                    if !is_variadic && args.len() != vars.len() {
-                        return Err(format!(
-                            "wrong number of args, expected {} ({}), got: ({})",
-                            vars.len(),
-                            vars.join(" "),
-                            args.into_iter()
-                                .map(|x| format!("{x}"))
-                                .collect::<Vec<String>>()
-                                .join(" ")
-                        ));
-                    }
-
-                    let mut args_map = vars
-                        .iter()
-                        .cloned()
-                        .zip(args.clone())
-                        .collect::<HashMap<String, Expr>>();
-
-                    variadic.inspect(|arg_name| {
-                        let (_, pairs) = args.split_at(vars.len());
-                        args_map.insert(arg_name.clone(), make_pair_from_vec(pairs.to_vec()));
-                    });
-
-                    let mut new_callframe_env = HashMap::new();
-
-                    for (k, v) in closeds.clone() {
-                        new_callframe_env.insert(k, v);
-                    }
-
-                    for (k, v) in args_map {
-                        let new_key = vm.heap.len();
-                        vm.heap.insert(new_key, v);
-                        new_callframe_env.insert(k, new_key);
-                    }
-
-                    for k in locals.clone() {
-                        let new_key = vm.heap.len();
-                        vm.heap.insert(new_key, Expr::Nil);
-                        new_callframe_env.insert(k, new_key);
-                    }
-
-                    vm.callframes.push(Callframe {
-                        ip: 0,
-                        chunk: chunk.to_owned(),
-                        env: new_callframe_env,
-                    });
-                }
-                found => {
-                    return Err(format!(
-                        "no function to call on stack, found: {}, \nstack: {}",
-                        found.map(|x| format!("{x}")).unwrap_or("None".to_string()),
-                        vm.stack
-                            .clone()
-                            .into_iter()
-                            .map(|expr| format!("{expr}"))
-                            .collect::<Vec<_>>()
-                            .join(" ")
-                    ))
-                }
+        VMInstruction::LookupGlobal(name) => {
+            let name = name.clone();
+            let global_frame = match vm.callframes.first() {
+                Some(global_frame) => global_frame,
+                None => return Err("no callframes".to_string()),
             };
+            // the outermost scope that shadowed name knows the global binding
+            let addr = global_frame
+                .scopes
+                .iter()
+                .find_map(|scope| {
+                    scope
+                        .shadowed
+                        .iter()
+                        .find(|(shadowed, _)| *shadowed == name)
+                        .map(|(_, addr)| *addr)
+                })
+                .unwrap_or_else(|| global_frame.env.get(&name).cloned());
+            match addr.and_then(|addr| vm.heap.get(&addr).cloned()) {
+                Some(expr) => vm.stack.push(expr),
+                None if BUILTIN_FNS.contains_key(&name) => vm.stack.push(Expr::Keyword(name, None)),
+                None => return Err(format!("not found: {name}")),
+            }
+        }
+        VMInstruction::Call(arity) => {
+            let arity = *arity;
+            call(vm, arity)?;
         }
         VMInstruction::Return => {
             // remove fn from stack?
@@ -394,9 +470,331 @@ pub fn step(vm: &mut VM) -> Result<(), String> {
         VMInstruction::Constant(expr) => {
             vm.stack.push(expr.clone());
         }
+        VMInstruction::Literal(expr) => {
+            vm.stack.push(copy_vectors(expr));
+        }
+        VMInstruction::MakeParameter(has_converter) => {
+            let converter = if *has_converter { vm.stack.pop() } else { None };
+            let value = match (vm.stack.pop(), converter.clone()) {
+                (Some(value), Some(converter)) => call_procedure(vm, converter, vec![value])?,
+                (Some(value), None) => value,
+                (None, _) => return Err("make-parameter: no value on stack".to_string()),
+            };
+            let addr = vm.heap.len();
+            vm.heap.insert(addr, value);
+            vm.stack
+                .push(Expr::Parameter(addr, converter.map(Box::new)));
+        }
+        VMInstruction::Parameterize(count) => {
+            let stack_len = vm.stack.len();
+            if stack_len < count * 2 {
+                return Err("parameterize: too few values on stack".to_string());
+            }
+            let args = vm
+                .stack
+                .drain(stack_len - count * 2..stack_len)
+                .collect::<Vec<Expr>>();
+            let mut bindings = HashMap::new();
+            for binding in args.chunks(2) {
+                match binding {
+                    [Expr::Parameter(addr, converter), value] => {
+                        let value = match converter {
+                            Some(box converter) => {
+                                call_procedure(vm, converter.clone(), vec![value.clone()])?
+                            }
+                            None => value.clone(),
+                        };
+                        bindings.insert(*addr, value);
+                    }
+                    [other, _] => {
+                        return Err(format!("parameterize: expected parameter, found: {other}"))
+                    }
+                    _ => return Err("parameterize: expected parameter and value".to_string()),
+                }
+            }
+            vm.winders.push(Winder::Parameterize(bindings));
+        }
+        VMInstruction::PopParameterize => match vm.winders.pop() {
+            Some(Winder::Parameterize(..)) => {}
+            found => {
+                return Err(format!(
+                    "expected parameterization to pop, found: {found:?}"
+                ))
+            }
+        },
+        VMInstruction::PushWind => {
+            let after = match vm.stack.pop() {
+                Some(after) => after,
+                None => return Err("dynamic-wind: no after-thunk on stack".to_string()),
+            };
+            vm.winders.push(Winder::DynamicWind {
+                after,
+                callframes_len: vm.callframes.len(),
+                stack_len: vm.stack.len(),
+            });
+        }
+        VMInstruction::MakePromise(delay_force) => {
+            let thunk = match vm.stack.pop() {
+                Some(thunk) => thunk,
+                None => return Err("no thunk for promise on stack, compiler bug!".to_string()),
+            };
+            let promise = if *delay_force {
+                Promise::DelayForce(thunk)
+            } else {
+                Promise::Delayed(thunk)
+            };
+            vm.stack.push(Expr::Promise(Rc::new(RefCell::new(promise))));
+        }
+        VMInstruction::Force => {
+            let value = match vm.stack.pop() {
+                Some(Expr::Promise(promise)) => force(vm, promise)?,
+                Some(value) => value,
+                None => return Err("no value to force on stack".to_string()),
+            };
+            vm.stack.push(value);
+        }
+        VMInstruction::CallWithValues => {
+            let values = match vm.stack.pop() {
+                Some(Expr::Values(values)) => values,
+                Some(value) => vec![value],
+                None => return Err("call-with-values: no values on stack".to_string()),
+            };
+            let arity = values.len();
+            vm.stack.extend(values);
+            call(vm, arity)?;
+        }
+        VMInstruction::SpreadValues(required, rest) => {
+            let (required, rest) = (*required, *rest);
+            let values = match vm.stack.pop() {
+                Some(Expr::Values(values)) => values,
+                Some(value) => vec![value],
+                None => return Err("define-values: no values on stack".to_string()),
+            };
+            if values.len() < required || (!rest && values.len() != required) {
+                return Err(format!(
+                    "define-values, expected {}{} values, but found: {}",
+                    if rest { "at least " } else { "" },
+                    required,
+                    values.len()
+                ));
+            }
+            let (required_values, rest_values) = values.split_at(required);
+            vm.stack.extend(required_values.iter().cloned());
+            if rest {
+                vm.stack.push(make_pair_from_vec(rest_values.to_vec()));
+            }
+        }
+        VMInstruction::EnterScope(loop_name) => {
+            let start = callframe.ip;
+            callframe.scopes.push(Scope {
+                start,
+                loop_name: loop_name.clone(),
+                shadowed: vec![],
+            });
+        }
+        VMInstruction::Bind(name) => {
+            let value = match vm.stack.pop() {
+                Some(value) => value,
+                None => return Err(format!("no value to bind to {name} on stack")),
+            };
+            let scope = match callframe.scopes.last_mut() {
+                Some(scope) => scope,
+                None => return Err(format!("no scope to bind {name} in, compiler bug!")),
+            };
+            // a loop binds its variables again on every iteration, the
+            // binding to restore is the one from before the first
+            if !scope.shadowed.iter().any(|(shadowed, _)| shadowed == name) {
+                scope
+                    .shadowed
+                    .push((name.clone(), callframe.env.get(name).cloned()));
+            }
+            let addr = vm.heap.len();
+            vm.heap.insert(addr, value);
+            callframe.env.insert(name.clone(), addr);
+        }
+        VMInstruction::ExitScope => {
+            let scope = match callframe.scopes.pop() {
+                Some(scope) => scope,
+                None => return Err("no scope to exit, compiler bug!".to_string()),
+            };
+            for (name, addr) in scope.shadowed {
+                match addr {
+                    Some(addr) => callframe.env.insert(name, addr),
+                    None => callframe.env.remove(&name),
+                };
+            }
+        }
+        VMInstruction::Recur(name) => match callframe.scopes.last() {
+            Some(Scope {
+                start,
+                loop_name: Some(loop_name),
+                ..
+            }) if loop_name == name => callframe.ip = *start,
+            _ => return Err(format!("{name}: can only loop from within its named let")),
+        },
+        VMInstruction::Eqv(datums) => {
+            let key = match vm.stack.last() {
+                Some(key) => key,
+                None => return Err("no key to compare on stack, compiler bug!".to_string()),
+            };
+            eqv_arg("case", key)?;
+            let matches = datums.iter().any(|datum| is_eqv(key, datum));
+            vm.stack.push(Expr::bool(matches));
+        }
+        VMInstruction::Swap => {
+            let stack_len = vm.stack.len();
+            if stack_len < 2 {
+                return Err("too few values to swap on stack".to_string());
+            }
+            vm.stack.swap(stack_len - 1, stack_len - 2);
+        }
+        VMInstruction::PopWind => match vm.winders.pop() {
+            Some(Winder::DynamicWind { after, .. }) => vm.stack.push(after),
+            found => return Err(format!("expected dynamic-wind to pop, found: {found:?}")),
+        },
+    }
+    Ok(())
+}
+pub fn call(vm: &mut VM, arity: usize) -> Result<(), String> {
+    let stack_len = vm.stack.len();
+    let first = vm.stack.get(stack_len - arity - 1).cloned();
+    // multiple values only make sense as the producer of call-with-values or receive
+    if let Some(Expr::Values(values)) = vm.stack[stack_len - arity..]
+        .iter()
+        .find(|arg| matches!(arg, Expr::Values(..)))
+    {
+        return Err(format!(
+            "expected a single value, but found {} values",
+            values.len()
+        ));
     }
+
+    match first {
+        Some(Expr::Keyword(str, ..)) if let Some(builtin) = BUILTIN_FNS.get(&str) => {
+            match builtin {
+                BuiltIn::OneArg(func) => {
+                    if arity != 1 {
+                        return Err(format!("{}, expected one arg but found: {}", str, arity));
+                    }
+                    let top = match vm.stack.pop() {
+                        Some(top) => top,
+                        None => return Err("Expected item on stack, but found none".to_string()),
+                    };
+                    vm.stack.pop();
+                    vm.stack.push(func(&top)?);
+                }
+                BuiltIn::TwoArg(func) => {
+                    if arity != 2 {
+                        return Err(format!("{}, expected two args but found: {}", str, arity));
+                    }
+                    let (second, first) = match (vm.stack.pop(), vm.stack.pop()) {
+                        (Some(first), Some(second)) => (first, second),
+                        _ => return Err("Expected item on stack, but found none".to_string()),
+                    };
+                    vm.stack.pop();
+                    vm.stack.push(func(&first, &second)?);
+                }
+                BuiltIn::Variadic(func) => {
+                    let args = vm
+                        .stack
+                        .drain(stack_len - arity..stack_len)
+                        .collect::<Vec<Expr>>();
+                    vm.stack.pop();
+                    vm.stack.push(func(&args)?)
+                }
+            }
+        }
+        Some(Expr::Lambda(chunk, vars, locals, variadic, closeds)) => {
+            let is_variadic = variadic.is_some();
+
+            let args = vm
+                .stack
+                .drain(stack_len - arity..stack_len)
+                .collect::<Vec<Expr>>();
+            if is_variadic && args.len() < vars.len() {
+                return Err(format!(
+                    "wrong number of args, expected at least {} ({}), got: ({})",
+                    vars.len(),
+                    vars.join(" "),
+                    args.into_iter()
+                        .map(|x| format!("{x}"))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ));
+            }
+
+            if !is_variadic && args.len() != vars.len() {
+                return Err(format!(
+                    "wrong number of args, expected {} ({}), got: ({})",
+                    vars.len(),
+                    vars.join(" "),
+                    args.into_iter()
+                        .map(|x| format!("{x}"))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ));
+            }
+
+            let mut args_map = vars
+                .iter()
+                .cloned()
+                .zip(args.clone())
+                .collect::<HashMap<String, Expr>>();
+
+            variadic.inspect(|arg_name| {
+                let (_, pairs) = args.split_at(vars.len());
+                args_map.insert(arg_name.clone(), make_pair_from_vec(pairs.to_vec()));
+            });
+
+            let mut new_callframe_env = HashMap::new();
+
+            for (k, v) in closeds.clone() {
+                new_callframe_env.insert(k, v);
+            }
+
+            for (k, v) in args_map {
+                let new_key = vm.heap.len();
+                vm.heap.insert(new_key, v);
+                new_callframe_env.insert(k, new_key);
+            }
+
+            for k in locals.clone() {
+                let new_key = vm.heap.len();
+                vm.heap.insert(new_key, Expr::Nil);
+                new_callframe_env.insert(k, new_key);
+            }
+
+            vm.callframes.push(Callframe {
+                ip: 0,
+                chunk: chunk.to_owned(),
+                env: new_callframe_env,
+                scopes: vec![],
+            });
+        }
+        Some(Expr::Parameter(addr, _)) => {
+            if arity != 0 {
+                return Err(format!("parameter, expected no args but found: {arity}"));
+            }
+            vm.stack.pop();
+            let value = parameter_value(vm, addr)?;
+            vm.stack.push(value);
+        }
+        found => {
+            return Err(format!(
+                "no function to call on stack, found: {}, \nstack: {}",
+                found.map(|x| format!("{x}")).unwrap_or("None".to_string()),
+                vm.stack
+                    .clone()
+                    .into_iter()
+                    .map(|expr| format!("{expr}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ))
+        }
+    };
     Ok(())
 }
+
 #[test]
 fn test_add() {
     let chunk = Chunk {
@@ -413,6 +811,7 @@ fn test_add() {
         ip: 0,
         chunk,
         env: HashMap::new(),
+        scopes: vec![],
     };
 
     let mut vm = VM::default();
@@ -431,26 +830,57 @@ pub struct CompilerEnv {
     pub macros: Macros,
 }
 
+fn expansion_context(compiler_env: &CompilerEnv) -> ExpansionContext {
+    // macros run with what was loaded before this program
+    ExpansionContext {
+        syntax_env: SyntaxEnv {
+            env: compiler_env.env.clone(),
+            heap: compiler_env.heap.clone(),
+        },
+        trace: None,
+        ..Default::default()
+    }
+}
+
+// Every macro call made while expanding the program, in the order they were
+// expanded, for showing how a program was rewritten.
+pub fn trace_expansion(
+    input: &ParseInput,
+    initial_env: Option<CompilerEnv>,
+) -> Result<Vec<ExpansionStep>, String> {
+    let compiler_env = initial_env.unwrap_or_default();
+    let exprs = parse::parse(input)?;
+    let mut context = ExpansionContext {
+        trace: Some(vec![]),
+        ..expansion_context(&compiler_env)
+    };
+    macro_expand(&exprs, &mut compiler_env.macros.clone(), &mut context)
+        .map_err(|x| x.to_string())?;
+    Ok(context.trace.unwrap_or_default())
+}
+
 pub fn prepare_vm(
     input: &ParseInput,
     initial_env: Option<CompilerEnv>,
 ) -> Result<(VM, Macros), String> {
     let compiler_env = initial_env.unwrap_or_default();
 
-    let exprs = match parse::parse(input) {
-        Ok(res) => res,
-        Err(err) => return Err(err),
-    };
+    let exprs = parse::parse(input)?;
 
-    let mut vm = VM::default();
-
-    vm.heap = compiler_env.heap.clone();
+    let mut vm = VM {
+        heap: compiler_env.heap.clone(),
+        ..Default::default()
+    };
 
     let mut chunk = Chunk { code: vec![] };
 
     let mut macros = compiler_env.macros.clone();
-    let macro_expanded = macro_expand(&exprs, &mut macros).map_err(|x| x.to_string())?;
+    let mut context = expansion_context(&compiler_env);
+    let macro_expanded =
+        macro_expand(&exprs, &mut macros, &mut context).map_err(|x| x.to_string())?;
     let initial_env_keys = compiler_env.env.keys().cloned().collect::<Vec<String>>();
+    // macros can expand to definitions
+    let defines = get_all_defines(&macro_expanded);
 
     compile_many_exprs(macro_expanded, &mut chunk, &mut initial_env_keys.clone())
         .map_err(|err| format!("{err}"))?;
@@ -459,14 +889,18 @@ pub fn prepare_vm(
         ip: 0,
         chunk,
         env: compiler_env.env.clone(),
+        scopes: vec![],
     };
 
-    let defines = get_all_defines(&exprs);
-
     for name in defines {
         if !compiler_env.env.contains_key(&name) {
             let addr = vm.heap.len();
-            vm.heap.insert(addr, Expr::Nil);
+            // a builtin the program redefines is the builtin until then
+            let value = match BUILTIN_FNS.contains_key(&name) {
+                true => Expr::Keyword(name.clone(), None),
+                false => Expr::Nil,
+            };
+            vm.heap.insert(addr, value);
             callframe.env.insert(name.clone(), addr);
             vm.exports.insert(name, addr);
         }
@@ -480,7 +914,7 @@ pub fn prepare_vm(
 #[allow(dead_code)]
 pub fn jit_run_vm(input: &str) -> Result<VM, String> {
     let prelude = get_prelude();
-    let (mut vm, _) = match prelude.and_then(|env| {
+    let (mut vm, _) = prelude.and_then(|env| {
         prepare_vm(
             &ParseInput {
                 source: input,
@@ -488,10 +922,7 @@ pub fn jit_run_vm(input: &str) -> Result<VM, String> {
             },
             Some(env),
         )
-    }) {
-        Ok(vm) => vm,
-        Err(err) => return Result::Err(err),
-    };
+    })?;
     run(&mut vm).map(|_| vm)
 }
 
@@ -654,7 +1085,7 @@ fn compiled_test() {
   (fib-iter (+ a b) a (+ count -1)))))
 (fib 90)"
         ),
-        Ok(Expr::num(2.880067194370816e18))
+        Ok(Expr::int(2880067194370816120))
     );
 
     assert_eq!(